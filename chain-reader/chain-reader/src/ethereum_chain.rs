@@ -11,7 +11,7 @@ use lazy_static::lazy_static;
 use log::{debug, info, warn};
 use massbit_chain_ethereum::data_type::EthereumBlock as Block;
 use massbit_common::NetworkType;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::error::Error as StdError;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
@@ -40,6 +40,8 @@ const USE_WEBSOCKET: bool = false;
 const BLOCK_BATCH_SIZE: u64 = 10;
 const RETRY_GET_BLOCK_LIMIT: u32 = 10;
 const GET_BLOCK_TIMEOUT_SEC: u64 = 60;
+// Number of sent blocks whose hash is kept to find where a reorg forks
+const REORG_HISTORY_SIZE: usize = 256;
 
 fn get_web3(network: &NetworkType) -> Arc<Web3<Transport>> {
     let config = CONFIG.get_chain_config(&CHAIN_TYPE, network).unwrap();
@@ -72,6 +74,84 @@ pub enum IngestorError {
     Unknown(Error),
 }
 
+/// Block fetched from the node with the hashes linking it to its parent
+struct FetchedBlock {
+    data: GenericDataProto,
+    hash: H256,
+    parent_hash: H256,
+}
+
+/// Hashes of the last blocks sent on the stream
+#[derive(Default)]
+struct ChainHistory {
+    blocks: VecDeque<(u64, H256)>,
+}
+
+impl ChainHistory {
+    fn push(&mut self, block_number: u64, hash: H256) {
+        self.blocks.push_back((block_number, hash));
+        if self.blocks.len() > REORG_HISTORY_SIZE {
+            self.blocks.pop_front();
+        }
+    }
+
+    /// The block is on another branch when its parent is not the last block sent
+    fn is_reorg(&self, block_number: u64, parent_hash: &H256) -> bool {
+        match self.blocks.back() {
+            Some((last_number, last_hash)) if last_number + 1 == block_number => {
+                last_hash != parent_hash
+            }
+            _ => false,
+        }
+    }
+
+    /// Latest block sent which is still on the chain of the node
+    fn common_ancestor<F>(&self, canonical_hash: F) -> Option<(u64, H256)>
+    where
+        F: Fn(u64) -> Option<H256>,
+    {
+        self.blocks
+            .iter()
+            .rev()
+            .find(|(block_number, hash)| canonical_hash(*block_number).as_ref() == Some(hash))
+            .cloned()
+    }
+
+    fn oldest(&self) -> Option<u64> {
+        self.blocks.front().map(|(block_number, _)| *block_number)
+    }
+
+    /// Forget the blocks after `block_number`, they are not on the chain anymore
+    fn truncate(&mut self, block_number: u64) {
+        while matches!(self.blocks.back(), Some((number, _)) if *number > block_number) {
+            self.blocks.pop_back();
+        }
+    }
+}
+
+fn get_canonical_hash(web3: &Web3<Transport>, block_number: u64) -> Option<H256> {
+    web3.eth()
+        .block(BlockId::Number(Web3BlockNumber::from(block_number)))
+        .wait()
+        .ok()
+        .flatten()
+        .and_then(|block| block.hash)
+}
+
+/// Block to go back to after a reorg, the oldest kept block is left when the fork is deeper
+fn find_fork_block(web3: &Web3<Transport>, history: &ChainHistory) -> Option<(u64, H256)> {
+    history
+        .common_ancestor(|block_number| get_canonical_hash(web3, block_number))
+        .or_else(|| {
+            let block_number = history.oldest()?.saturating_sub(1);
+            warn!(
+                "ETHEREUM reorg is deeper than the {} kept blocks, rolling back to block {}",
+                REORG_HISTORY_SIZE, block_number
+            );
+            get_canonical_hash(web3, block_number).map(|hash| (block_number, hash))
+        })
+}
+
 async fn wait_for_new_block_http(
     web3_http: &Web3<Transport>,
     got_block_number: &Option<u64>,
@@ -219,7 +299,7 @@ async fn get_block(
     permit: OwnedSemaphorePermit,
     clone_web3: Arc<Web3<Transport>>,
     clone_version: String,
-) -> Result<GenericDataProto, Box<dyn std::error::Error + Send + Sync + 'static>> {
+) -> Result<FetchedBlock, Box<dyn std::error::Error + Send + Sync + 'static>> {
     debug!("Before permit block {}", block_number);
     let _permit = permit;
     debug!("After permit block {}", block_number);
@@ -244,7 +324,10 @@ async fn get_block(
     if let Ok(Some(block)) = block {
         //println!("Got ETHEREUM Block {:?}",block);
         // Convert to generic
-        let block_hash = block.hash.clone().unwrap_or_default().to_string();
+        let hash = block.hash.clone().unwrap_or_default();
+        let parent_hash = block.parent_hash.clone();
+        // The Display of H256 abbreviates the hash, the consumers need all of it
+        let block_hash = format!("{:#x}", hash);
 
         // Get receipts
         info!("Getting ETHEREUM of block: {}", block_number);
@@ -272,7 +355,11 @@ async fn get_block(
 
         let generic_data_proto =
             _create_generic_block(block_hash, block_number, &eth_block, clone_version);
-        return Ok(generic_data_proto);
+        return Ok(FetchedBlock {
+            data: generic_data_proto,
+            hash,
+            parent_hash,
+        });
     } else {
         info!("Got ETHEREUM block error {:?}", &block);
         return Err("Got ETHEREUM block error".into());
@@ -305,6 +392,7 @@ pub async fn loop_get_block(
         Some(start_block) => Some(start_block - 1),
         None => None,
     };
+    let mut history = ChainHistory::default();
     loop {
         if exit.load(Ordering::Relaxed) {
             eprintln!("{}", "exit".to_string());
@@ -374,7 +462,7 @@ pub async fn loop_get_block(
 
         let blocks: Vec<Result<_, _>> = futures03::future::join_all(tasks).await;
 
-        let mut blocks: Vec<GenericDataProto> = blocks
+        let mut blocks: Vec<FetchedBlock> = blocks
             .into_iter()
             .filter_map(|res_block| {
                 if let Ok(Ok(block)) = res_block {
@@ -384,11 +472,35 @@ pub async fn loop_get_block(
                 }
             })
             .collect();
-        blocks.sort_by(|a, b| a.block_number.cmp(&b.block_number));
+        blocks.sort_by(|a, b| a.data.block_number.cmp(&b.data.block_number));
         info!("Finished get blocks");
 
-        for block in blocks.into_iter() {
+        let mut rollback_to = None;
+        for fetched_block in blocks.into_iter() {
+            let mut block = fetched_block.data;
             let block_number = block.block_number;
+            if history.is_reorg(block_number, &fetched_block.parent_hash) {
+                // The blocks sent after the fork are replaced by the ones of the new chain
+                if let Some((fork_number, fork_hash)) = find_fork_block(&WEB3, &history) {
+                    warn!(
+                        "ETHEREUM reorg at block {}, rolling back to block {}",
+                        block_number, fork_number
+                    );
+                    let mut rollback = _create_rollback(fork_hash, fork_number, version.clone());
+                    rollback.chain_head = latest_block_number;
+                    if chan.send(Ok(rollback)).await.is_err() {
+                        return Err("Stream is closed!".into());
+                    }
+                    history.truncate(fork_number);
+                    rollback_to = Some(fork_number);
+                    break;
+                }
+                warn!(
+                    "ETHEREUM reorg at block {}, the block to roll back to is not found",
+                    block_number
+                );
+            }
+            block.chain_head = latest_block_number;
             debug!("gRPC sending block {}", &block_number);
             if !chan.is_closed() {
                 let send_res = chan.send(Ok(block as GenericDataProto)).await;
@@ -400,9 +512,13 @@ pub async fn loop_get_block(
             } else {
                 return Err("Stream is closed!".into());
             }
+            history.push(block_number, fetched_block.hash);
         }
 
-        got_block_number = Some(got_block_number.unwrap() + getting_block);
+        got_block_number = match rollback_to {
+            Some(fork_number) => Some(fork_number),
+            None => Some(got_block_number.unwrap() + getting_block),
+        };
     }
     Ok(())
 }
@@ -420,6 +536,76 @@ fn _create_generic_block(
         block_hash,
         block_number,
         payload: serde_json::to_vec(block).unwrap(),
+        chain_head: 0,
     };
     generic_data
 }
+
+fn _create_rollback(block_hash: H256, block_number: u64, version: String) -> GenericDataProto {
+    GenericDataProto {
+        chain_type: CHAIN_TYPE as i32,
+        version,
+        data_type: DataType::Rollback as i32,
+        block_hash: format!("{:#x}", block_hash),
+        block_number,
+        payload: vec![],
+        chain_head: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(hashes: &[(u64, u64)]) -> ChainHistory {
+        let mut history = ChainHistory::default();
+        for (block_number, hash) in hashes {
+            history.push(*block_number, H256::from_low_u64_be(*hash));
+        }
+        history
+    }
+
+    #[test]
+    fn block_with_another_parent_is_a_reorg() {
+        let history = history(&[(1, 1), (2, 2)]);
+        assert!(!history.is_reorg(3, &H256::from_low_u64_be(2)));
+        assert!(history.is_reorg(3, &H256::from_low_u64_be(20)));
+        // Not the next block, its parent is unknown
+        assert!(!history.is_reorg(5, &H256::from_low_u64_be(20)));
+        assert!(!ChainHistory::default().is_reorg(1, &H256::from_low_u64_be(1)));
+    }
+
+    #[test]
+    fn rollback_goes_to_the_latest_block_still_on_the_chain() {
+        let mut history = history(&[(1, 1), (2, 2), (3, 3), (4, 4)]);
+        // Blocks 3 and 4 are replaced on the chain of the node
+        let canonical_hash = |block_number: u64| match block_number {
+            1 | 2 => Some(H256::from_low_u64_be(block_number)),
+            _ => Some(H256::from_low_u64_be(block_number * 10)),
+        };
+        assert_eq!(
+            history.common_ancestor(canonical_hash),
+            Some((2, H256::from_low_u64_be(2)))
+        );
+        history.truncate(2);
+        assert!(!history.is_reorg(3, &H256::from_low_u64_be(2)));
+        assert_eq!(history.common_ancestor(|_| None), None);
+    }
+
+    #[test]
+    fn rollback_has_the_full_block_hash() {
+        let rollback = _create_rollback(H256::repeat_byte(0xab), 7, String::new());
+        assert_eq!(rollback.block_hash, format!("0x{}", "ab".repeat(32)));
+        assert_eq!(rollback.block_number, 7);
+    }
+
+    #[test]
+    fn history_keeps_the_last_blocks() {
+        let mut history = ChainHistory::default();
+        for block_number in 0..(REORG_HISTORY_SIZE as u64 + 10) {
+            history.push(block_number, H256::from_low_u64_be(block_number));
+        }
+        assert_eq!(history.blocks.len(), REORG_HISTORY_SIZE);
+        assert_eq!(history.oldest(), Some(10));
+    }
+}
@@ -7,7 +7,7 @@ use massbit_common::NetworkType;
 use std::collections::HashMap;
 use stream_mod::{
     streamout_server::Streamout, ChainType, GenericDataProto, GetBlocksRequest, HelloReply,
-    HelloRequest,
+    HelloRequest, ListNetworksReply, ListNetworksRequest, NetworkInfo,
 };
 use tonic::{Request, Response, Status};
 
@@ -37,6 +37,21 @@ impl Streamout for StreamService {
         Ok(Response::new(reply))
     }
 
+    async fn list_networks(
+        &self,
+        _request: Request<ListNetworksRequest>,
+    ) -> Result<Response<ListNetworksReply>, Status> {
+        let networks = self
+            .chans
+            .keys()
+            .map(|(chain_type, network)| NetworkInfo {
+                chain_type: *chain_type as i32,
+                network: network.clone(),
+            })
+            .collect();
+        Ok(Response::new(ListNetworksReply { networks }))
+    }
+
     type ListBlocksStream = ReceiverStream<Result<GenericDataProto, Status>>;
 
     async fn list_blocks(
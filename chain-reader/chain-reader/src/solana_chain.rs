@@ -72,6 +72,7 @@ pub async fn loop_get_block(
                                     let generic_data_proto = _create_generic_block(
                                         block.block.blockhash.clone(),
                                         block_height,
+                                        current_root,
                                         &block,
                                     );
                                     debug!(
@@ -96,7 +97,12 @@ pub async fn loop_get_block(
     Ok(())
 }
 
-fn _create_generic_block(block_hash: String, block_number: u64, block: &Block) -> GenericDataProto {
+fn _create_generic_block(
+    block_hash: String,
+    block_number: u64,
+    chain_head: u64,
+    block: &Block,
+) -> GenericDataProto {
     let generic_data = GenericDataProto {
         chain_type: CHAIN_TYPE as i32,
         version: VERSION.to_string(),
@@ -104,6 +110,7 @@ fn _create_generic_block(block_hash: String, block_number: u64, block: &Block) -
         block_hash,
         block_number,
         payload: serde_json::to_vec(block).unwrap(),
+        chain_head,
     };
     generic_data
 }
@@ -13,9 +13,10 @@ use tokio::sync::broadcast;
 use crate::command::fix_one_thread_not_receive;
 #[cfg(feature = "std")]
 use codec::{Decode, Encode};
-use log::{error, info};
+use log::{error, info, warn};
 use node_template_runtime::Block as OrgBlock;
 use node_template_runtime::Event;
+use node_template_runtime::Header as OrgHeader;
 use sp_keyring::AccountKeyring;
 use std::env;
 use substrate_api_client::rpc::WsRpcClient;
@@ -52,7 +53,18 @@ fn get_block_and_hash_from_header(
     Ok((ext_block, hash))
 }
 
-fn _create_generic_block(block_hash: String, block: &Block) -> GenericDataProto {
+// Number of the last finalized block of the node, the blocks are streamed up to it
+fn get_chain_head(api: &Api<sr25519::Pair, WsRpcClient>) -> Result<u64, Box<dyn Error>> {
+    let hash = api
+        .get_finalized_head()?
+        .ok_or("Node has no finalized head")?;
+    let header = api
+        .get_header::<OrgHeader>(Some(hash))?
+        .ok_or("Header of the finalized head is not found")?;
+    Ok(header.number as u64)
+}
+
+fn _create_generic_block(block_hash: String, block: &Block, chain_head: u64) -> GenericDataProto {
     let block = (*block).clone();
 
     let generic_data = GenericDataProto {
@@ -62,6 +74,7 @@ fn _create_generic_block(block_hash: String, block: &Block) -> GenericDataProto
         block_hash: block_hash,
         block_number: block.block.header.number as u64,
         payload: block.encode(),
+        chain_head,
     };
     generic_data
 }
@@ -74,6 +87,7 @@ fn _create_generic_event(event: &EventRecord) -> GenericDataProto {
         block_hash: "unknown".to_string(),
         block_number: 0 as u64,
         payload: event.encode(),
+        chain_head: 0,
     };
     generic_data
 }
@@ -154,7 +168,12 @@ pub async fn loop_get_block_and_extrinsic(
             .unwrap();
         // Call rpc to create block from header
         let (block, hash) = get_block_and_hash_from_header(&api, head).unwrap();
-        let generic_block = _create_generic_block(hash.clone(), &block);
+        let block_number = block.block.header.number as u64;
+        let chain_head = get_chain_head(&api).unwrap_or_else(|e| {
+            warn!("Cannot get the finalized head of the node: {}", e);
+            block_number
+        });
+        let generic_block = _create_generic_block(hash.clone(), &block, chain_head);
         // Send block
         info!(
             "Got block number: {:?}, hash: {:?}",
@@ -165,23 +165,92 @@ pub(crate) const PRIMARY_KEY_COLUMN: &str = "id";
 pub struct ModelField {
     pub name: String,
     pub field_type: ModelFieldType,
+    pub is_list: bool,
+    /// Entity type referenced by the field
+    pub reference: Option<String>,
+    /// Field of the referencing entity for a `@derivedFrom(field: "...")` field, it is not stored
+    pub derived_from: Option<String>,
 }
 
 impl ModelField {
     fn new(field: &s::Field, enums: &EnumMap, id_types: &IdTypeMap) -> Result<ModelField, Error> {
         let name = (&*field.name).to_snake_case();
         let field_type = ModelFieldType::from_field_type(&field.field_type, enums, id_types)?;
-        Ok(ModelField { name, field_type })
+        let reference = if is_object_type(&field.field_type, enums) {
+            Some(named_type(&field.field_type).to_string())
+        } else {
+            None
+        };
+        Ok(ModelField {
+            name,
+            field_type,
+            is_list: is_list_type(&field.field_type),
+            reference,
+            derived_from: derived_from(field),
+        })
     }
 
-    fn rust_type(&self) -> &str {
-        self.field_type.rust_type()
+    fn rust_type(&self) -> String {
+        if self.is_list {
+            format!("Vec<{}>", self.field_type.rust_type())
+        } else {
+            self.field_type.rust_type().to_string()
+        }
     }
 
     fn as_rust(&self, out: &mut String) -> fmt::Result {
         write!(out, "pub {}: {}", self.name, self.rust_type())?;
         Ok(())
     }
+
+    /// `load_<field>` accessor of a derived field or of a list of references, false for the other fields
+    fn accessor_as_rust(&self, out: &mut String) -> Result<bool, fmt::Error> {
+        let reference = match &self.reference {
+            Some(reference) => reference,
+            None => return Ok(false),
+        };
+        match (&self.derived_from, self.is_list) {
+            (Some(source), true) => {
+                writeln!(
+                    out,
+                    "    pub fn load_{}(&self) -> Vec<{}> {{",
+                    self.name, reference
+                )?;
+                writeln!(
+                    out,
+                    "        {}::load_derived(\"{}\", &self.id)",
+                    reference, source
+                )?;
+            }
+            (Some(source), false) => {
+                writeln!(
+                    out,
+                    "    pub fn load_{}(&self) -> Option<{}> {{",
+                    self.name, reference
+                )?;
+                writeln!(
+                    out,
+                    "        {}::load_derived(\"{}\", &self.id).into_iter().next()",
+                    reference, source
+                )?;
+            }
+            (None, true) => {
+                writeln!(
+                    out,
+                    "    pub fn load_{}(&self) -> Vec<{}> {{",
+                    self.name, reference
+                )?;
+                writeln!(
+                    out,
+                    "        self.{}.iter().filter_map(|id| {}::get(id)).collect()",
+                    self.name, reference
+                )?;
+            }
+            (None, false) => return Ok(false),
+        }
+        writeln!(out, "    }}")?;
+        Ok(true)
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -208,12 +277,28 @@ impl Model {
 
     pub fn as_rust(&self, out: &mut String) -> fmt::Result {
         writeln!(out, "pub struct {} {{", self.name)?;
-        for field in self.fields.iter() {
+        // The derived fields are not stored, they are loaded by their accessor
+        for field in self
+            .fields
+            .iter()
+            .filter(|field| field.derived_from.is_none())
+        {
             write!(out, "    ")?;
             field.as_rust(out)?;
             writeln!(out, ",")?;
         }
-        write!(out, "}}")
+        write!(out, "}}")?;
+        let mut accessors = String::new();
+        for field in self.fields.iter() {
+            field.accessor_as_rust(&mut accessors)?;
+        }
+        if !accessors.is_empty() {
+            writeln!(out)?;
+            writeln!(out, "impl {} {{", self.name)?;
+            write!(out, "{}", accessors)?;
+            write!(out, "}}")?;
+        }
+        Ok(())
     }
 }
 
@@ -282,6 +367,30 @@ fn named_type(field_type: &q::Type) -> &str {
     }
 }
 
+fn is_list_type(field_type: &q::Type) -> bool {
+    match field_type {
+        q::Type::NamedType(_) => false,
+        q::Type::ListType(_) => true,
+        q::Type::NonNullType(child) => is_list_type(child),
+    }
+}
+
+fn derived_from(field: &s::Field) -> Option<String> {
+    field
+        .directives
+        .iter()
+        .find(|directive| directive.name == "derivedFrom")
+        .and_then(|directive| {
+            directive
+                .arguments
+                .iter()
+                .find_map(|(name, value)| match (name.as_str(), value) {
+                    ("field", s::Value::String(field)) => Some(field.clone()),
+                    _ => None,
+                })
+        })
+}
+
 fn is_object_type(field_type: &q::Type, enums: &EnumMap) -> bool {
     let name = named_type(field_type);
 
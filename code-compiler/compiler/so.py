@@ -10,7 +10,7 @@ import requests
 import yaml
 from distutils.dir_util import copy_tree
 from helper.helper import write_to_disk, populate_stub, get_abi_files, upload_abi_to_ipfs, replace_abi_with_hash, \
-    get_index_manager_url
+    get_index_manager_url, get_index_name
 
 success_file = "success.txt"
 error_file = "error.txt"
@@ -161,10 +161,10 @@ def deploy_so(data):
     parsed_subgraph_res = client.add(parsed_subgraph_path)
 
     # Deploy a new index to Index Manager
-    deploy_to_index_manager(subgraph_res, parsed_subgraph_res, mapping_res, schema_res)
+    deploy_to_index_manager(get_index_name(data, compilation_id), subgraph_res, parsed_subgraph_res, mapping_res, schema_res)
 
 
-def deploy_to_index_manager(subgraph_res, parsed_subgraph_res, mapping_res, schema_res):
+def deploy_to_index_manager(name, subgraph_res, parsed_subgraph_res, mapping_res, schema_res):
     null = None
     res = requests.post(get_index_manager_url(),
                         json={
@@ -175,7 +175,10 @@ def deploy_to_index_manager(subgraph_res, parsed_subgraph_res, mapping_res, sche
                                 mapping_res['Hash'],
                                 schema_res['Hash'],
                                 null,
-                                parsed_subgraph_res['Hash']
+                                parsed_subgraph_res['Hash'],
+                                null,
+                                null,
+                                name
                             ],
                             'id': '1',
                         })
@@ -8,7 +8,7 @@ import threading
 import requests
 import yaml
 from helper.helper import write_to_disk, get_abi_files, upload_abi_to_ipfs, ipfs_client_init, get_index_manager_url, \
-    is_template_exist, replace_abi_with_hash
+    is_template_exist, replace_abi_with_hash, get_index_name
 
 success_file = "success.txt"
 error_file = "error.txt"
@@ -124,7 +124,7 @@ def deploy_wasm(data):
     parsed_subgraph_res = client.add(parsed_subgraph_path)
 
     # Deploy a new index to Index Manager
-    deploy_to_index_manager(parsed_subgraph_res, ds_mapping_res, schema_res, abi_res)
+    deploy_to_index_manager(get_index_name(data, compilation_id), parsed_subgraph_res, ds_mapping_res, schema_res, abi_res)
 
 
 def parse_subgraph(subgraph_path, parsed_subgraph_path, schema_res, abi_res, ds_mapping_res, tp_mapping_res=None):
@@ -150,7 +150,7 @@ def parse_subgraph(subgraph_path, parsed_subgraph_path, schema_res, abi_res, ds_
     file.close()
 
 
-def deploy_to_index_manager(parsed_subgraph_res, ds_mapping_res, schema_res, abi_res):
+def deploy_to_index_manager(name, parsed_subgraph_res, ds_mapping_res, schema_res, abi_res):
     # TODO: The config and subgraph is the same for WASM, so we only need to send one.
     # TODO: ds_mapping_res should be removed because when we use the graph's logic,
     #       we only need the parsed_subgraph.yaml Note:
@@ -165,7 +165,10 @@ def deploy_to_index_manager(parsed_subgraph_res, ds_mapping_res, schema_res, abi
                                 ds_mapping_res['Hash'],
                                 schema_res['Hash'],
                                 abi_res,
-                                parsed_subgraph_res['Hash']
+                                parsed_subgraph_res['Hash'],
+                                None,
+                                None,
+                                name
                             ],
                             'id': '1',
                         })
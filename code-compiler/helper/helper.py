@@ -157,4 +157,9 @@ def replace_abi_with_hash(subgraph_type, subgraph, abi_res):
                 if file_name.lower() == abi_object["name"].lower():
                     subgraph[subgraph_type][0]['mapping']['abis'][i] = {'name': name,
                                                                         'file': {'/': '/ipfs/' + abi_object["hash"]}}
-    return subgraph
\ No newline at end of file
+    return subgraph
+
+
+def get_index_name(data, compilation_id):
+    # The deploys with the same name are versions of the same index, a compilation without a name is a new index
+    return data.get("name") or compilation_id
@@ -36,12 +36,13 @@ pub fn from_entity(input: TokenStream) -> TokenStream {
         .map(|field| match field.ty.clone() {
             Type::Path(typepath) => {
                 // TODO: options and results
-                // TODO: vecs
                 // TODO: genericized numerics
 
-                // get the type of the specified field, lowercase
-                let typename: String =
-                    format!("as_{}", quote! {#typepath}.to_string().to_lowercase());
+                // get the type of the specified field, lowercase, `Vec<T>` is parsed with `as_<t>_list`
+                let typename: String = match list_item_type(&typepath) {
+                    Some(item) => format!("as_{}_list", quote! {#item}.to_string().to_lowercase()),
+                    None => format!("as_{}", quote! {#typepath}.to_string().to_lowercase()),
+                };
                 // initialize new Ident for codegen
                 Ident::new(&typename, Span::mixed_site())
             }
@@ -131,6 +132,23 @@ pub fn to_map(input: TokenStream) -> TokenStream {
     TokenStream::from(tokens)
 }
 
+/// Type of the items of a `Vec<T>` field
+fn list_item_type(typepath: &syn::TypePath) -> Option<&Type> {
+    let segment = typepath.path.segments.last()?;
+    if segment.ident != "Vec" {
+        return None;
+    }
+    match &segment.arguments {
+        syn::PathArguments::AngleBracketed(arguments) => {
+            arguments.args.iter().find_map(|argument| match argument {
+                syn::GenericArgument::Type(item) => Some(item),
+                _ => None,
+            })
+        }
+        _ => None,
+    }
+}
+
 /// Example of user-defined [procedural macro attribute][1].
 ///
 /// [1]: https://doc.rust-lang.org/reference/procedural-macros.html#attribute-macros
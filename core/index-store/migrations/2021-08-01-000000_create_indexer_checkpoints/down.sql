@@ -0,0 +1,3 @@
+DROP TABLE IF EXISTS indexer_blocks;
+DROP TABLE IF EXISTS indexer_failures;
+DROP TABLE IF EXISTS indexer_checkpoints;
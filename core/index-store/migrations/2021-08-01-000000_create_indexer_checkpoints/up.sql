@@ -0,0 +1,38 @@
+-- Last block processed by each stream of an indexer.
+-- It is written in the same transaction as the entity modifications of this block,
+-- so after a crash the indexer can restart right after the last committed block.
+-- An indexer with data sources on several chains or networks has one stream for each of them.
+-- The statements accept the tables created before the migrations were embedded.
+create table if not exists indexer_checkpoints
+(
+    indexer_id   varchar not null,
+    stream_id    varchar not null default '',
+    block_hash   bytea   not null,
+    block_number bigint  not null,
+    updated_at   timestamptz not null default now()
+);
+alter table indexer_checkpoints add column if not exists stream_id varchar not null default '';
+alter table indexer_checkpoints drop constraint if exists indexer_checkpoints_pkey;
+create unique index if not exists indexer_checkpoints_stream_idx
+    on indexer_checkpoints (indexer_id, stream_id);
+create table if not exists indexer_failures
+(
+    indexer_id    varchar not null,
+    stream_id     varchar not null default '',
+    message       text    not null,
+    block_hash    bytea,
+    block_number  bigint,
+    handler       varchar,
+    deterministic boolean not null default false,
+    failed_at     timestamptz not null default now(),
+    primary key (indexer_id, stream_id)
+);
+create table if not exists indexer_blocks
+(
+    indexer_id   varchar not null,
+    block_hash   bytea   not null,
+    block_number bigint  not null,
+    primary key (indexer_id, block_hash)
+);
+create index if not exists indexer_blocks_number_idx
+    on indexer_blocks (indexer_id, block_number);
@@ -1,14 +1,14 @@
-use diesel::{Connection, PgConnection, RunQueryDsl};
-use graph::components::store::{EntityFilter, EntityOrder, EntityRange, WritableStore};
+use graph::components::store::{
+    BlockNumber, EntityFilter, EntityOrder, EntityRange, WritableStore,
+};
 use graph::data::query::QueryExecutionError;
 use graph::data::store::{Entity, Value};
 use lazy_static::lazy_static;
 use massbit_common::prelude::structmap::GenericMap;
 use std::collections::HashMap;
 use std::error::Error;
-use std::fmt::Write;
 use std::sync::{Arc, Mutex};
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio;
 use tokio_postgres::NoTls;
 
@@ -62,13 +62,47 @@ impl TableBuffer {
 }
 
 pub trait QueryableStore: Sync + Send {
+    /// Entities as of `block`, or their latest version when it is None
     fn query(
         &self,
         entity_type: String,
         filter: Option<EntityFilter>,
         order: EntityOrder,
         range: EntityRange,
+        block: Option<BlockNumber>,
     ) -> Vec<Entity>;
+    /// Entities of `entity_type` whose field `field` references the entity `id`,
+    /// they are the value of a `@derivedFrom(field: "...")` field of the referenced entity
+    fn load_derived(&self, entity_type: String, field: String, id: String) -> Vec<Entity> {
+        self.query(
+            entity_type,
+            Some(EntityFilter::Equal(field, Value::String(id))),
+            EntityOrder::Default,
+            EntityRange {
+                first: None,
+                skip: 0,
+            },
+            None,
+        )
+    }
+    /// Entities matching `text` in the full text field `fulltext` declared with `@fulltext` in the schema.
+    /// The text follows the syntax of postgres `to_tsquery`, like `uni & swap`
+    fn search(
+        &self,
+        entity_type: String,
+        fulltext: String,
+        text: String,
+        range: EntityRange,
+        block: Option<BlockNumber>,
+    ) -> Vec<Entity> {
+        self.query(
+            entity_type,
+            Some(EntityFilter::Equal(fulltext, Value::String(text))),
+            EntityOrder::Default,
+            range,
+            block,
+        )
+    }
 }
 pub trait ToWritableStore {
     fn to_writable_store<'a>(self: Arc<Self>) -> Arc<dyn WritableStore + 'a>
@@ -219,80 +253,6 @@ pub trait Store: QueryableStore + Sync + Send {
 //         };
 //     }
 // }
-//fn flush_entity(table_name : &String, _buffer : &Vec<GenericMap>, conn: &TransactionalConnection<PgConnection>) -> QueryResult<usize> {
-fn flush_entity(table_name: &String, _buffer: &Vec<GenericMap>, conn: &PgConnection) {
-    let start = Instant::now();
-    if let Some(query) = create_query(table_name, _buffer) {
-        match diesel::sql_query(query.as_str()).execute(conn) {
-            Ok(res) => {
-                log::info!("{} Execute query with result {:?}.", &*COMPONENT_NAME, res);
-            }
-            Err(err) => {
-                log::error!(
-                    "{} Error {:?} while insert into table: {:?}.",
-                    &*COMPONENT_NAME,
-                    err,
-                    table_name
-                );
-            }
-        }
-        log::info!(
-            "{} Insert {:?} records into table {:?} in: {:?} ms.",
-            &*COMPONENT_NAME,
-            _buffer.len(),
-            table_name,
-            start.elapsed()
-        );
-    }
-}
-
-///
-/// Create Query with format
-/// INSERT INTO {entity_name} ({field1}, {field2}, {field3}) VALUES
-/// ('strval11',numberval12, numberval13),
-/// ('strval21',numberval22, numberval23),
-///
-fn create_query(_entity_name: &str, buffer: &Vec<GenericMap>) -> Option<String> {
-    let mut query = None;
-    if buffer.len() > 0 {
-        if let Some(_data) = buffer.get(0) {
-            let fields: Vec<String> = _data.iter().map(|(k, _)| k.to_string()).collect();
-            //Store vector of row's form ('strval11',numberval12, numberval13)
-            let row_values: Vec<String> = buffer
-                .iter()
-                .map(|_data| {
-                    let field_values: Vec<String> = _data
-                        .iter()
-                        .map(|(_, v)| {
-                            let mut str_val = String::new();
-                            if let Some(r) = v.bool() {
-                                write!(str_val, "{}", r).unwrap();
-                            } else if let Some(r) = v.f64() {
-                                write!(str_val, "{}", r).unwrap();
-                            } else if let Some(r) = v.i64() {
-                                write!(str_val, "{}", r).unwrap();
-                            } else if let Some(r) = v.u64() {
-                                write!(str_val, "{}", r).unwrap();
-                            } else if let Some(r) = v.string() {
-                                write!(str_val, "'{}'", r).unwrap();
-                            }
-                            str_val
-                        })
-                        .collect();
-                    format!("({})", field_values.join(","))
-                })
-                .collect();
-            query = Some(format!(
-                "INSERT INTO {} ({}) VALUES {};",
-                _entity_name,
-                fields.join(","),
-                row_values.join(",")
-            ));
-        }
-    }
-    query
-}
-
 ///
 /// Get relationship dependencies from database
 /// When flush data into one table, first check and flush data in reference table
@@ -3,7 +3,7 @@ use crate::DEPLOYMENT_HASH;
 use graph::blockchain::BlockHash;
 use graph::cheap_clone::CheapClone;
 use graph::components::store::{
-    EntityCache, EntityFilter, EntityKey, EntityOrder, EntityRange, EntityType,
+    BlockNumber, EntityCache, EntityFilter, EntityKey, EntityOrder, EntityRange, EntityType,
     ModificationsAndCache, StoreError, WritableStore,
 };
 use graph::components::subgraph::Entity;
@@ -55,8 +55,12 @@ impl QueryableStore for IndexerState {
         filter: Option<EntityFilter>,
         order: EntityOrder,
         range: EntityRange,
+        block: Option<BlockNumber>,
     ) -> Vec<Entity> {
-        self.store.query(entity_type, filter, order, range)
+        self.store.query(entity_type, filter, order, range, block)
+    }
+    fn load_derived(&self, entity_type: String, field: String, id: String) -> Vec<Entity> {
+        self.store.load_derived(entity_type, field, id)
     }
 }
 impl Store for IndexerState {
@@ -100,44 +104,41 @@ impl Store for IndexerState {
         result
     }
 
+    /// Write the entities saved by the handlers of this block and move the block pointer of the indexer
+    /// in a single transaction. The cache is dropped even if it fails, so the block can be handled again from scratch.
     fn flush(&mut self, block_hash: &String, block_number: u64) -> Result<(), Box<dyn Error>> {
-        //let mut data = self.entity_cache.lock().unwrap();
-
         let entity_cache = std::mem::replace(
             &mut self.entity_cache,
             Self::create_entity_cache(&self.store),
         );
-        if let Ok(ModificationsAndCache {
+        let ModificationsAndCache {
             modifications: mods,
             data_sources: _,
             entity_lfu_cache: _cache,
-        }) = entity_cache.as_modifications().map_err(|e| {
-            log::error!("Error {:?}", e);
-            StoreError::Unknown(e.into())
-        }) {
-            // Transact entity modifications into the store
-            let length = mods.len();
-            if length > 0 {
-                let start = Instant::now();
-                let block_ptr = BlockPtr {
-                    hash: BlockHash::from(block_hash.as_bytes().to_vec()),
-                    number: block_number as i32,
-                };
-                match self.store.transact_block_operations(
-                    block_ptr,
-                    mods,
-                    self.stopwatch.cheap_clone(),
-                    Vec::default(),
-                    vec![],
-                ) {
-                    Ok(_) => {
-                        log::info!("Transact block operation with {} records successfully in {:?}", length, start.elapsed());
-                    }
-                    Err(err) => {
-                        log::error!("Transact block operation with error {:?}", &err);
-                    }
-                }
-            }
+        } = entity_cache
+            .as_modifications()
+            .map_err(|e| StoreError::Unknown(e.into()))?;
+        // Transact entity modifications into the store.
+        // Do it even if there is no modification so the block pointer of the indexer is moved forward
+        let length = mods.len();
+        let start = Instant::now();
+        let block_ptr = BlockPtr {
+            hash: BlockHash::from(block_hash.as_bytes().to_vec()),
+            number: block_number as i32,
+        };
+        self.store.transact_block_operations(
+            block_ptr,
+            mods,
+            self.stopwatch.cheap_clone(),
+            Vec::default(),
+            vec![],
+        )?;
+        if length > 0 {
+            log::info!(
+                "Transact block operation with {} records successfully in {:?}",
+                length,
+                start.elapsed()
+            );
         }
         Ok(())
     }
@@ -0,0 +1,482 @@
+use graph::components::store::{EntityModification, EntityType};
+use graph::components::subgraph::Entity;
+use graph::prelude::{BlockNumber, BlockPtr, StopwatchMetrics};
+use graph::util::cache_weight::CacheWeight;
+use massbit_common::prelude::lazy_static::lazy_static;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::env;
+use std::time::{Duration, Instant};
+
+lazy_static! {
+    /// Max number of blocks kept in memory before they are committed
+    static ref BATCH_MAX_BLOCKS: usize = get_env("INDEX_STORE_BATCH_MAX_BLOCKS", 500);
+    /// Max number of entity modifications kept in memory before they are committed
+    static ref BATCH_MAX_MODIFICATIONS: usize =
+        get_env("INDEX_STORE_BATCH_MAX_MODIFICATIONS", 20_000);
+    /// Max size in bytes of the entities kept in memory before they are committed
+    static ref BATCH_MAX_BYTES: usize = get_env("INDEX_STORE_BATCH_MAX_BYTES", 64 * 1024 * 1024);
+    /// Max time the first block of the batch waits before it is committed
+    static ref BATCH_MAX_AGE: Duration =
+        Duration::from_millis(get_env("INDEX_STORE_BATCH_MAX_AGE_MS", 10_000) as u64);
+    /// Blocks closer than this to the chain head are committed one by one
+    static ref BATCH_MIN_HEAD_DISTANCE: u64 =
+        get_env("INDEX_STORE_BATCH_MIN_HEAD_DISTANCE", 100) as u64;
+}
+
+fn get_env(name: &str, default: usize) -> usize {
+    env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Limits of a batch, a batch reaching one of them is committed
+#[derive(Clone, Debug)]
+pub struct BatchLimits {
+    pub max_blocks: usize,
+    pub max_modifications: usize,
+    pub max_bytes: usize,
+    pub max_age: Duration,
+    pub min_head_distance: u64,
+}
+
+impl Default for BatchLimits {
+    fn default() -> Self {
+        BatchLimits {
+            max_blocks: *BATCH_MAX_BLOCKS,
+            max_modifications: *BATCH_MAX_MODIFICATIONS,
+            max_bytes: *BATCH_MAX_BYTES,
+            max_age: *BATCH_MAX_AGE,
+            min_head_distance: *BATCH_MIN_HEAD_DISTANCE,
+        }
+    }
+}
+
+/// Rows of a table written when a batch is committed
+#[derive(Debug, Default)]
+pub struct TableChanges<'a> {
+    /// Current versions in the table which are ended by the batch, with the block ending them
+    pub clamps: Vec<(&'a str, BlockNumber)>,
+    /// Versions created by the batch with their block range, the last one of an entity stays open
+    pub versions: Vec<(&'a Entity, BlockNumber, Option<BlockNumber>)>,
+}
+
+/// Entity modifications of the blocks which are processed but not committed yet.
+/// While an indexer is far behind the chain head, the blocks are committed together in one
+/// transaction. Each block is still written with its own block number so the history is the same
+/// as with one transaction per block.
+#[derive(Default)]
+pub struct BlockBatch {
+    pub blocks: Vec<(BlockPtr, Vec<EntityModification>)>,
+    /// Latest value of the modified entities, None if the entity is removed
+    pub entities: HashMap<(EntityType, String), Option<Entity>>,
+    pub stopwatch: Option<StopwatchMetrics>,
+    limits: BatchLimits,
+    modifications: usize,
+    bytes: usize,
+    started_at: Option<Instant>,
+    chain_head: Option<u64>,
+}
+
+impl BlockBatch {
+    pub fn with_limits(limits: BatchLimits) -> Self {
+        BlockBatch {
+            limits,
+            ..Default::default()
+        }
+    }
+
+    pub fn push(&mut self, block_ptr: BlockPtr, mods: Vec<EntityModification>) {
+        for modification in &mods {
+            let (key, entity) = match modification {
+                EntityModification::Insert { key, data }
+                | EntityModification::Overwrite { key, data } => (key, Some(data.clone())),
+                EntityModification::Remove { key } => (key, None),
+            };
+            self.bytes += entity.weight();
+            self.entities
+                .insert((key.entity_type.clone(), key.entity_id.clone()), entity);
+        }
+        self.modifications += mods.len();
+        self.started_at.get_or_insert_with(Instant::now);
+        // A block pushed again, e.g. by several handlers, stays one block of the batch
+        match self.blocks.last_mut() {
+            Some((last_ptr, last_mods)) if *last_ptr == block_ptr => {
+                for modification in mods {
+                    merge_modification(last_mods, modification);
+                }
+            }
+            _ => self.blocks.push((block_ptr, mods)),
+        }
+    }
+
+    pub fn set_chain_head(&mut self, chain_head: u64) {
+        self.chain_head = Some(chain_head);
+    }
+
+    /// Commit when a limit is reached, or block by block when the chain head is close or unknown
+    pub fn is_full(&self) -> bool {
+        let limits = &self.limits;
+        let near_head = match (self.chain_head, self.blocks.last()) {
+            (Some(chain_head), Some((block_ptr, _))) => {
+                chain_head.saturating_sub(block_ptr.number as u64) < limits.min_head_distance
+            }
+            _ => true,
+        };
+        near_head
+            || self.blocks.len() >= limits.max_blocks
+            || self.modifications >= limits.max_modifications
+            || self.bytes >= limits.max_bytes
+            || self
+                .started_at
+                .map(|started_at| started_at.elapsed() >= limits.max_age)
+                .unwrap_or_default()
+    }
+
+    /// Rows written for the entity types accepted by `include`. The modifications of an entity
+    /// across the blocks become a chain of versions, so a table is written with one statement
+    /// for the ended versions and one for the new ones whatever the number of blocks.
+    pub fn table_changes<F>(&self, include: F) -> HashMap<EntityType, TableChanges<'_>>
+    where
+        F: Fn(&EntityType) -> bool,
+    {
+        use EntityModification::*;
+        let mut changes: HashMap<EntityType, TableChanges> = HashMap::new();
+        // Version of each entity created by the previous blocks which is still open
+        let mut open: HashMap<(&EntityType, &str), Option<(&Entity, BlockNumber)>> = HashMap::new();
+        for (block_ptr, mods) in &self.blocks {
+            let block = block_ptr.number;
+            for modification in mods {
+                let key = modification.entity_key();
+                if !include(&key.entity_type) {
+                    continue;
+                }
+                let table = changes.entry(key.entity_type.clone()).or_default();
+                let version = match modification {
+                    Insert { data, .. } | Overwrite { data, .. } => Some((data, block)),
+                    Remove { .. } => None,
+                };
+                match open.entry((&key.entity_type, key.entity_id.as_str())) {
+                    Entry::Vacant(entry) => {
+                        // Only an inserted entity has no current version in the table
+                        if !matches!(modification, Insert { .. }) {
+                            table.clamps.push((key.entity_id.as_str(), block));
+                        }
+                        entry.insert(version);
+                    }
+                    Entry::Occupied(mut entry) => {
+                        if let Some((entity, lower)) = entry.insert(version) {
+                            table.versions.push((entity, lower, Some(block)));
+                        }
+                    }
+                }
+            }
+        }
+        for ((entity_type, _), version) in open {
+            if let Some((entity, lower)) = version {
+                changes
+                    .entry(entity_type.clone())
+                    .or_default()
+                    .versions
+                    .push((entity, lower, None));
+            }
+        }
+        changes
+    }
+
+    /// Modifications of the entity types accepted by `include`, block by block
+    pub fn block_modifications<F>(&self, include: F) -> Vec<(&BlockPtr, Vec<EntityModification>)>
+    where
+        F: Fn(&EntityType) -> bool,
+    {
+        self.blocks
+            .iter()
+            .map(|(block_ptr, mods)| {
+                let mods = mods
+                    .iter()
+                    .filter(|modification| include(&modification.entity_key().entity_type))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                (block_ptr, mods)
+            })
+            .filter(|(_, mods)| !mods.is_empty())
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    /// Empty the batch once its blocks are committed, only the limits and the chain head are kept
+    pub fn take(&mut self) -> Vec<(BlockPtr, Vec<EntityModification>)> {
+        let batch = std::mem::replace(
+            self,
+            BlockBatch {
+                limits: self.limits.clone(),
+                chain_head: self.chain_head,
+                ..Default::default()
+            },
+        );
+        batch.blocks
+    }
+}
+
+/// Add a modification to the ones of the same block, the entity keeps a single modification
+fn merge_modification(mods: &mut Vec<EntityModification>, modification: EntityModification) {
+    use EntityModification::*;
+    let position = mods
+        .iter()
+        .position(|previous| previous.entity_key() == modification.entity_key());
+    let previous = match position {
+        Some(position) => mods.remove(position),
+        None => {
+            mods.push(modification);
+            return;
+        }
+    };
+    let merged = match (previous, modification) {
+        // The entity doesn't exist before the block
+        (Insert { .. }, Insert { key, data }) | (Insert { .. }, Overwrite { key, data }) => {
+            Some(Insert { key, data })
+        }
+        (Insert { .. }, Remove { .. }) => None,
+        // The entity exists before the block
+        (Remove { .. }, Insert { key, data }) => Some(Overwrite { key, data }),
+        (_, modification) => Some(modification),
+    };
+    mods.extend(merged);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::blockchain::BlockHash;
+    use graph::components::store::EntityKey;
+    use graph::prelude::DeploymentHash;
+
+    fn block_ptr(number: i32) -> BlockPtr {
+        BlockPtr {
+            hash: BlockHash::from(vec![number as u8; 32]),
+            number,
+        }
+    }
+
+    fn key(id: &str) -> EntityKey {
+        EntityKey::data(
+            DeploymentHash::new("indexer").unwrap(),
+            "Token".to_string(),
+            id.to_string(),
+        )
+    }
+
+    fn entity(id: &str) -> Entity {
+        let mut entity = Entity::new();
+        entity.set("id", id);
+        entity
+    }
+
+    #[test]
+    fn two_pushes_of_the_same_block_are_one_block() {
+        let mut batch = BlockBatch::default();
+        batch.push(
+            block_ptr(1),
+            vec![EntityModification::Insert {
+                key: key("1"),
+                data: entity("1"),
+            }],
+        );
+        batch.push(
+            block_ptr(1),
+            vec![
+                EntityModification::Overwrite {
+                    key: key("1"),
+                    data: entity("1"),
+                },
+                EntityModification::Insert {
+                    key: key("2"),
+                    data: entity("2"),
+                },
+            ],
+        );
+        batch.push(block_ptr(2), vec![]);
+
+        let blocks = batch.take();
+        assert_eq!(blocks.len(), 2);
+        let (ptr, mods) = &blocks[0];
+        assert_eq!(ptr, &block_ptr(1));
+        assert_eq!(mods.len(), 2);
+        assert!(mods
+            .iter()
+            .all(|modification| matches!(modification, EntityModification::Insert { .. })));
+    }
+
+    #[test]
+    fn entity_inserted_then_removed_in_a_block_is_not_written() {
+        let mut mods = vec![EntityModification::Insert {
+            key: key("1"),
+            data: entity("1"),
+        }];
+        merge_modification(&mut mods, EntityModification::Remove { key: key("1") });
+        assert!(mods.is_empty());
+    }
+
+    fn limits() -> BatchLimits {
+        BatchLimits {
+            max_blocks: 100,
+            max_modifications: 100,
+            max_bytes: 1024 * 1024,
+            max_age: Duration::from_secs(3600),
+            min_head_distance: 10,
+        }
+    }
+
+    fn insert(id: &str) -> EntityModification {
+        EntityModification::Insert {
+            key: key(id),
+            data: entity(id),
+        }
+    }
+
+    fn far_from_head(limits: BatchLimits) -> BlockBatch {
+        let mut batch = BlockBatch::with_limits(limits);
+        batch.set_chain_head(1000);
+        batch
+    }
+
+    #[test]
+    fn batch_is_full_near_the_chain_head() {
+        let mut batch = BlockBatch::with_limits(limits());
+        batch.push(block_ptr(1), vec![]);
+        // Unknown chain head
+        assert!(batch.is_full());
+
+        batch.set_chain_head(1000);
+        assert!(!batch.is_full());
+        batch.push(block_ptr(991), vec![]);
+        assert!(batch.is_full());
+    }
+
+    #[test]
+    fn batch_is_full_at_max_blocks() {
+        let mut batch = far_from_head(BatchLimits {
+            max_blocks: 2,
+            ..limits()
+        });
+        batch.push(block_ptr(1), vec![]);
+        assert!(!batch.is_full());
+        batch.push(block_ptr(2), vec![]);
+        assert!(batch.is_full());
+    }
+
+    #[test]
+    fn batch_is_full_at_max_modifications() {
+        let mut batch = far_from_head(BatchLimits {
+            max_modifications: 2,
+            ..limits()
+        });
+        batch.push(block_ptr(1), vec![insert("1")]);
+        assert!(!batch.is_full());
+        batch.push(block_ptr(2), vec![insert("2")]);
+        assert!(batch.is_full());
+    }
+
+    #[test]
+    fn batch_is_full_at_max_bytes() {
+        let mut batch = far_from_head(BatchLimits {
+            max_bytes: Some(entity("1")).weight() + 1,
+            ..limits()
+        });
+        batch.push(block_ptr(1), vec![insert("1")]);
+        assert!(!batch.is_full());
+        batch.push(block_ptr(2), vec![insert("2")]);
+        assert!(batch.is_full());
+    }
+
+    #[test]
+    fn batch_is_full_at_max_age() {
+        let mut batch = far_from_head(limits());
+        batch.push(block_ptr(1), vec![]);
+        assert!(!batch.is_full());
+        batch.started_at = Instant::now().checked_sub(Duration::from_secs(7200));
+        assert!(batch.is_full());
+    }
+
+    #[test]
+    fn take_keeps_the_limits_and_the_chain_head() {
+        let mut batch = far_from_head(BatchLimits {
+            max_blocks: 2,
+            ..limits()
+        });
+        batch.push(block_ptr(1), vec![insert("1")]);
+        assert_eq!(batch.take().len(), 1);
+        assert!(batch.is_empty());
+        assert!(batch.entities.is_empty());
+        batch.push(block_ptr(2), vec![]);
+        assert!(!batch.is_full());
+        batch.push(block_ptr(3), vec![]);
+        assert!(batch.is_full());
+    }
+
+    #[test]
+    fn modifications_of_an_entity_become_a_chain_of_versions() {
+        let mut batch = far_from_head(limits());
+        batch.push(block_ptr(1), vec![insert("1")]);
+        batch.push(
+            block_ptr(2),
+            vec![
+                EntityModification::Overwrite {
+                    key: key("1"),
+                    data: entity("1"),
+                },
+                // Entity 2 exists before the batch
+                EntityModification::Overwrite {
+                    key: key("2"),
+                    data: entity("2"),
+                },
+            ],
+        );
+        batch.push(
+            block_ptr(3),
+            vec![
+                EntityModification::Remove { key: key("1") },
+                EntityModification::Remove { key: key("3") },
+            ],
+        );
+
+        let mut changes = batch.table_changes(|_| true);
+        assert_eq!(changes.len(), 1);
+        let table = changes
+            .remove(&EntityType::new("Token".to_string()))
+            .unwrap();
+        let mut clamps = table.clamps.clone();
+        clamps.sort();
+        assert_eq!(clamps, vec![("2", 2), ("3", 3)]);
+        let mut versions = table
+            .versions
+            .iter()
+            .map(|(entity, lower, upper)| (entity.id().unwrap(), *lower, *upper))
+            .collect::<Vec<_>>();
+        versions.sort();
+        assert_eq!(
+            versions,
+            vec![
+                ("1".to_string(), 1, Some(2)),
+                ("1".to_string(), 2, Some(3)),
+                ("2".to_string(), 2, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn excluded_entity_types_are_written_block_by_block() {
+        let mut batch = far_from_head(limits());
+        batch.push(block_ptr(1), vec![insert("1")]);
+        batch.push(block_ptr(2), vec![]);
+        assert!(batch.table_changes(|_| false).is_empty());
+        let blocks = batch.block_modifications(|_| true);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].0, &block_ptr(1));
+        assert!(batch.block_modifications(|_| false).is_empty());
+    }
+}
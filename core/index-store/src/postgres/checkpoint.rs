@@ -0,0 +1,332 @@
+use diesel::sql_types::{Array, BigInt, Binary, Bool, Nullable, Text};
+use diesel::QueryableByName;
+use graph::blockchain::BlockHash;
+use graph::prelude::{BlockPtr, StoreError};
+use massbit_common::prelude::diesel::{sql_query, PgConnection, RunQueryDsl};
+
+/// Bytes of a block hash sent by the chain reader or given in a query. The `0x` hex strings
+/// (ethereum, substrate) are decoded, so a hash matches whatever the case of its digits.
+/// The other hashes (base58 for solana) are kept as they are
+pub fn block_hash_bytes(hash: &str) -> Vec<u8> {
+    match hash.strip_prefix("0x").map(hex::decode) {
+        Some(Ok(bytes)) => bytes,
+        _ => hash.as_bytes().to_vec(),
+    }
+}
+
+/// Last block processed by a stream of an indexer.
+/// The checkpoint tables are created by the embedded migrations, see metadata.rs
+#[derive(Debug, Clone, QueryableByName)]
+struct Checkpoint {
+    #[sql_type = "Binary"]
+    pub block_hash: Vec<u8>,
+    #[sql_type = "BigInt"]
+    pub block_number: i64,
+}
+
+impl Checkpoint {
+    fn into_block_ptr(self) -> BlockPtr {
+        BlockPtr {
+            hash: BlockHash::from(self.block_hash),
+            number: self.block_number as i32,
+        }
+    }
+}
+
+pub fn load_block_ptr(
+    conn: &PgConnection,
+    indexer: &str,
+    stream: &str,
+) -> Result<Option<BlockPtr>, StoreError> {
+    let checkpoint = sql_query(
+        "select block_hash, block_number from indexer_checkpoints where indexer_id = $1 and stream_id = $2",
+    )
+    .bind::<Text, _>(indexer)
+    .bind::<Text, _>(stream)
+    .get_results::<Checkpoint>(conn)?
+    .pop();
+    Ok(checkpoint.map(Checkpoint::into_block_ptr))
+}
+
+/// Checkpoint of the stream which is the most behind
+pub fn load_lowest_block_ptr(
+    conn: &PgConnection,
+    indexer: &str,
+) -> Result<Option<BlockPtr>, StoreError> {
+    let checkpoint = sql_query(
+        "select block_hash, block_number from indexer_checkpoints where indexer_id = $1 order by block_number limit 1",
+    )
+    .bind::<Text, _>(indexer)
+    .get_results::<Checkpoint>(conn)?
+    .pop();
+    Ok(checkpoint.map(Checkpoint::into_block_ptr))
+}
+
+#[derive(Debug, Clone, QueryableByName)]
+struct BlockNumberRow {
+    #[sql_type = "BigInt"]
+    pub block_number: i64,
+}
+
+/// Keep the hashes of the committed blocks, so the entities can be queried as of a block hash.
+/// Must be called inside the transaction which writes the entities of these blocks
+pub fn save_block_hashes(
+    conn: &PgConnection,
+    indexer: &str,
+    block_ptrs: &[&BlockPtr],
+) -> Result<(), StoreError> {
+    if block_ptrs.is_empty() {
+        return Ok(());
+    }
+    // Postgres can't update the same row twice in one insert
+    let block_ptrs = unique_block_ptrs(block_ptrs);
+    let hashes: Vec<Vec<u8>> = block_ptrs
+        .iter()
+        .map(|block_ptr| block_ptr.hash_slice().to_vec())
+        .collect();
+    let numbers: Vec<i64> = block_ptrs
+        .iter()
+        .map(|block_ptr| block_ptr.number as i64)
+        .collect();
+    sql_query(
+        r#"insert into indexer_blocks (indexer_id, block_hash, block_number)
+        select $1, unnest($2::bytea[]), unnest($3::bigint[])
+        on conflict (indexer_id, block_hash)
+        do update set block_number = excluded.block_number"#,
+    )
+    .bind::<Text, _>(indexer)
+    .bind::<Array<Binary>, _>(hashes)
+    .bind::<Array<BigInt>, _>(numbers)
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Keep the last pointer of each block hash, in the order of the blocks
+fn unique_block_ptrs<'a>(block_ptrs: &[&'a BlockPtr]) -> Vec<&'a BlockPtr> {
+    let mut unique: Vec<&BlockPtr> = vec![];
+    for &block_ptr in block_ptrs {
+        match unique.iter().position(|ptr| ptr.hash == block_ptr.hash) {
+            Some(position) => unique[position] = block_ptr,
+            None => unique.push(block_ptr),
+        }
+    }
+    unique
+}
+
+/// Number of a committed block of the indexer
+pub fn load_block_number(
+    conn: &PgConnection,
+    indexer: &str,
+    block_hash: &[u8],
+) -> Result<Option<i64>, StoreError> {
+    let row = sql_query(
+        "select block_number from indexer_blocks where indexer_id = $1 and block_hash = $2",
+    )
+    .bind::<Text, _>(indexer)
+    .bind::<Binary, _>(block_hash)
+    .get_results::<BlockNumberRow>(conn)?
+    .pop();
+    Ok(row.map(|row| row.block_number))
+}
+
+#[derive(Debug, Clone, QueryableByName)]
+struct StreamRow {
+    #[sql_type = "Text"]
+    pub stream_id: String,
+}
+
+/// Streams of the indexer which have a checkpoint
+pub fn load_stream_ids(conn: &PgConnection, indexer: &str) -> Result<Vec<String>, StoreError> {
+    let rows =
+        sql_query("select distinct stream_id from indexer_checkpoints where indexer_id = $1")
+            .bind::<Text, _>(indexer)
+            .get_results::<StreamRow>(conn)?;
+    Ok(rows.into_iter().map(|row| row.stream_id).collect())
+}
+
+/// Forget the blocks reverted by a reorg
+pub fn remove_block_hashes_after(
+    conn: &PgConnection,
+    indexer: &str,
+    block_number: i64,
+) -> Result<(), StoreError> {
+    sql_query("delete from indexer_blocks where indexer_id = $1 and block_number > $2")
+        .bind::<Text, _>(indexer)
+        .bind::<BigInt, _>(block_number)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Must be called inside the transaction which writes the entities of `block_ptr`
+pub fn save_block_ptr(
+    conn: &PgConnection,
+    indexer: &str,
+    stream: &str,
+    block_ptr: &BlockPtr,
+) -> Result<(), StoreError> {
+    sql_query(
+        r#"insert into indexer_checkpoints (indexer_id, stream_id, block_hash, block_number, updated_at)
+        values ($1, $2, $3, $4, now())
+        on conflict (indexer_id, stream_id)
+        do update set block_hash = excluded.block_hash,
+                      block_number = excluded.block_number,
+                      updated_at = excluded.updated_at"#,
+    )
+    .bind::<Text, _>(indexer)
+    .bind::<Text, _>(stream)
+    .bind::<Binary, _>(block_ptr.hash_slice())
+    .bind::<BigInt, _>(block_ptr.number as i64)
+    .execute(conn)?;
+    Ok(())
+}
+
+/// Remove the checkpoints, the block hashes and the failures of every stream of the indexer
+pub fn remove_block_ptr(conn: &PgConnection, indexer: &str) -> Result<(), StoreError> {
+    sql_query("delete from indexer_checkpoints where indexer_id = $1")
+        .bind::<Text, _>(indexer)
+        .execute(conn)?;
+    sql_query("delete from indexer_blocks where indexer_id = $1")
+        .bind::<Text, _>(indexer)
+        .execute(conn)?;
+    sql_query("delete from indexer_failures where indexer_id = $1")
+        .bind::<Text, _>(indexer)
+        .execute(conn)?;
+    Ok(())
+}
+
+/// Keep the error which stopped a stream of the indexer, only the last one is kept
+pub fn save_failure(
+    conn: &PgConnection,
+    indexer: &str,
+    stream: &str,
+    message: &str,
+    block_ptr: Option<&BlockPtr>,
+    handler: Option<&str>,
+    deterministic: bool,
+) -> Result<(), StoreError> {
+    sql_query(
+        r#"insert into indexer_failures
+            (indexer_id, stream_id, message, block_hash, block_number, handler, deterministic, failed_at)
+        values ($1, $2, $3, $4, $5, $6, $7, now())
+        on conflict (indexer_id, stream_id)
+        do update set message = excluded.message,
+                      block_hash = excluded.block_hash,
+                      block_number = excluded.block_number,
+                      handler = excluded.handler,
+                      deterministic = excluded.deterministic,
+                      failed_at = excluded.failed_at"#,
+    )
+    .bind::<Text, _>(indexer)
+    .bind::<Text, _>(stream)
+    .bind::<Text, _>(message)
+    .bind::<Nullable<Binary>, _>(block_ptr.map(|block_ptr| block_ptr.hash_slice()))
+    .bind::<Nullable<BigInt>, _>(block_ptr.map(|block_ptr| block_ptr.number as i64))
+    .bind::<Nullable<Text>, _>(handler)
+    .bind::<Bool, _>(deterministic)
+    .execute(conn)?;
+    Ok(())
+}
+
+pub fn remove_failure(conn: &PgConnection, indexer: &str, stream: &str) -> Result<(), StoreError> {
+    sql_query("delete from indexer_failures where indexer_id = $1 and stream_id = $2")
+        .bind::<Text, _>(indexer)
+        .bind::<Text, _>(stream)
+        .execute(conn)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postgres::metadata;
+    use crate::postgres::store_builder::DATABASE_CONNECTION_STRING;
+    use massbit_common::prelude::diesel::Connection;
+
+    fn block_ptr(number: i32) -> BlockPtr {
+        BlockPtr {
+            hash: BlockHash::from(vec![number as u8; 32]),
+            number,
+        }
+    }
+
+    #[test]
+    fn block_hashes_are_saved_once() {
+        let (first, second) = (block_ptr(1), block_ptr(2));
+        let block_ptrs = vec![&first, &second, &first];
+        assert_eq!(unique_block_ptrs(&block_ptrs), vec![&first, &second]);
+    }
+
+    #[test]
+    fn hex_block_hashes_are_decoded() {
+        let hash = format!("0x{}", "ab".repeat(32));
+        assert_eq!(block_hash_bytes(&hash), vec![0xab; 32]);
+        assert_eq!(
+            block_hash_bytes(&hash.to_uppercase().replace("0X", "0x")),
+            vec![0xab; 32]
+        );
+        // Solana hashes are base58
+        let solana = "4sGjMW1sUnHzSxGspuhpqLDx6wiyjNtZAMdL4VZHirAn";
+        assert_eq!(block_hash_bytes(solana), solana.as_bytes().to_vec());
+        assert_eq!(block_hash_bytes("0xnot hex"), b"0xnot hex".to_vec());
+    }
+
+    #[test]
+    fn last_number_of_a_block_hash_is_kept() {
+        let first = block_ptr(1);
+        let moved = BlockPtr {
+            hash: first.hash.clone(),
+            number: 3,
+        };
+        let second = block_ptr(2);
+        let block_ptrs = vec![&first, &second, &moved];
+        assert_eq!(unique_block_ptrs(&block_ptrs), vec![&moved, &second]);
+    }
+
+    // Needs the database of DATABASE_CONNECTION_STRING
+    #[test]
+    #[ignore]
+    fn checkpoints_and_block_hashes_are_saved_by_indexer() {
+        let indexer = "index_store_checkpoint_test";
+        let conn = PgConnection::establish(DATABASE_CONNECTION_STRING.as_str()).unwrap();
+        metadata::run_migrations(&conn).unwrap();
+        remove_block_ptr(&conn, indexer).unwrap();
+
+        save_block_ptr(&conn, indexer, "stream1", &block_ptr(5)).unwrap();
+        save_block_ptr(&conn, indexer, "stream2", &block_ptr(3)).unwrap();
+        save_block_ptr(&conn, indexer, "stream1", &block_ptr(6)).unwrap();
+        assert_eq!(
+            load_block_ptr(&conn, indexer, "stream1").unwrap(),
+            Some(block_ptr(6))
+        );
+        assert_eq!(
+            load_lowest_block_ptr(&conn, indexer).unwrap(),
+            Some(block_ptr(3))
+        );
+        let mut streams = load_stream_ids(&conn, indexer).unwrap();
+        streams.sort();
+        assert_eq!(streams, vec!["stream1", "stream2"]);
+
+        let block_ptrs: Vec<BlockPtr> = (1..=6).map(block_ptr).collect();
+        save_block_hashes(&conn, indexer, &block_ptrs.iter().collect::<Vec<_>>()).unwrap();
+        assert_eq!(
+            load_block_number(&conn, indexer, block_ptr(4).hash_slice()).unwrap(),
+            Some(4)
+        );
+        remove_block_hashes_after(&conn, indexer, 3).unwrap();
+        assert_eq!(
+            load_block_number(&conn, indexer, block_ptr(4).hash_slice()).unwrap(),
+            None
+        );
+        assert_eq!(
+            load_block_number(&conn, indexer, block_ptr(3).hash_slice()).unwrap(),
+            Some(3)
+        );
+
+        remove_block_ptr(&conn, indexer).unwrap();
+        assert_eq!(load_block_ptr(&conn, indexer, "stream1").unwrap(), None);
+        assert_eq!(
+            load_block_number(&conn, indexer, block_ptr(3).hash_slice()).unwrap(),
+            None
+        );
+    }
+}
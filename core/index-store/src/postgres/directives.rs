@@ -0,0 +1,366 @@
+use graph::components::store::{EntityFilter, EntityType};
+use graph::data::schema::Schema;
+use graph::prelude::{s, StoreError, Value};
+use graph_store_postgres::relational::{Column, Layout, Table};
+use massbit_common::prelude::anyhow::anyhow;
+
+/// Postgres truncates the longer identifiers
+const MAX_IDENTIFIER_LENGTH: usize = 63;
+/// Type of the graphql schema holding the `@fulltext` directives, it has no table
+pub const SCHEMA_TYPE_NAME: &str = "_Schema_";
+
+/// Sql statements creating the indexes declared with directives in the graphql schema:
+/// - `type Pair @index(fields: ["token0", "token1"])` creates a btree index on the fields, or a gin index on a list field
+/// - `symbol: String! @unique` creates a unique index on the current versions of the entities
+/// The full text fields of `@fulltext` are created with their gin index by the layout itself.
+pub fn directive_indexes(layout: &Layout, schema: &Schema) -> Result<Vec<String>, StoreError> {
+    let mut statements = Vec::new();
+    for object_type in get_entity_types(&schema.document) {
+        let table = layout.table_for_entity(&EntityType::new(object_type.name.clone()))?;
+        for directive in object_type
+            .directives
+            .iter()
+            .filter(|directive| directive.name == "index")
+        {
+            let fields = match directive
+                .arguments
+                .iter()
+                .find(|(name, _)| name == "fields")
+            {
+                Some((_, s::Value::List(fields))) => fields
+                    .iter()
+                    .map(|field| match field {
+                        s::Value::String(field) => Ok(field.as_str()),
+                        _ => Err(invalid_directive(object_type, "fields must be strings")),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+                _ => {
+                    return Err(invalid_directive(
+                        object_type,
+                        "a list of fields is required",
+                    ))
+                }
+            };
+            let columns = fields
+                .iter()
+                .map(|field| get_column(object_type, table, field))
+                .collect::<Result<Vec<_>, _>>()?;
+            let method = match columns.as_slice() {
+                [] => {
+                    return Err(invalid_directive(
+                        object_type,
+                        "a list of fields is required",
+                    ))
+                }
+                [column] if column.is_list() => "gin",
+                columns if columns.iter().any(|column| column.is_list()) => {
+                    return Err(invalid_directive(
+                        object_type,
+                        "a list field can only be indexed alone",
+                    ))
+                }
+                _ => "btree",
+            };
+            statements.push(format!(
+                "create index if not exists {} on {} using {}({})",
+                index_name(table, &columns, "idx"),
+                table.qualified_name.as_str(),
+                method,
+                quoted_names(&columns)
+            ));
+        }
+        for field in object_type.fields.iter().filter(|field| {
+            field
+                .directives
+                .iter()
+                .any(|directive| directive.name == "unique")
+        }) {
+            let column = get_column(object_type, table, &field.name)?;
+            if column.is_list() {
+                return Err(invalid_directive(
+                    object_type,
+                    &format!("the list field {} can not be unique", &field.name),
+                ));
+            }
+            // The id is unique already
+            if field.name == "id" {
+                continue;
+            }
+            // The older versions of an entity keep the value, only the current ones must be unique
+            statements.push(format!(
+                "create unique index if not exists {} on {}({}) where upper_inf(block_range)",
+                index_name(table, &[column], "key"),
+                table.qualified_name.as_str(),
+                quoted_names(&[column])
+            ));
+        }
+    }
+    Ok(statements)
+}
+
+/// Name and entity type of each `@fulltext(name: "...", include: [{ entity: "..." }])` of the schema
+pub fn fulltext_fields(document: &s::Document) -> Vec<(String, String)> {
+    document
+        .definitions
+        .iter()
+        .filter_map(|definition| match definition {
+            s::Definition::TypeDefinition(s::TypeDefinition::Object(object_type))
+                if object_type.name == SCHEMA_TYPE_NAME =>
+            {
+                Some(object_type)
+            }
+            _ => None,
+        })
+        .flat_map(|object_type| object_type.directives.iter())
+        .filter(|directive| directive.name == "fulltext")
+        .filter_map(|directive| {
+            let name = match directive.arguments.iter().find(|(name, _)| name == "name") {
+                Some((_, s::Value::String(name))) => name.clone(),
+                _ => return None,
+            };
+            let entity = match directive
+                .arguments
+                .iter()
+                .find(|(name, _)| name == "include")
+            {
+                Some((_, s::Value::List(includes))) => {
+                    includes.iter().find_map(|include| match include {
+                        s::Value::Object(include) => match include.get("entity") {
+                            Some(s::Value::String(entity)) => Some(entity.clone()),
+                            _ => None,
+                        },
+                        _ => None,
+                    })
+                }
+                _ => None,
+            }?;
+            Some((name, entity))
+        })
+        .collect()
+}
+
+/// Field of the referencing entity a `@derivedFrom(field: "...")` field is derived from
+pub fn derived_from(field: &s::Field) -> Option<&str> {
+    field
+        .directives
+        .iter()
+        .find(|directive| directive.name == "derivedFrom")
+        .and_then(|directive| {
+            directive
+                .arguments
+                .iter()
+                .find_map(|(name, value)| match (name.as_str(), value) {
+                    ("field", s::Value::String(field)) => Some(field.as_str()),
+                    _ => None,
+                })
+        })
+}
+
+/// Filter of the entities of `entity_type` referencing the entity `id` in their field `field`,
+/// either directly or in a list of references
+pub fn derived_filter(
+    document: &s::Document,
+    entity_type: &str,
+    field: &str,
+    id: &str,
+) -> EntityFilter {
+    let is_list = get_entity_types(document)
+        .find(|object_type| object_type.name == entity_type)
+        .and_then(|object_type| {
+            object_type
+                .fields
+                .iter()
+                .find(|definition| definition.name == field)
+        })
+        .map(|definition| is_list_type(&definition.field_type))
+        .unwrap_or_default();
+    if is_list {
+        EntityFilter::Contains(
+            field.to_string(),
+            Value::List(vec![Value::String(id.to_string())]),
+        )
+    } else {
+        EntityFilter::Equal(field.to_string(), Value::String(id.to_string()))
+    }
+}
+
+fn is_list_type(field_type: &s::Type) -> bool {
+    match field_type {
+        s::Type::NamedType(_) => false,
+        s::Type::ListType(_) => true,
+        s::Type::NonNullType(inner) => is_list_type(inner),
+    }
+}
+
+fn get_entity_types(document: &s::Document) -> impl Iterator<Item = &s::ObjectType> {
+    document
+        .definitions
+        .iter()
+        .filter_map(|definition| match definition {
+            s::Definition::TypeDefinition(s::TypeDefinition::Object(object_type))
+                if object_type.name != SCHEMA_TYPE_NAME =>
+            {
+                Some(object_type)
+            }
+            _ => None,
+        })
+}
+
+fn get_column<'a>(
+    object_type: &s::ObjectType,
+    table: &'a Table,
+    field: &str,
+) -> Result<&'a Column, StoreError> {
+    table
+        .columns
+        .iter()
+        .find(|column| column.field == field)
+        .ok_or_else(|| {
+            invalid_directive(
+                object_type,
+                &format!("{} is not a stored field of the entity", field),
+            )
+        })
+}
+
+fn invalid_directive(object_type: &s::ObjectType, message: &str) -> StoreError {
+    StoreError::Unknown(anyhow!(
+        "Invalid index directive on type {}: {}",
+        &object_type.name,
+        message
+    ))
+}
+
+fn quoted_names(columns: &[&Column]) -> String {
+    columns
+        .iter()
+        .map(|column| format!("\"{}\"", column.name.as_str()))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Index names must be stable, so `create index if not exists` finds the index created by a previous start
+fn index_name(table: &Table, columns: &[&Column], suffix: &str) -> String {
+    let column_names = columns
+        .iter()
+        .map(|column| column.name.as_str())
+        .collect::<Vec<&str>>()
+        .join("_");
+    let name = format!("{}_{}_{}", table.name.as_str(), column_names, suffix);
+    if name.len() <= MAX_IDENTIFIER_LENGTH {
+        return format!("\"{}\"", name);
+    }
+    // FNV-1a hash of the full name keeps the truncated names distinct
+    let hash = name.bytes().fold(0x811c9dc5u32, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x01000193)
+    });
+    let prefix: String = name.chars().take(MAX_IDENTIFIER_LENGTH - 13).collect();
+    format!("\"{}_{:08x}_{}\"", prefix, hash, suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postgres::store_builder::StoreBuilder;
+    use graph::prelude::DeploymentHash;
+    use std::fs;
+
+    const SCHEMA: &str = "
+        type Pair @entity @index(fields: [\"base\", \"quote\"]) @index(fields: [\"tags\"]) {
+            id: ID!
+            base: String!
+            quote: String!
+            symbol: String! @unique
+            tags: [String!]!
+        }
+    ";
+
+    fn statements(name: &str, schema: &str) -> Result<Vec<String>, StoreError> {
+        let path = std::env::temp_dir().join(format!("index_store_directives_{}.graphql", name));
+        fs::write(&path, schema).unwrap();
+        let layout = StoreBuilder::create_layout("sgd0", &path);
+        let schema = StoreBuilder::load_schema(&path);
+        fs::remove_file(&path).unwrap();
+        directive_indexes(&layout?, &schema?)
+    }
+
+    #[test]
+    fn index_and_unique_directives_create_indexes() {
+        let statements = statements("index", SCHEMA).unwrap();
+        assert_eq!(
+            statements,
+            vec![
+                "create index if not exists \"pair_base_quote_idx\" on \"sgd0\".\"pair\" using btree(\"base\", \"quote\")",
+                "create index if not exists \"pair_tags_idx\" on \"sgd0\".\"pair\" using gin(\"tags\")",
+                "create unique index if not exists \"pair_symbol_key\" on \"sgd0\".\"pair\"(\"symbol\") where upper_inf(block_range)",
+            ]
+        );
+    }
+
+    #[test]
+    fn index_names_are_stable() {
+        assert_eq!(
+            statements("stable_1", SCHEMA).unwrap(),
+            statements("stable_2", SCHEMA).unwrap()
+        );
+    }
+
+    #[test]
+    fn long_index_names_are_truncated_with_a_hash() {
+        let schema = "
+            type Pair @entity @index(fields: [\"firstTokenOfThePairWithALongName\", \"secondTokenOfThePairWithALongName\"]) {
+                id: ID!
+                firstTokenOfThePairWithALongName: String!
+                secondTokenOfThePairWithALongName: String!
+            }
+        ";
+        let statements = statements("long", schema).unwrap();
+        let name = statements[0].split_whitespace().nth(5).unwrap();
+        assert_eq!(name.trim_matches('"').len(), MAX_IDENTIFIER_LENGTH);
+        assert!(name.ends_with("_idx\""));
+        assert_eq!(statements, self::statements("long_again", schema).unwrap());
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let schema = "type Pair @entity @index(fields: [\"price\"]) { id: ID! base: String! }";
+        let err = statements("unknown", schema).unwrap_err();
+        assert!(err.to_string().contains(
+            "Invalid index directive on type Pair: price is not a stored field of the entity"
+        ));
+    }
+
+    #[test]
+    fn list_fields_are_indexed_alone() {
+        let schema = "
+            type Pair @entity @index(fields: [\"base\", \"tags\"]) {
+                id: ID!
+                base: String!
+                tags: [String!]!
+            }
+        ";
+        let err = statements("list", schema).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("a list field can only be indexed alone"));
+    }
+
+    #[test]
+    fn fulltext_directives_are_parsed() {
+        let schema = Schema::parse(
+            "
+            type _Schema_
+                @fulltext(name: \"bandSearch\", language: en, algorithm: rank, include: [{ entity: \"Band\", fields: [{ name: \"name\" }] }])
+                @fulltext(include: [{ entity: \"Band\" }])
+            type Band @entity { id: ID! name: String! }
+            ",
+            DeploymentHash::new("indexer").unwrap(),
+        )
+        .unwrap();
+        // The directive without a name is ignored
+        assert_eq!(
+            fulltext_fields(&schema.document),
+            vec![("bandSearch".to_string(), "Band".to_string())]
+        );
+    }
+}
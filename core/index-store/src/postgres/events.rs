@@ -0,0 +1,139 @@
+use graph::components::store::EntityModification;
+use graph::prelude::{BlockNumber, BlockPtr};
+use massbit_common::prelude::lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::env;
+use tokio::sync::broadcast;
+
+lazy_static! {
+    /// Events kept for the slow subscribers, the older ones are dropped
+    static ref STORE_EVENT_BUFFER: usize = env::var("INDEX_STORE_EVENT_BUFFER")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(1024);
+    static ref STORE_EVENTS: broadcast::Sender<IndexStoreEvent> =
+        broadcast::channel(*STORE_EVENT_BUFFER).0;
+}
+
+/// Entities changed by a commit of an indexer, published once the transaction succeeded.
+/// After a revert the changed entities are unknown, so every entity of the indexer may have changed.
+#[derive(Clone, Debug)]
+pub struct IndexStoreEvent {
+    pub indexer: String,
+    /// Last committed block, or the block the indexer is reverted to
+    pub block_number: BlockNumber,
+    /// Entity type and id of the inserted, updated and removed entities
+    pub changes: HashSet<(String, String)>,
+    pub revert: bool,
+}
+
+impl IndexStoreEvent {
+    pub fn from_blocks(indexer: &str, blocks: &[(BlockPtr, Vec<EntityModification>)]) -> Self {
+        let changes = blocks
+            .iter()
+            .flat_map(|(_, mods)| mods.iter())
+            .map(|modification| {
+                let key = match modification {
+                    EntityModification::Insert { key, .. }
+                    | EntityModification::Overwrite { key, .. }
+                    | EntityModification::Remove { key } => key,
+                };
+                (key.entity_type.to_string(), key.entity_id.clone())
+            })
+            .collect();
+        IndexStoreEvent {
+            indexer: indexer.to_string(),
+            block_number: blocks
+                .last()
+                .map(|(block_ptr, _)| block_ptr.number)
+                .unwrap_or_default(),
+            changes,
+            revert: false,
+        }
+    }
+
+    pub fn revert(indexer: &str, block_number: BlockNumber) -> Self {
+        IndexStoreEvent {
+            indexer: indexer.to_string(),
+            block_number,
+            changes: HashSet::new(),
+            revert: true,
+        }
+    }
+
+    /// Whether the entity, or any entity of the type when `entity_id` is None, may have changed
+    pub fn matches(&self, entity_type: &str, entity_id: Option<&str>) -> bool {
+        self.revert
+            || self.changes.iter().any(|(changed_type, changed_id)| {
+                changed_type == entity_type && entity_id.map(|id| id == changed_id).unwrap_or(true)
+            })
+    }
+}
+
+/// Send the event to the subscribers of every indexer, it is dropped when there is none
+pub fn publish(event: IndexStoreEvent) {
+    if !event.revert && event.changes.is_empty() {
+        return;
+    }
+    let _ = STORE_EVENTS.send(event);
+}
+
+pub fn subscribe() -> broadcast::Receiver<IndexStoreEvent> {
+    STORE_EVENTS.subscribe()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::blockchain::BlockHash;
+    use graph::components::store::EntityKey;
+    use graph::prelude::DeploymentHash;
+
+    fn event(changes: &[(&str, &str)]) -> IndexStoreEvent {
+        let mods = changes
+            .iter()
+            .map(|(entity_type, id)| EntityModification::Remove {
+                key: EntityKey::data(
+                    DeploymentHash::new("indexer").unwrap(),
+                    entity_type.to_string(),
+                    id.to_string(),
+                ),
+            })
+            .collect();
+        let block_ptr = BlockPtr {
+            hash: BlockHash::from(vec![1u8; 32]),
+            number: 7,
+        };
+        IndexStoreEvent::from_blocks("indexer", &[(block_ptr, mods)])
+    }
+
+    #[test]
+    fn changes_are_collected_from_the_blocks() {
+        let event = event(&[("Token", "t1"), ("Token", "t1"), ("Account", "alice")]);
+        assert_eq!(event.block_number, 7);
+        assert_eq!(event.changes.len(), 2);
+        assert!(!event.revert);
+    }
+
+    #[test]
+    fn entity_type_must_match() {
+        let event = event(&[("Token", "t1")]);
+        assert!(event.matches("Token", None));
+        assert!(!event.matches("Account", None));
+        assert!(!event.matches("Account", Some("t1")));
+    }
+
+    #[test]
+    fn entity_id_must_match() {
+        let event = event(&[("Token", "t1")]);
+        assert!(event.matches("Token", Some("t1")));
+        assert!(!event.matches("Token", Some("t2")));
+    }
+
+    #[test]
+    fn revert_matches_every_entity() {
+        let event = IndexStoreEvent::revert("indexer", 3);
+        assert!(event.matches("Token", Some("t1")));
+        assert!(event.matches("Account", None));
+    }
+}
@@ -0,0 +1,1093 @@
+/**
+ *** Objective of this file is to answer the graphql queries of an indexer from its tables
+ *** without Hasura. The query api follows the one of the graph:
+ *** - `token(id: "...")` returns one entity, `tokens(where, orderBy, orderDirection, first, skip)` a list
+ *** - the fields which reference other entities can be queried with a nested selection
+ *** - a `@derivedFrom` field returns the entities referencing this one, it accepts `first`, `skip` and `orderBy`
+ *** - `block: { number: ... }` or `block: { hash: "..." }` queries the entities as of a block
+ *** - `bandSearch(text: "...")` searches the entities with the full text field `bandSearch` of `@fulltext`
+ *** - a subscription is answered like a query, `watched_entities` tells when to answer it again
+ *** The nested selections are resolved level by level: the entities referenced by all the entities
+ *** of a level are loaded with one query per field. Queries too deep or loading too many entities
+ *** are rejected before they run.
+ **/
+use super::directives::{derived_from, fulltext_fields, SCHEMA_TYPE_NAME};
+use super::relational::LayoutExt;
+use super::PostgresIndexStore;
+use graph::components::store::{
+    AttributeNames, BlockNumber, ChildMultiplicity, EntityFilter, EntityLink, EntityOrder,
+    EntityRange, EntityType, EntityWindow, WindowAttribute,
+};
+use graph::data::store::scalar::Bytes;
+use graph::prelude::{q, s, BigDecimal, BigInt, Entity, Value, ValueType};
+use inflector::Inflector;
+use massbit_common::prelude::anyhow::{anyhow, Error};
+use massbit_common::prelude::diesel::PgConnection;
+use massbit_common::prelude::lazy_static::lazy_static;
+use massbit_common::prelude::serde_json::{self, json};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::env;
+use std::str::FromStr;
+
+/// Attribute holding the parent of an entity loaded by a window query
+const PARENT_ID: &str = "g$parent_id";
+
+/// Number of entities returned by a list query without `first`, and the max of `first` and `skip`
+const DEFAULT_FIRST: u32 = 100;
+const MAX_FIRST: u32 = 1000;
+const MAX_SKIP: u32 = 5000;
+
+lazy_static! {
+    /// Max number of nested selections of a query
+    static ref GRAPHQL_MAX_DEPTH: usize = env::var("INDEX_STORE_GRAPHQL_MAX_DEPTH")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(10);
+    /// Max number of entities a query may load, a list counts for `first` entities
+    static ref GRAPHQL_MAX_COMPLEXITY: u64 = env::var("INDEX_STORE_GRAPHQL_MAX_COMPLEXITY")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(1_000_000);
+}
+
+/// Filters of the `where` argument, the longest suffixes first
+const FILTER_SUFFIXES: [&str; 13] = [
+    "_not_starts_with",
+    "_not_ends_with",
+    "_not_contains",
+    "_starts_with",
+    "_ends_with",
+    "_contains",
+    "_not_in",
+    "_not",
+    "_gte",
+    "_lte",
+    "_gt",
+    "_lt",
+    "_in",
+];
+
+/// Run a graphql query against the tables of the indexer.
+/// Return the response body: `data` if the query succeeded, otherwise `errors`
+pub fn execute_query(
+    store: &PostgresIndexStore,
+    query: &str,
+    variables: &serde_json::Map<String, serde_json::Value>,
+) -> serde_json::Value {
+    let result = store.get_conn().map_err(Error::from).and_then(|conn| {
+        let resolver = QueryResolver {
+            store,
+            conn: &conn,
+            variables,
+        };
+        resolver.resolve(query)
+    });
+    match result {
+        Ok(data) => json!({ "data": data }),
+        Err(err) => json!({ "errors": [{ "message": err.to_string() }] }),
+    }
+}
+
+/// Entities whose changes can modify the result of the query: the entity type of each root field
+/// with its `id` argument if any, and the types of the nested selections with any id
+pub fn watched_entities(
+    store: &PostgresIndexStore,
+    query: &str,
+    variables: &serde_json::Map<String, serde_json::Value>,
+) -> Result<Vec<(String, Option<String>)>, Error> {
+    let conn = store.get_conn()?;
+    let resolver = QueryResolver {
+        store,
+        conn: &conn,
+        variables,
+    };
+    let document = q::parse_query(query).map_err(|e| anyhow!("Invalid query: {}", e))?;
+    let mut entities = Vec::new();
+    for field in get_fields(get_selection_set(&document)?)? {
+        if let Some(object_type) = resolver.get_fulltext_type(&field.name) {
+            entities.push((object_type.name.clone(), None));
+            resolver.collect_nested_types(object_type, &field.selection_set, &mut entities)?;
+            continue;
+        }
+        for object_type in get_object_types(&store.schema.document) {
+            let single_name = object_type.name.to_camel_case();
+            if field.name == single_name {
+                let id = match resolver.get_argument(field, "id")? {
+                    Some(q::Value::String(id)) => Some(id),
+                    _ => None,
+                };
+                entities.push((object_type.name.clone(), id));
+            } else if field.name == single_name.to_plural() {
+                entities.push((object_type.name.clone(), None));
+            } else {
+                continue;
+            }
+            resolver.collect_nested_types(object_type, &field.selection_set, &mut entities)?;
+        }
+    }
+    Ok(entities)
+}
+
+fn get_selection_set(document: &q::Document) -> Result<&q::SelectionSet, Error> {
+    if document
+        .definitions
+        .iter()
+        .any(|definition| matches!(definition, q::Definition::Fragment(_)))
+    {
+        return Err(anyhow!("Fragments are not supported"));
+    }
+    document
+        .definitions
+        .iter()
+        .find_map(|definition| match definition {
+            q::Definition::Operation(q::OperationDefinition::Query(query)) => {
+                Some(&query.selection_set)
+            }
+            q::Definition::Operation(q::OperationDefinition::Subscription(subscription)) => {
+                Some(&subscription.selection_set)
+            }
+            q::Definition::Operation(q::OperationDefinition::SelectionSet(selection_set)) => {
+                Some(selection_set)
+            }
+            _ => None,
+        })
+        .ok_or_else(|| anyhow!("The document does not contain a query"))
+}
+
+struct QueryResolver<'a> {
+    store: &'a PostgresIndexStore,
+    conn: &'a PgConnection,
+    variables: &'a serde_json::Map<String, serde_json::Value>,
+}
+
+impl<'a> QueryResolver<'a> {
+    fn resolve(&self, query: &str) -> Result<serde_json::Value, Error> {
+        let document = q::parse_query(query).map_err(|e| anyhow!("Invalid query: {}", e))?;
+        let selection_set = get_selection_set(&document)?;
+        check_limits(
+            &self.store.schema.document,
+            selection_set,
+            self.variables,
+            *GRAPHQL_MAX_DEPTH,
+            *GRAPHQL_MAX_COMPLEXITY,
+        )?;
+        let mut data = serde_json::Map::new();
+        for field in get_fields(selection_set)? {
+            let key = field.alias.clone().unwrap_or(field.name.clone());
+            let value = if field.name == "__typename" {
+                json!("Query")
+            } else {
+                self.resolve_root_field(field)?
+            };
+            data.insert(key, value);
+        }
+        Ok(serde_json::Value::Object(data))
+    }
+
+    /// `token` returns the entity Token with the given id, `tokens` the list of the matching ones
+    fn resolve_root_field(&self, field: &q::Field) -> Result<serde_json::Value, Error> {
+        let block = self.get_block(field)?;
+        if let Some(object_type) = self.get_fulltext_type(&field.name) {
+            let text = match self.get_argument(field, "text")? {
+                Some(q::Value::String(text)) => text,
+                _ => return Err(anyhow!("Field {} requires a string text", &field.name)),
+            };
+            let search = EntityFilter::Equal(field.name.clone(), Value::String(text));
+            return self.resolve_list(object_type, field, block, Some(search));
+        }
+        for object_type in get_object_types(&self.store.schema.document) {
+            let single_name = object_type.name.to_camel_case();
+            if field.name == single_name {
+                let id = match self.get_argument(field, "id")? {
+                    Some(q::Value::String(id)) => id,
+                    _ => return Err(anyhow!("Field {} requires a string id", &field.name)),
+                };
+                return self.resolve_reference(object_type, &id, &field.selection_set, block);
+            }
+            if field.name == single_name.to_plural() {
+                return self.resolve_list(object_type, field, block, None);
+            }
+        }
+        Err(anyhow!("Query has no field {}", &field.name))
+    }
+
+    /// Entity type searched by the full text field `name`
+    fn get_fulltext_type(&self, name: &str) -> Option<&s::ObjectType> {
+        fulltext_fields(&self.store.schema.document)
+            .into_iter()
+            .find(|(fulltext, _)| fulltext == name)
+            .and_then(|(_, entity)| get_object_type(&self.store.schema.document, &entity))
+    }
+
+    fn collect_nested_types(
+        &self,
+        object_type: &s::ObjectType,
+        selection_set: &q::SelectionSet,
+        entities: &mut Vec<(String, Option<String>)>,
+    ) -> Result<(), Error> {
+        for field in get_fields(selection_set)? {
+            if field.name == "__typename" {
+                continue;
+            }
+            let definition = get_field(object_type, &field.name).ok_or_else(|| {
+                anyhow!("Type {} has no field {}", &object_type.name, &field.name)
+            })?;
+            // The ids of a derived field change with the entities referencing this one
+            if field.selection_set.items.is_empty() && derived_from(definition).is_none() {
+                continue;
+            }
+            let reference_type = match get_object_type(
+                &self.store.schema.document,
+                named_type(&definition.field_type),
+            ) {
+                Some(reference_type) => reference_type,
+                None => continue,
+            };
+            entities.push((reference_type.name.clone(), None));
+            self.collect_nested_types(reference_type, &field.selection_set, entities)?;
+        }
+        Ok(())
+    }
+
+    /// Block of the `block` argument, the nested selections are resolved at the same block
+    fn get_block(&self, field: &q::Field) -> Result<BlockNumber, Error> {
+        let (number, hash) = match self.get_argument(field, "block")? {
+            None => (None, None),
+            Some(q::Value::Object(block)) => match (block.get("number"), block.get("hash")) {
+                (Some(q::Value::Int(number)), None) => {
+                    let number = number
+                        .as_i64()
+                        .filter(|number| *number >= 0 && *number <= i32::MAX as i64)
+                        .ok_or_else(|| anyhow!("Invalid block number"))?;
+                    (Some(number as BlockNumber), None)
+                }
+                (None, Some(q::Value::String(hash))) => (None, Some(hash.clone())),
+                _ => {
+                    return Err(anyhow!(
+                        "The value of block must be either {{ number: Int }} or {{ hash: String }}"
+                    ))
+                }
+            },
+            Some(_) => return Err(anyhow!("The value of block must be an object")),
+        };
+        Ok(self
+            .store
+            .resolve_block(self.conn, number, hash.as_deref())?)
+    }
+
+    fn resolve_list(
+        &self,
+        object_type: &s::ObjectType,
+        field: &q::Field,
+        block: BlockNumber,
+        search: Option<EntityFilter>,
+    ) -> Result<serde_json::Value, Error> {
+        let filter = match (self.get_argument(field, "where")?, search) {
+            (Some(value), Some(search)) => Some(EntityFilter::And(vec![
+                search,
+                self.build_filter(object_type, &value)?,
+            ])),
+            (Some(value), None) => Some(self.build_filter(object_type, &value)?),
+            (None, search) => search,
+        };
+        let order = self.build_order(object_type, field)?;
+        let range = self.build_range(field)?;
+        let entities = self.store.layout.filter::<Entity>(
+            &self.store.logger,
+            self.conn,
+            EntityType::new(object_type.name.clone()),
+            filter,
+            order,
+            range,
+            block,
+        )?;
+        let values = self.render_entities(object_type, &entities, &field.selection_set, block)?;
+        Ok(serde_json::Value::Array(values))
+    }
+
+    fn resolve_reference(
+        &self,
+        object_type: &s::ObjectType,
+        id: &str,
+        selection_set: &q::SelectionSet,
+        block: BlockNumber,
+    ) -> Result<serde_json::Value, Error> {
+        let entity = self.store.layout.find(
+            self.conn,
+            &EntityType::new(object_type.name.clone()),
+            id,
+            block,
+        )?;
+        match entity {
+            Some(entity) => Ok(self
+                .render_entities(object_type, &[entity], selection_set, block)?
+                .pop()
+                .unwrap_or(serde_json::Value::Null)),
+            None => Ok(serde_json::Value::Null),
+        }
+    }
+
+    /// Render the entities of a level together, the entities of their nested selections
+    /// are loaded for all of them and rendered as the next level
+    fn render_entities(
+        &self,
+        object_type: &s::ObjectType,
+        entities: &[Entity],
+        selection_set: &q::SelectionSet,
+        block: BlockNumber,
+    ) -> Result<Vec<serde_json::Value>, Error> {
+        let mut results = vec![serde_json::Map::new(); entities.len()];
+        for field in get_fields(selection_set)? {
+            let key = field.alias.clone().unwrap_or(field.name.clone());
+            if field.name == "__typename" {
+                for result in results.iter_mut() {
+                    result.insert(key.clone(), json!(&object_type.name));
+                }
+                continue;
+            }
+            let field_definition = get_field(object_type, &field.name).ok_or_else(|| {
+                anyhow!("Type {} has no field {}", &object_type.name, &field.name)
+            })?;
+            let reference_type = get_object_type(
+                &self.store.schema.document,
+                named_type(&field_definition.field_type),
+            );
+            let values = match (reference_type, derived_from(field_definition)) {
+                // A derived field is resolved by looking up the entities which reference these ones
+                (Some(reference_type), Some(source_field)) => self.resolve_derived(
+                    reference_type,
+                    source_field,
+                    entities,
+                    field_definition,
+                    field,
+                    block,
+                )?,
+                // A nested selection loads the referenced entities
+                (Some(reference_type), None) if !field.selection_set.items.is_empty() => {
+                    self.resolve_references(reference_type, entities, field, block)?
+                }
+                _ => entities
+                    .iter()
+                    .map(|entity| to_json(entity.get(&field.name).unwrap_or(&Value::Null)))
+                    .collect(),
+            };
+            for (result, value) in results.iter_mut().zip(values) {
+                result.insert(key.clone(), value);
+            }
+        }
+        Ok(results.into_iter().map(serde_json::Value::Object).collect())
+    }
+
+    /// Entities referenced by the field `field` of the entities, loaded with one query
+    fn resolve_references(
+        &self,
+        reference_type: &s::ObjectType,
+        entities: &[Entity],
+        field: &q::Field,
+        block: BlockNumber,
+    ) -> Result<Vec<serde_json::Value>, Error> {
+        let mut ids: Vec<String> = entities
+            .iter()
+            .flat_map(|entity| reference_ids(entity.get(&field.name).unwrap_or(&Value::Null)))
+            .collect();
+        ids.sort();
+        ids.dedup();
+        let mut rendered = HashMap::new();
+        if !ids.is_empty() {
+            let entity_type = EntityType::new(reference_type.name.clone());
+            let mut ids_for_type = BTreeMap::new();
+            ids_for_type.insert(&entity_type, ids.iter().map(String::as_str).collect());
+            let references = self
+                .store
+                .layout
+                .find_many(self.conn, ids_for_type, block)?
+                .remove(&entity_type)
+                .unwrap_or_default();
+            let values =
+                self.render_entities(reference_type, &references, &field.selection_set, block)?;
+            for (reference, value) in references.iter().zip(values) {
+                for id in reference_ids(reference.get("id").unwrap_or(&Value::Null)) {
+                    rendered.insert(id, value.clone());
+                }
+            }
+        }
+        let render = |id: &Value| {
+            reference_ids(id)
+                .pop()
+                .and_then(|id| rendered.get(&id).cloned())
+                .unwrap_or(serde_json::Value::Null)
+        };
+        Ok(entities
+            .iter()
+            .map(
+                |entity| match entity.get(&field.name).unwrap_or(&Value::Null) {
+                    Value::List(ids) => serde_json::Value::Array(ids.iter().map(render).collect()),
+                    id => render(id),
+                },
+            )
+            .collect())
+    }
+
+    /// Entities referencing the entities in the field a `@derivedFrom` field is derived from.
+    /// They are loaded with one query, `first`, `skip` and `orderBy` apply to the entities
+    /// referencing each one
+    fn resolve_derived(
+        &self,
+        reference_type: &s::ObjectType,
+        source_field: &str,
+        entities: &[Entity],
+        field_definition: &s::Field,
+        field: &q::Field,
+        block: BlockNumber,
+    ) -> Result<Vec<serde_json::Value>, Error> {
+        let parent_ids: Vec<String> = entities
+            .iter()
+            .flat_map(|entity| reference_ids(entity.get("id").unwrap_or(&Value::Null)))
+            .collect();
+        let mut children: HashMap<String, Vec<serde_json::Value>> = HashMap::new();
+        if !parent_ids.is_empty() {
+            let source_is_list = get_field(reference_type, source_field)
+                .map(|definition| is_list(&definition.field_type))
+                .unwrap_or_default();
+            let attribute = if source_is_list {
+                WindowAttribute::List(source_field.to_string())
+            } else {
+                WindowAttribute::Scalar(source_field.to_string())
+            };
+            let multiplicity = if is_list(&field_definition.field_type) {
+                ChildMultiplicity::Many
+            } else {
+                ChildMultiplicity::Single
+            };
+            let window = EntityWindow {
+                child_type: EntityType::new(reference_type.name.clone()),
+                ids: parent_ids.clone(),
+                link: EntityLink::Direct(attribute, multiplicity),
+                column_names: AttributeNames::All,
+            };
+            let references: Vec<Entity> = self.store.layout.filter_windows(
+                self.conn,
+                vec![window],
+                self.build_order(reference_type, field)?,
+                self.build_range(field)?,
+                block,
+            )?;
+            let values = if field.selection_set.items.is_empty() {
+                references
+                    .iter()
+                    .map(|reference| to_json(reference.get("id").unwrap_or(&Value::Null)))
+                    .collect()
+            } else {
+                self.render_entities(reference_type, &references, &field.selection_set, block)?
+            };
+            let parent_ids: HashSet<&String> = parent_ids.iter().collect();
+            for (reference, value) in references.iter().zip(values) {
+                // Each row of the window query is for one parent, without its id the parents
+                // are taken from the reference field
+                let parents = match reference.get(PARENT_ID) {
+                    Some(parent) => reference_ids(parent),
+                    None => reference_ids(reference.get(source_field).unwrap_or(&Value::Null)),
+                };
+                for parent in parents {
+                    if parent_ids.contains(&parent) {
+                        let siblings = children.entry(parent).or_default();
+                        if !siblings.contains(&value) {
+                            siblings.push(value.clone());
+                        }
+                    }
+                }
+            }
+        }
+        Ok(entities
+            .iter()
+            .map(|entity| {
+                let values = reference_ids(entity.get("id").unwrap_or(&Value::Null))
+                    .pop()
+                    .and_then(|id| children.remove(&id))
+                    .unwrap_or_default();
+                if is_list(&field_definition.field_type) {
+                    serde_json::Value::Array(values)
+                } else {
+                    values.into_iter().next().unwrap_or(serde_json::Value::Null)
+                }
+            })
+            .collect())
+    }
+
+    /// Each key of `where` is a field, optionally followed by the filter: `name_not`, `amount_gt`, `symbol_in`...
+    fn build_filter(
+        &self,
+        object_type: &s::ObjectType,
+        value: &q::Value,
+    ) -> Result<EntityFilter, Error> {
+        let conditions = match value {
+            q::Value::Object(conditions) => conditions,
+            _ => return Err(anyhow!("The value of where must be an object")),
+        };
+        let mut filters = Vec::new();
+        for (key, value) in conditions {
+            let (field_name, suffix) = FILTER_SUFFIXES
+                .iter()
+                .find_map(|suffix| {
+                    key.strip_suffix(suffix)
+                        .filter(|name| get_field(object_type, name).is_some())
+                        .map(|name| (name, *suffix))
+                })
+                .unwrap_or((key.as_str(), ""));
+            let field = get_field(object_type, field_name)
+                .ok_or_else(|| anyhow!("Type {} has no field {}", &object_type.name, field_name))?;
+            let attribute = field_name.to_string();
+            let filter = match suffix {
+                "_in" | "_not_in" => {
+                    let values = match value {
+                        q::Value::List(values) => values
+                            .iter()
+                            .map(|value| self.to_store_value(named_type(&field.field_type), value))
+                            .collect::<Result<Vec<_>, _>>()?,
+                        _ => return Err(anyhow!("The value of {} must be a list", key)),
+                    };
+                    if suffix == "_in" {
+                        EntityFilter::In(attribute, values)
+                    } else {
+                        EntityFilter::NotIn(attribute, values)
+                    }
+                }
+                _ => {
+                    let value = match value {
+                        q::Value::List(values) => Value::List(
+                            values
+                                .iter()
+                                .map(|value| {
+                                    self.to_store_value(named_type(&field.field_type), value)
+                                })
+                                .collect::<Result<Vec<_>, _>>()?,
+                        ),
+                        value => self.to_store_value(named_type(&field.field_type), value)?,
+                    };
+                    match suffix {
+                        "_not" => EntityFilter::Not(attribute, value),
+                        "_gt" => EntityFilter::GreaterThan(attribute, value),
+                        "_lt" => EntityFilter::LessThan(attribute, value),
+                        "_gte" => EntityFilter::GreaterOrEqual(attribute, value),
+                        "_lte" => EntityFilter::LessOrEqual(attribute, value),
+                        "_contains" => EntityFilter::Contains(attribute, value),
+                        "_not_contains" => EntityFilter::NotContains(attribute, value),
+                        "_starts_with" => EntityFilter::StartsWith(attribute, value),
+                        "_not_starts_with" => EntityFilter::NotStartsWith(attribute, value),
+                        "_ends_with" => EntityFilter::EndsWith(attribute, value),
+                        "_not_ends_with" => EntityFilter::NotEndsWith(attribute, value),
+                        _ => EntityFilter::Equal(attribute, value),
+                    }
+                }
+            };
+            filters.push(filter);
+        }
+        Ok(EntityFilter::And(filters))
+    }
+
+    fn build_order(
+        &self,
+        object_type: &s::ObjectType,
+        field: &q::Field,
+    ) -> Result<EntityOrder, Error> {
+        let order_by = match self.get_argument(field, "orderBy")? {
+            Some(q::Value::Enum(name)) | Some(q::Value::String(name)) => name,
+            Some(_) => return Err(anyhow!("The value of orderBy must be a field name")),
+            None => return Ok(EntityOrder::Default),
+        };
+        let order_field = get_field(object_type, &order_by)
+            .ok_or_else(|| anyhow!("Type {} has no field {}", &object_type.name, &order_by))?;
+        if is_list(&order_field.field_type) {
+            return Err(anyhow!("Can not order by the list field {}", &order_by));
+        }
+        // Enums and references are stored as strings
+        let value_type =
+            ValueType::from_str(named_type(&order_field.field_type)).unwrap_or(ValueType::String);
+        let direction = match self.get_argument(field, "orderDirection")? {
+            Some(q::Value::Enum(direction)) | Some(q::Value::String(direction)) => direction,
+            _ => String::from("asc"),
+        };
+        match direction.as_str() {
+            "asc" => Ok(EntityOrder::Ascending(order_by, value_type)),
+            "desc" => Ok(EntityOrder::Descending(order_by, value_type)),
+            _ => Err(anyhow!("The value of orderDirection must be asc or desc")),
+        }
+    }
+
+    /// `first` (100 by default) and `skip` arguments of a list
+    fn build_range(&self, field: &q::Field) -> Result<EntityRange, Error> {
+        let first = range_argument(self.get_argument(field, "first")?, "first", MAX_FIRST)?
+            .unwrap_or(DEFAULT_FIRST);
+        let skip =
+            range_argument(self.get_argument(field, "skip")?, "skip", MAX_SKIP)?.unwrap_or(0);
+        Ok(EntityRange {
+            first: Some(first),
+            skip,
+        })
+    }
+
+    /// Value of the argument with the variables replaced by their value
+    fn get_argument(&self, field: &q::Field, name: &str) -> Result<Option<q::Value>, Error> {
+        match field.arguments.iter().find(|(key, _)| key == name) {
+            Some((_, value)) => self.resolve_variables(value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn resolve_variables(&self, value: &q::Value) -> Result<q::Value, Error> {
+        match value {
+            q::Value::Variable(name) => match self.variables.get(name) {
+                Some(value) => Ok(from_json(value)),
+                None => Err(anyhow!("Variable ${} is not defined", name)),
+            },
+            q::Value::List(values) => Ok(q::Value::List(
+                values
+                    .iter()
+                    .map(|value| self.resolve_variables(value))
+                    .collect::<Result<Vec<_>, _>>()?,
+            )),
+            q::Value::Object(values) => Ok(q::Value::Object(
+                values
+                    .iter()
+                    .map(|(key, value)| Ok((key.clone(), self.resolve_variables(value)?)))
+                    .collect::<Result<BTreeMap<_, _>, Error>>()?,
+            )),
+            value => Ok(value.clone()),
+        }
+    }
+
+    /// Convert an argument to the value stored for a field of type `type_name`
+    fn to_store_value(&self, type_name: &str, value: &q::Value) -> Result<Value, Error> {
+        let invalid = || anyhow!("Invalid value {} for type {}", value, type_name);
+        match (type_name, value) {
+            (_, q::Value::Null) => Ok(Value::Null),
+            ("Int", q::Value::Int(number)) => {
+                Ok(Value::Int(number.as_i64().ok_or_else(invalid)? as i32))
+            }
+            ("BigInt", q::Value::Int(number)) => Ok(Value::BigInt(BigInt::from(
+                number.as_i64().ok_or_else(invalid)?,
+            ))),
+            ("BigInt", q::Value::String(number)) => Ok(Value::BigInt(
+                BigInt::from_str(number).map_err(|_| invalid())?,
+            )),
+            ("BigDecimal", q::Value::Int(number)) => Ok(Value::BigDecimal(BigDecimal::from(
+                number.as_i64().ok_or_else(invalid)?,
+            ))),
+            ("BigDecimal", q::Value::Float(number)) => {
+                Ok(Value::BigDecimal(BigDecimal::from(*number)))
+            }
+            ("BigDecimal", q::Value::String(number)) => Ok(Value::BigDecimal(
+                BigDecimal::from_str(number).map_err(|_| invalid())?,
+            )),
+            ("Boolean", q::Value::Boolean(value)) => Ok(Value::Bool(*value)),
+            ("Bytes", q::Value::String(value)) => {
+                Ok(Value::Bytes(Bytes::from_str(value).map_err(|_| invalid())?))
+            }
+            ("Int", _) | ("BigInt", _) | ("BigDecimal", _) | ("Boolean", _) | ("Bytes", _) => {
+                Err(invalid())
+            }
+            // String, ID, enums and references
+            (_, q::Value::String(value)) | (_, q::Value::Enum(value)) => {
+                Ok(Value::String(value.clone()))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Reject a query with too many nested selections, or which may load too many entities:
+/// a list counts for `first` entities (100 by default), each with its own nested selections
+fn check_limits(
+    schema: &s::Document,
+    selection_set: &q::SelectionSet,
+    variables: &serde_json::Map<String, serde_json::Value>,
+    max_depth: usize,
+    max_complexity: u64,
+) -> Result<(), Error> {
+    let mut complexity: u64 = 0;
+    for field in get_fields(selection_set)? {
+        let fulltext_type = fulltext_fields(schema)
+            .into_iter()
+            .find(|(fulltext, _)| fulltext == &field.name)
+            .and_then(|(_, entity)| get_object_type(schema, &entity));
+        let root = fulltext_type
+            .map(|object_type| (object_type, true))
+            .or_else(|| {
+                get_object_types(schema).find_map(|object_type| {
+                    let single_name = object_type.name.to_camel_case();
+                    if field.name == single_name {
+                        Some((object_type, false))
+                    } else if field.name == single_name.to_plural() {
+                        Some((object_type, true))
+                    } else {
+                        None
+                    }
+                })
+            });
+        if let Some((object_type, list)) = root {
+            let count = if list { get_first(field, variables) } else { 1 };
+            let nested = selection_complexity(
+                schema,
+                object_type,
+                &field.selection_set,
+                variables,
+                2,
+                max_depth,
+            )?;
+            complexity = complexity.saturating_add(count.saturating_mul(nested.saturating_add(1)));
+        }
+    }
+    if complexity > max_complexity {
+        return Err(anyhow!(
+            "Query may load {} entities, the limit is {}",
+            complexity,
+            max_complexity
+        ));
+    }
+    Ok(())
+}
+
+/// Number of entities loaded by the nested selections of one entity
+fn selection_complexity(
+    schema: &s::Document,
+    object_type: &s::ObjectType,
+    selection_set: &q::SelectionSet,
+    variables: &serde_json::Map<String, serde_json::Value>,
+    depth: usize,
+    max_depth: usize,
+) -> Result<u64, Error> {
+    let mut complexity: u64 = 0;
+    for field in get_fields(selection_set)? {
+        let definition = match get_field(object_type, &field.name) {
+            Some(definition) => definition,
+            None => continue,
+        };
+        let reference_type = match get_object_type(schema, named_type(&definition.field_type)) {
+            Some(reference_type) => reference_type,
+            None => continue,
+        };
+        let derived = derived_from(definition).is_some();
+        if field.selection_set.items.is_empty() && !derived {
+            continue;
+        }
+        if depth > max_depth {
+            return Err(anyhow!(
+                "Query has more than {} levels of nested selections",
+                max_depth
+            ));
+        }
+        let count = if derived || is_list(&definition.field_type) {
+            get_first(field, variables)
+        } else {
+            1
+        };
+        let nested = selection_complexity(
+            schema,
+            reference_type,
+            &field.selection_set,
+            variables,
+            depth + 1,
+            max_depth,
+        )?;
+        complexity = complexity.saturating_add(count.saturating_mul(nested.saturating_add(1)));
+    }
+    Ok(complexity)
+}
+
+/// Value of the `first` argument, or the number of entities returned without it
+fn get_first(field: &q::Field, variables: &serde_json::Map<String, serde_json::Value>) -> u64 {
+    let first = match field.arguments.iter().find(|(key, _)| key == "first") {
+        Some((_, q::Value::Int(first))) => first.as_i64().map(|first| first as u64),
+        Some((_, q::Value::Variable(name))) => variables.get(name).and_then(|first| first.as_u64()),
+        _ => None,
+    };
+    first.unwrap_or(DEFAULT_FIRST as u64)
+}
+
+/// Value of `first` or `skip`, which must be an integer between 0 and `max`
+fn range_argument(value: Option<q::Value>, name: &str, max: u32) -> Result<Option<u32>, Error> {
+    let number = match value {
+        None | Some(q::Value::Null) => return Ok(None),
+        Some(q::Value::Int(number)) => number.as_i64(),
+        Some(_) => None,
+    };
+    match number {
+        Some(number) if number >= 0 && number <= max as i64 => Ok(Some(number as u32)),
+        _ => Err(anyhow!(
+            "The value of {} must be an integer between 0 and {}",
+            name,
+            max
+        )),
+    }
+}
+
+/// Ids in a reference field, or in a list of references
+fn reference_ids(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(id) => vec![id.clone()],
+        Value::Bytes(id) => vec![id.to_string()],
+        Value::List(values) => values.iter().flat_map(reference_ids).collect(),
+        _ => vec![],
+    }
+}
+
+fn get_fields(selection_set: &q::SelectionSet) -> Result<Vec<&q::Field>, Error> {
+    selection_set
+        .items
+        .iter()
+        .map(|selection| match selection {
+            q::Selection::Field(field) => Ok(field),
+            _ => Err(anyhow!("Fragments are not supported")),
+        })
+        .collect()
+}
+
+fn get_object_types(document: &s::Document) -> impl Iterator<Item = &s::ObjectType> {
+    document
+        .definitions
+        .iter()
+        .filter_map(|definition| match definition {
+            s::Definition::TypeDefinition(s::TypeDefinition::Object(object_type))
+                if object_type.name != SCHEMA_TYPE_NAME =>
+            {
+                Some(object_type)
+            }
+            _ => None,
+        })
+}
+
+fn get_object_type<'a>(document: &'a s::Document, name: &str) -> Option<&'a s::ObjectType> {
+    get_object_types(document).find(|object_type| object_type.name == name)
+}
+
+fn get_field<'a>(object_type: &'a s::ObjectType, name: &str) -> Option<&'a s::Field> {
+    object_type.fields.iter().find(|field| field.name == name)
+}
+
+fn named_type(field_type: &s::Type) -> &str {
+    match field_type {
+        s::Type::NamedType(name) => name.as_str(),
+        s::Type::ListType(child) => named_type(child),
+        s::Type::NonNullType(child) => named_type(child),
+    }
+}
+
+fn is_list(field_type: &s::Type) -> bool {
+    match field_type {
+        s::Type::NamedType(_) => false,
+        s::Type::ListType(_) => true,
+        s::Type::NonNullType(child) => is_list(child),
+    }
+}
+
+/// BigInt and BigDecimal are returned as strings so they don't lose precision
+fn to_json(value: &Value) -> serde_json::Value {
+    match value {
+        Value::String(value) => json!(value),
+        Value::Int(value) => json!(value),
+        Value::BigDecimal(value) => json!(value.to_string()),
+        Value::Bool(value) => json!(value),
+        Value::List(values) => serde_json::Value::Array(values.iter().map(to_json).collect()),
+        Value::Null => serde_json::Value::Null,
+        Value::Bytes(value) => json!(value.to_string()),
+        Value::BigInt(value) => json!(value.to_string()),
+    }
+}
+
+fn from_json(value: &serde_json::Value) -> q::Value {
+    match value {
+        serde_json::Value::Null => q::Value::Null,
+        serde_json::Value::Bool(value) => q::Value::Boolean(*value),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(value) if value >= i32::MIN as i64 && value <= i32::MAX as i64 => {
+                q::Value::Int(q::Number::from(value as i32))
+            }
+            _ => q::Value::Float(number.as_f64().unwrap_or_default()),
+        },
+        serde_json::Value::String(value) => q::Value::String(value.clone()),
+        serde_json::Value::Array(values) => q::Value::List(values.iter().map(from_json).collect()),
+        serde_json::Value::Object(values) => q::Value::Object(
+            values
+                .iter()
+                .map(|(key, value)| (key.clone(), from_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::IndexStore;
+    use crate::postgres::{checkpoint, metadata, store_builder::StoreBuilder};
+    use graph::blockchain::BlockHash;
+    use graph::components::metrics::stopwatch::StopwatchMetrics;
+    use graph::components::store::{EntityKey, EntityModification};
+    use graph::data::schema::Schema;
+    use graph::prelude::{BlockPtr, DeploymentHash};
+    use graph_mock::MockMetricsRegistry;
+    use massbit_common::prelude::slog::{self, Logger};
+    use std::fs;
+    use std::sync::Arc;
+
+    const SCHEMA: &str = "
+        type Account @entity { id: ID! tokens: [Token!]! @derivedFrom(field: \"owner\") }
+        type Token @entity {
+            id: ID!
+            owner: Account!
+            transfers: [Transfer!]! @derivedFrom(field: \"token\")
+        }
+        type Transfer @entity { id: ID! token: Token! }
+    ";
+
+    fn schema() -> s::Document {
+        Schema::parse(SCHEMA, DeploymentHash::new("indexer").unwrap())
+            .unwrap()
+            .document
+    }
+
+    fn check(query: &str, max_depth: usize, max_complexity: u64) -> Result<(), Error> {
+        let document = q::parse_query(query).unwrap();
+        check_limits(
+            &schema(),
+            get_selection_set(&document)?,
+            &serde_json::Map::new(),
+            max_depth,
+            max_complexity,
+        )
+    }
+
+    #[test]
+    fn query_deeper_than_the_max_depth_is_rejected() {
+        let query = "{ tokens { owner { tokens { id } } } }";
+        assert!(check(query, 3, u64::MAX).is_ok());
+        let err = check(query, 2, u64::MAX).unwrap_err();
+        assert!(err.to_string().contains("levels of nested selections"));
+    }
+
+    #[test]
+    fn nested_lists_multiply_the_complexity() {
+        // 10 tokens, each with its owner and 5 transfers
+        let query = "{ tokens(first: 10) { id owner { id } transfers(first: 5) { id } } }";
+        assert!(check(query, 10, 70).is_ok());
+        let err = check(query, 10, 69).unwrap_err();
+        assert!(err.to_string().contains("may load 70 entities"));
+    }
+
+    #[test]
+    fn first_is_read_from_the_variables() {
+        let document = q::parse_query("query($n: Int) { tokens(first: $n) { id } }").unwrap();
+        let mut variables = serde_json::Map::new();
+        variables.insert("n".to_string(), json!(500));
+        let selection_set = get_selection_set(&document).unwrap();
+        assert!(check_limits(&schema(), selection_set, &variables, 10, 500).is_ok());
+        assert!(check_limits(&schema(), selection_set, &variables, 10, 499).is_err());
+    }
+
+    #[test]
+    fn first_and_skip_must_be_in_range() {
+        let int = |number: i32| Some(q::Value::Int(number.into()));
+        assert_eq!(range_argument(None, "first", MAX_FIRST).unwrap(), None);
+        assert_eq!(range_argument(int(0), "skip", MAX_SKIP).unwrap(), Some(0));
+        assert_eq!(
+            range_argument(int(1000), "first", MAX_FIRST).unwrap(),
+            Some(1000)
+        );
+        assert!(range_argument(int(1001), "first", MAX_FIRST).is_err());
+        assert!(range_argument(int(5001), "skip", MAX_SKIP).is_err());
+        let err = range_argument(int(-1), "skip", MAX_SKIP).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The value of skip must be an integer between 0 and 5000"
+        );
+    }
+
+    #[test]
+    fn fragments_are_rejected() {
+        let document =
+            q::parse_query("{ tokens { ...TokenFields } } fragment TokenFields on Token { id }")
+                .unwrap();
+        let err = get_selection_set(&document).unwrap_err();
+        assert_eq!(err.to_string(), "Fragments are not supported");
+    }
+
+    fn modification(entity_type: &str, id: &str, fields: &[(&str, &str)]) -> EntityModification {
+        let mut data = Entity::new();
+        data.set("id", id);
+        for (name, value) in fields {
+            data.set(*name, *value);
+        }
+        EntityModification::Insert {
+            key: EntityKey::data(
+                DeploymentHash::new("indexer").unwrap(),
+                entity_type.to_string(),
+                id.to_string(),
+            ),
+            data,
+        }
+    }
+
+    // Needs the database of DATABASE_CONNECTION_STRING
+    #[test]
+    #[ignore]
+    fn nested_references_and_derived_fields_are_resolved() {
+        let indexer = "index_store_graphql_test";
+        let schema_path = std::env::temp_dir().join("index_store_graphql_test.graphql");
+        fs::write(&schema_path, SCHEMA).unwrap();
+        let store = StoreBuilder::create_store(indexer, "sgd999998", &schema_path)
+            .unwrap()
+            .for_stream("stream");
+        let conn = store.get_conn().unwrap();
+        metadata::run_migrations(&conn).unwrap();
+        checkpoint::remove_block_ptr(&conn, indexer).unwrap();
+        store.revert_entities(&conn, 0).unwrap();
+        let stopwatch = StopwatchMetrics::new(
+            Logger::root(slog::Discard, slog::o!()),
+            DeploymentHash::new("indexer").unwrap(),
+            Arc::new(MockMetricsRegistry::new()),
+        );
+        store
+            .transact_block_operations(
+                BlockPtr {
+                    hash: BlockHash::from(vec![1u8; 32]),
+                    number: 1,
+                },
+                vec![
+                    modification("Account", "alice", &[]),
+                    modification("Account", "bob", &[]),
+                    modification("Token", "t1", &[("owner", "alice")]),
+                    modification("Token", "t2", &[("owner", "bob")]),
+                    modification("Transfer", "x1", &[("token", "t1")]),
+                    modification("Transfer", "x2", &[("token", "t1")]),
+                    modification("Transfer", "x3", &[("token", "t2")]),
+                ],
+                stopwatch,
+                vec![],
+                vec![],
+            )
+            .unwrap();
+        store.flush_batch().unwrap();
+
+        let query = "{ tokens(orderBy: id) {
+            id
+            owner { id tokens { id } }
+            transfers(orderBy: id) { id }
+        } }";
+        let result = execute_query(&store, query, &serde_json::Map::new());
+        assert_eq!(
+            result,
+            json!({ "data": { "tokens": [
+                {
+                    "id": "t1",
+                    "owner": { "id": "alice", "tokens": [{ "id": "t1" }] },
+                    "transfers": [{ "id": "x1" }, { "id": "x2" }]
+                },
+                {
+                    "id": "t2",
+                    "owner": { "id": "bob", "tokens": [{ "id": "t2" }] },
+                    "transfers": [{ "id": "x3" }]
+                }
+            ] } })
+        );
+    }
+}
@@ -0,0 +1,33 @@
+use diesel::connection::SimpleConnection;
+use graph::prelude::StoreError;
+use massbit_common::prelude::anyhow::anyhow;
+use massbit_common::prelude::diesel::PgConnection;
+
+/// Key of the advisory lock taken while migrating the metadata tables,
+/// so the processes starting together don't apply the same migration twice
+const LOCK_MIGRATIONS: &str = "select pg_advisory_lock(20210801)";
+const UNLOCK_MIGRATIONS: &str = "select pg_advisory_unlock(20210801)";
+
+embed_migrations!("migrations");
+
+/// Create or upgrade the tables of the index store which are not specific to an indexer: checkpoints,
+/// failures and block hashes. The applied versions are kept in `__diesel_schema_migrations`.
+pub fn run_migrations(conn: &PgConnection) -> Result<(), StoreError> {
+    with_migration_lock(conn, || {
+        embedded_migrations::run(conn).map_err(|e| {
+            StoreError::Unknown(anyhow!("Cannot migrate the index store tables: {}", e))
+        })
+    })
+}
+
+/// Run `migrate` while holding the migration lock, it is reentrant for the same connection
+pub fn with_migration_lock<T, E, F>(conn: &PgConnection, migrate: F) -> Result<T, E>
+where
+    F: FnOnce() -> Result<T, E>,
+    E: From<diesel::result::Error>,
+{
+    conn.batch_execute(LOCK_MIGRATIONS)?;
+    let result = migrate();
+    conn.batch_execute(UNLOCK_MIGRATIONS)?;
+    result
+}
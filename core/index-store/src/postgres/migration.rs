@@ -0,0 +1,419 @@
+use super::directives::directive_indexes;
+use diesel::sql_types::{Bool, Text};
+use diesel::QueryableByName;
+use graph::data::schema::Schema;
+use graph::prelude::StoreError;
+use graph_store_postgres::relational::{Column, Layout, Table};
+use inflector::Inflector;
+use massbit_common::prelude::anyhow::anyhow;
+use massbit_common::prelude::diesel::{sql_query, PgConnection, RunQueryDsl};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+
+/// Columns added by graph to every table, they are not fields of the schema
+const SYSTEM_COLUMNS: [&str; 2] = ["vid", "block_range"];
+
+#[derive(Debug, Clone, QueryableByName)]
+struct ExistingColumn {
+    #[sql_type = "Text"]
+    pub table_name: String,
+    #[sql_type = "Text"]
+    pub column_name: String,
+    #[sql_type = "Text"]
+    pub column_type: String,
+    #[sql_type = "Bool"]
+    pub not_null: bool,
+}
+
+#[derive(Debug, Clone, QueryableByName)]
+struct ExistingEnumValue {
+    #[sql_type = "Text"]
+    pub type_name: String,
+    #[sql_type = "Text"]
+    pub value: String,
+}
+
+/// Changes needed to bring the tables of a namespace to the layout of a new graphql schema.
+/// Only the changes which keep the existing data valid are applied, the others are `conflicts`.
+#[derive(Debug, Default)]
+pub struct SchemaMigration {
+    /// Sql statements applying the safe changes, in order
+    pub statements: Vec<String>,
+    /// Description of the safe changes
+    pub changes: Vec<String>,
+    /// Description of the changes which can not be applied to the existing data
+    pub conflicts: Vec<String>,
+}
+
+impl SchemaMigration {
+    /// Compare the layout of the schema with the tables and enums found in its namespace
+    pub fn plan(
+        conn: &PgConnection,
+        layout: &Layout,
+        schema: &Schema,
+    ) -> Result<SchemaMigration, StoreError> {
+        let namespace = layout.site.namespace.as_str();
+        let mut existing_tables: HashMap<String, HashMap<String, ExistingColumn>> = HashMap::new();
+        for column in load_columns(conn, namespace)? {
+            existing_tables
+                .entry(column.table_name.clone())
+                .or_default()
+                .insert(column.column_name.clone(), column);
+        }
+        let mut existing_enums: HashMap<String, BTreeSet<String>> = HashMap::new();
+        for value in load_enum_values(conn, namespace)? {
+            existing_enums
+                .entry(value.type_name)
+                .or_default()
+                .insert(value.value);
+        }
+        // Enums, tables and indexes missing from the namespace are created by the ddl of the layout,
+        // the statements are made idempotent so the existing ones are kept
+        let ddl = layout
+            .as_ddl()
+            .map_err(|_| StoreError::Unknown(anyhow!("failed to generate DDL for layout")))?;
+        let mut new_types = Vec::new();
+        let mut new_tables = Vec::new();
+        for statement in ddl.split(';') {
+            let statement = statement.trim();
+            if statement.starts_with("create type") {
+                // The values of the existing enums are added by plan_enums
+                let type_name = statement
+                    .split_whitespace()
+                    .nth(2)
+                    .map(normalize_type)
+                    .unwrap_or_default();
+                if !existing_enums.contains_key(&type_name) {
+                    new_types.push(statement.to_string());
+                }
+            } else if let Some(rest) = statement.strip_prefix("create table ") {
+                new_tables.push(format!("create table if not exists {}", rest));
+            } else if let Some(rest) = statement.strip_prefix("create index ") {
+                new_tables.push(format!("create index if not exists {}", rest));
+            } else if let Some(rest) = statement.strip_prefix("create unique index ") {
+                new_tables.push(format!("create unique index if not exists {}", rest));
+            } else if !statement.is_empty() {
+                new_tables.push(statement.to_string());
+            }
+        }
+        // The new columns may use the new enums, the new indexes the new columns
+        let mut migration = SchemaMigration {
+            statements: new_types,
+            ..Default::default()
+        };
+        migration.plan_enums(layout, &existing_enums);
+        migration.plan_tables(layout, &existing_tables);
+        migration.statements.extend(new_tables);
+        migration
+            .statements
+            .extend(directive_indexes(layout, schema)?);
+        Ok(migration)
+    }
+
+    pub fn is_compatible(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+
+    fn plan_enums(&mut self, layout: &Layout, existing_enums: &HashMap<String, BTreeSet<String>>) {
+        let namespace = layout.site.namespace.as_str();
+        for (name, values) in layout.enums.iter() {
+            // Enum types are named after the snake case of the graphql enum
+            let type_name = [name.to_lowercase(), name.to_snake_case()]
+                .iter()
+                .find(|type_name| existing_enums.contains_key(*type_name))
+                .cloned();
+            match type_name.and_then(|type_name| {
+                existing_enums
+                    .get(&type_name)
+                    .map(|existing_values| (type_name, existing_values))
+            }) {
+                None => self.changes.push(format!("create enum {}", name)),
+                Some((type_name, existing_values)) => {
+                    for value in existing_values.difference(values) {
+                        self.conflicts.push(format!(
+                            "enum {}: value `{}` is removed but may be stored",
+                            name, value
+                        ));
+                    }
+                    for value in values.difference(existing_values) {
+                        self.statements.push(format!(
+                            "alter type {}.\"{}\" add value if not exists '{}'",
+                            namespace,
+                            type_name,
+                            value.replace('\'', "''")
+                        ));
+                        self.changes
+                            .push(format!("enum {}: add value `{}`", name, value));
+                    }
+                }
+            }
+        }
+    }
+
+    fn plan_tables(
+        &mut self,
+        layout: &Layout,
+        existing_tables: &HashMap<String, HashMap<String, ExistingColumn>>,
+    ) {
+        let mut table_names = HashSet::new();
+        for table in layout.tables.values() {
+            table_names.insert(table.name.as_str().to_string());
+            match existing_tables.get(table.name.as_str()) {
+                None => self
+                    .changes
+                    .push(format!("create table {}", table.name.as_str())),
+                Some(existing_columns) => self.plan_columns(table, existing_columns),
+            }
+        }
+        // The tables of the removed entities are kept with their data,
+        // in the shared namespace they may belong to other indexers
+        for table_name in existing_tables.keys() {
+            if !table_names.contains(table_name) {
+                self.changes.push(format!(
+                    "keep table {} which is not in the schema anymore",
+                    table_name
+                ));
+            }
+        }
+    }
+
+    fn plan_columns(&mut self, table: &Table, existing_columns: &HashMap<String, ExistingColumn>) {
+        let table_name = table.name.as_str();
+        for column in table.columns.iter() {
+            let column_name = column.name.as_str();
+            let column_type = column_sql_type(column);
+            match existing_columns.get(column_name) {
+                None if column.is_nullable() => {
+                    self.statements.push(format!(
+                        "alter table {} add column \"{}\" {}",
+                        table.qualified_name.as_str(),
+                        column_name,
+                        column_type
+                    ));
+                    self.changes
+                        .push(format!("table {}: add column {}", table_name, column_name));
+                }
+                None => self.conflicts.push(format!(
+                    "table {}: new field `{}` is non-null, the existing rows have no value for it",
+                    table_name, column_name
+                )),
+                Some(existing) => {
+                    if normalize_type(&existing.column_type) != normalize_type(&column_type) {
+                        self.conflicts.push(format!(
+                            "table {}: field `{}` changes type from {} to {}",
+                            table_name, column_name, &existing.column_type, column_type
+                        ));
+                    } else if existing.not_null && column.is_nullable() {
+                        self.statements.push(format!(
+                            "alter table {} alter column \"{}\" drop not null",
+                            table.qualified_name.as_str(),
+                            column_name
+                        ));
+                        self.changes.push(format!(
+                            "table {}: column {} becomes nullable",
+                            table_name, column_name
+                        ));
+                    } else if !existing.not_null && !column.is_nullable() {
+                        self.conflicts.push(format!(
+                            "table {}: field `{}` becomes non-null but may hold null values",
+                            table_name, column_name
+                        ));
+                    }
+                }
+            }
+        }
+        // The columns of the removed fields are kept, they must accept null for the new rows
+        for (column_name, existing) in existing_columns.iter() {
+            if SYSTEM_COLUMNS.contains(&column_name.as_str())
+                || table
+                    .columns
+                    .iter()
+                    .any(|column| column.name.as_str() == column_name)
+            {
+                continue;
+            }
+            if existing.not_null {
+                self.statements.push(format!(
+                    "alter table {} alter column \"{}\" drop not null",
+                    table.qualified_name.as_str(),
+                    column_name
+                ));
+            }
+            self.changes.push(format!(
+                "table {}: keep column {} which is not in the schema anymore",
+                table_name, column_name
+            ));
+        }
+    }
+}
+
+impl fmt::Display for SchemaMigration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for conflict in &self.conflicts {
+            writeln!(f, "- incompatible: {}", conflict)?;
+        }
+        for change in &self.changes {
+            writeln!(f, "- {}", change)?;
+        }
+        Ok(())
+    }
+}
+
+fn load_columns(conn: &PgConnection, namespace: &str) -> Result<Vec<ExistingColumn>, StoreError> {
+    let columns = sql_query(
+        r#"select c.relname::text as table_name,
+                  a.attname::text as column_name,
+                  format_type(a.atttypid, a.atttypmod) as column_type,
+                  a.attnotnull as not_null
+        from pg_attribute a
+            join pg_class c on a.attrelid = c.oid
+            join pg_namespace n on c.relnamespace = n.oid
+        where n.nspname = $1 and c.relkind = 'r' and a.attnum > 0 and not a.attisdropped"#,
+    )
+    .bind::<Text, _>(namespace)
+    .get_results::<ExistingColumn>(conn)?;
+    Ok(columns)
+}
+
+fn load_enum_values(
+    conn: &PgConnection,
+    namespace: &str,
+) -> Result<Vec<ExistingEnumValue>, StoreError> {
+    let values = sql_query(
+        r#"select t.typname::text as type_name, e.enumlabel::text as value
+        from pg_type t
+            join pg_enum e on e.enumtypid = t.oid
+            join pg_namespace n on t.typnamespace = n.oid
+        where n.nspname = $1"#,
+    )
+    .bind::<Text, _>(namespace)
+    .get_results::<ExistingEnumValue>(conn)?;
+    Ok(values)
+}
+
+/// Type of the column as written in the ddl of graph
+fn column_sql_type(column: &Column) -> String {
+    if column.is_list() {
+        format!("{}[]", column.column_type.sql_type())
+    } else {
+        column.column_type.sql_type().to_string()
+    }
+}
+
+/// Compare the types without quotes nor schema, the enums are qualified with their namespace
+fn normalize_type(sql_type: &str) -> String {
+    let sql_type = sql_type.replace('"', "").to_lowercase();
+    match sql_type.rfind('.') {
+        Some(position) => sql_type[position + 1..].to_string(),
+        None => sql_type,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::postgres::store_builder::StoreBuilder;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn schema_file(name: &str, schema: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(name);
+        fs::write(&path, schema).unwrap();
+        path
+    }
+
+    fn existing_column(name: &str, column_type: &str, not_null: bool) -> ExistingColumn {
+        ExistingColumn {
+            table_name: String::from("token"),
+            column_name: name.to_string(),
+            column_type: column_type.to_string(),
+            not_null,
+        }
+    }
+
+    fn plan_token_columns(existing: Vec<ExistingColumn>) -> SchemaMigration {
+        let path = schema_file(
+            "migration_columns_test.graphql",
+            "type Token @entity { id: ID! symbol: String! decimals: Int }",
+        );
+        let layout = StoreBuilder::create_layout("sgd999996", &path).unwrap();
+        let mut existing_tables = HashMap::new();
+        existing_tables.insert(
+            String::from("token"),
+            existing
+                .into_iter()
+                .map(|column| (column.column_name.clone(), column))
+                .collect(),
+        );
+        let mut migration = SchemaMigration::default();
+        migration.plan_tables(&layout, &existing_tables);
+        migration
+    }
+
+    #[test]
+    fn column_type_change_is_a_conflict() {
+        let migration = plan_token_columns(vec![
+            existing_column("id", "text", true),
+            existing_column("symbol", "integer", true),
+            existing_column("decimals", "integer", false),
+        ]);
+        assert_eq!(
+            migration.conflicts,
+            vec!["table token: field `symbol` changes type from integer to text"]
+        );
+        assert!(!migration.is_compatible());
+    }
+
+    #[test]
+    fn new_nullable_column_is_added_and_removed_column_is_kept() {
+        let migration = plan_token_columns(vec![
+            existing_column("id", "text", true),
+            existing_column("symbol", "text", true),
+            existing_column("name", "text", true),
+        ]);
+        assert!(migration.is_compatible());
+        assert_eq!(
+            migration.statements,
+            vec![
+                "alter table \"sgd999996\".\"token\" add column \"decimals\" integer",
+                "alter table \"sgd999996\".\"token\" alter column \"name\" drop not null",
+            ]
+        );
+    }
+
+    #[test]
+    fn new_non_null_column_is_a_conflict() {
+        let migration = plan_token_columns(vec![
+            existing_column("id", "text", true),
+            existing_column("decimals", "integer", false),
+        ]);
+        assert_eq!(
+            migration.conflicts,
+            vec!["table token: new field `symbol` is non-null, the existing rows have no value for it"]
+        );
+    }
+
+    // Needs the database of DATABASE_CONNECTION_STRING
+    #[test]
+    #[ignore]
+    fn plan_reports_an_incompatible_column_type_change() {
+        let path = schema_file(
+            "migration_plan_test.graphql",
+            "type Token @entity { id: ID! symbol: String! }",
+        );
+        let store =
+            StoreBuilder::create_store("index_store_migration_test", "sgd999995", &path).unwrap();
+        let conn = store.get_conn().unwrap();
+        let path = schema_file(
+            "migration_plan_test.graphql",
+            "type Token @entity { id: ID! symbol: Int! }",
+        );
+        let layout = StoreBuilder::create_layout("sgd999995", &path).unwrap();
+        let schema = StoreBuilder::load_schema(&path).unwrap();
+        let migration = SchemaMigration::plan(&conn, &layout, &schema).unwrap();
+        assert_eq!(
+            migration.conflicts,
+            vec!["table token: field `symbol` changes type from text to integer"]
+        );
+    }
+}
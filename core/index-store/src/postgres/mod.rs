@@ -1,44 +1,71 @@
+pub mod batch;
+pub mod checkpoint;
+pub mod directives;
+pub mod events;
+pub mod graphql;
+pub mod metadata;
+pub mod migration;
 pub mod relational;
 pub mod store_builder;
 use graph::components::metrics::stopwatch::StopwatchMetrics;
 use graph::components::store::{
     EntityCollection, EntityFilter, EntityKey, EntityModification, EntityOrder, EntityRange,
-    EntityType, StoreError, StoreEvent, StoredDynamicDataSource, WritableStore,
+    EntityType, StoreError, StoredDynamicDataSource, WritableStore,
 };
 use graph::components::subgraph::Entity;
 use graph::data::query::QueryExecutionError;
+use graph::data::schema::Schema;
 use graph::data::subgraph::schema::SubgraphError;
 use graph::prelude::BlockPtr;
 use graph::prelude::{BlockNumber, DynTryFuture};
 use graph_store_postgres::command_support::Layout;
 use graph_store_postgres::connection_pool::ConnectionPool;
+use graph_store_postgres::relational::{Column, Table};
 use massbit_common::prelude::anyhow;
 use massbit_common::prelude::diesel::{
     r2d2::{ConnectionManager, PooledConnection},
-    Connection, PgConnection,
+    sql_query,
+    sql_types::{Integer, Text},
+    Connection, PgConnection, RunQueryDsl,
 };
+use massbit_common::prelude::serde_json::{self, json};
 use massbit_common::prelude::slog::Logger;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use crate::core::{IndexStore, QueryableStore};
-use crate::postgres::relational::LayoutExt;
+use crate::postgres::relational::{LayoutExt, PRIMARY_KEY_COLUMN};
 use crate::Value;
+use batch::{BlockBatch, TableChanges};
+use events::IndexStoreEvent;
 use massbit_common::prelude::{
     anyhow::{anyhow, Error},
     async_trait::async_trait,
     log,
 };
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::PathBuf;
-use store_builder::StoreBuilder;
+use std::time::Instant;
+use store_builder::{StoreBuilder, NAMESPACE};
 
 pub const BLOCK_NUMBER_MAX: BlockNumber = <i32>::MAX;
 
+/// Pending batch of each stream of an indexer
+pub type StreamBatches = Arc<Mutex<HashMap<String, Arc<Mutex<BlockBatch>>>>>;
+
 #[derive(Clone)]
 pub struct PostgresIndexStore {
+    pub indexer: String,
+    /// Stream of the indexer whose checkpoint is moved by this store
+    pub stream: String,
     pub logger: Logger,
     pub connection: ConnectionPool,
     pub layout: Layout,
+    /// Graphql schema the layout is built from
+    pub schema: Arc<Schema>,
+    /// Blocks of the stream which are handled but not committed yet
+    pub batch: Arc<Mutex<BlockBatch>>,
+    /// Batches of all the streams of the indexer, shared by the stores of the streams
+    pub batches: StreamBatches,
     //buffer: HashMap<String, TableBuffer>,
     //pub entity_dependencies: HashMap<EntityType, HashSet<EntityType>>,
 }
@@ -46,7 +73,142 @@ pub struct PostgresIndexStore {
 impl PostgresIndexStore {
     pub fn new(indexer: &str) -> Result<PostgresIndexStore, anyhow::Error> {
         let path = PathBuf::new();
-        StoreBuilder::create_store(indexer, &path)
+        StoreBuilder::create_store(indexer, NAMESPACE.as_str(), &path)
+    }
+    /// Same tables, but the checkpoint of another stream of the indexer
+    pub fn for_stream(&self, stream: &str) -> PostgresIndexStore {
+        let mut store = self.clone();
+        store.stream = stream.to_string();
+        store.batch = Default::default();
+        self.batches
+            .lock()
+            .unwrap()
+            .insert(stream.to_string(), store.batch.clone());
+        store
+    }
+    /// Latest block of the chain, the blocks far from it are committed in batches
+    pub fn set_chain_head(&self, chain_head: u64) {
+        self.batch.lock().unwrap().set_chain_head(chain_head);
+    }
+    /// Drop the pending blocks, they must be handled again from the checkpoint of the stream
+    pub fn discard_batch(&self) {
+        self.batch.lock().unwrap().take();
+    }
+    /// Commit the pending blocks in one transaction and move the checkpoint of the stream to the last one.
+    /// The blocks are kept in the batch if it fails, so the next flush tries them again.
+    pub fn flush_batch(&self) -> Result<(), StoreError> {
+        self.flush(self.stream.as_str(), &self.batch)
+    }
+    fn flush(&self, stream: &str, batch: &Mutex<BlockBatch>) -> Result<(), StoreError> {
+        let mut batch = batch.lock().unwrap();
+        let stopwatch = match (batch.is_empty(), batch.stopwatch.clone()) {
+            (false, Some(stopwatch)) => stopwatch,
+            _ => return Ok(()),
+        };
+        let start = Instant::now();
+        let conn = self.get_conn()?;
+        let count = batch
+            .blocks
+            .iter()
+            .map(|(_, mods)| mods.len())
+            .sum::<usize>();
+        let block_ptr = conn.transaction(|| -> Result<_, StoreError> {
+            let section = stopwatch.start_section("apply_entity_modifications");
+            // The search columns of the fulltext tables are filled by the layout, block by block
+            for (entity_type, changes) in
+                batch.table_changes(|entity_type| !self.has_fulltext(entity_type))
+            {
+                self.write_table_changes(&conn, &entity_type, &changes)?;
+            }
+            for (block_ptr, mods) in
+                batch.block_modifications(|entity_type| self.has_fulltext(entity_type))
+            {
+                self.apply_entity_modifications(&conn, mods, block_ptr, stopwatch.clone())?;
+            }
+            section.end();
+            let block_ptrs: Vec<&BlockPtr> = batch
+                .blocks
+                .iter()
+                .map(|(block_ptr, _)| block_ptr)
+                .collect();
+            checkpoint::save_block_hashes(&conn, self.indexer.as_str(), &block_ptrs)?;
+            let last_ptr = block_ptrs.last().cloned();
+            if let Some(block_ptr) = last_ptr {
+                checkpoint::save_block_ptr(&conn, self.indexer.as_str(), stream, block_ptr)?;
+            }
+            Ok(last_ptr.cloned())
+        })?;
+        log::info!(
+            "Indexer {} committed {} blocks of stream {} with {} records up to block {:?} in {:?}",
+            &self.indexer,
+            batch.blocks.len(),
+            stream,
+            count,
+            block_ptr.map(|ptr| ptr.number),
+            start.elapsed()
+        );
+        events::publish(IndexStoreEvent::from_blocks(&self.indexer, &batch.blocks));
+        batch.take();
+        Ok(())
+    }
+    /// Commit the batches of the other streams which hold one of the entities,
+    /// so the tables have their latest version before they are read
+    fn flush_streams_holding(&self, keys: &[(EntityType, String)]) -> Result<(), StoreError> {
+        let batches: Vec<(String, Arc<Mutex<BlockBatch>>)> = self
+            .batches
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(stream, _)| *stream != &self.stream)
+            .map(|(stream, batch)| (stream.clone(), batch.clone()))
+            .collect();
+        for (stream, batch) in batches {
+            let pending = {
+                let batch = batch.lock().unwrap();
+                keys.iter().any(|key| batch.entities.contains_key(key))
+            };
+            if pending {
+                self.flush(stream.as_str(), &batch)?;
+            }
+        }
+        Ok(())
+    }
+    /// Block number to query the entities at, given the number or the hash of a committed block.
+    /// The latest version of the entities is read when no block is given.
+    pub fn resolve_block(
+        &self,
+        conn: &PgConnection,
+        number: Option<BlockNumber>,
+        hash: Option<&str>,
+    ) -> Result<BlockNumber, StoreError> {
+        let number = match (number, hash) {
+            (_, Some(hash)) => checkpoint::load_block_number(
+                conn,
+                &self.indexer,
+                &checkpoint::block_hash_bytes(hash),
+            )?
+            .map(|number| number as BlockNumber)
+            .ok_or_else(|| {
+                StoreError::QueryExecutionError(format!(
+                    "Block {} is not indexed by indexer {}",
+                    hash, &self.indexer
+                ))
+            })?,
+            (Some(number), None) => number,
+            (None, None) => return Ok(BLOCK_NUMBER_MAX),
+        };
+        self.check_single_stream(conn, "Querying a past block")?;
+        // The entities of the blocks after the checkpoint are not all written yet
+        let latest = checkpoint::load_lowest_block_ptr(conn, &self.indexer)?
+            .map(|block_ptr| block_ptr.number)
+            .unwrap_or(-1);
+        if number < 0 || number > latest {
+            return Err(StoreError::QueryExecutionError(format!(
+                "Block {} is not indexed yet, indexer {} is at block {}",
+                number, &self.indexer, latest
+            )));
+        }
+        Ok(number)
     }
 }
 
@@ -57,6 +219,7 @@ impl QueryableStore for PostgresIndexStore {
         filter: Option<EntityFilter>,
         order: EntityOrder,
         range: EntityRange,
+        block: Option<BlockNumber>,
     ) -> Vec<Entity> {
         match self.get_conn() {
             Ok(conn) => {
@@ -67,6 +230,7 @@ impl QueryableStore for PostgresIndexStore {
                     filter,
                     order,
                     range,
+                    block.unwrap_or(BLOCK_NUMBER_MAX),
                 ) {
                     Ok(vec) => vec,
                     Err(err) => {
@@ -81,35 +245,105 @@ impl QueryableStore for PostgresIndexStore {
             }
         }
     }
+    /// The field may also be a list of references
+    fn load_derived(&self, entity_type: String, field: String, id: String) -> Vec<Entity> {
+        let filter = directives::derived_filter(&self.schema.document, &entity_type, &field, &id);
+        self.query(
+            entity_type,
+            Some(filter),
+            EntityOrder::Default,
+            EntityRange {
+                first: None,
+                skip: 0,
+            },
+            None,
+        )
+    }
 }
 impl IndexStore for PostgresIndexStore {}
 #[async_trait]
 impl WritableStore for PostgresIndexStore {
     fn block_ptr(&self) -> Result<Option<BlockPtr>, Error> {
-        Ok(None)
+        let conn = self.get_conn()?;
+        Ok(checkpoint::load_block_ptr(
+            &conn,
+            self.indexer.as_str(),
+            self.stream.as_str(),
+        )?)
     }
 
     fn start_subgraph_deployment(&self, _logger: &Logger) -> Result<(), StoreError> {
         Ok(())
     }
 
-    fn revert_block_operations(&self, _block_ptr_to: BlockPtr) -> Result<(), StoreError> {
+    /// Roll the tables back to the state they had after `block_ptr_to` and move the checkpoint
+    /// of the stream there, so the blocks after it are indexed again from the new chain.
+    /// Only indexers with a single stream are reverted, see `check_single_stream`.
+    fn revert_block_operations(&self, block_ptr_to: BlockPtr) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        self.check_single_stream(&conn, "Reverting blocks")?;
+        // Commit the pending blocks first so the revert sees all of them
+        self.flush_batch()?;
+        conn.transaction(|| -> Result<_, StoreError> {
+            let count = self.revert_entities(&conn, block_ptr_to.number + 1)?;
+            checkpoint::remove_block_hashes_after(
+                &conn,
+                self.indexer.as_str(),
+                block_ptr_to.number as i64,
+            )?;
+            checkpoint::save_block_ptr(
+                &conn,
+                self.indexer.as_str(),
+                self.stream.as_str(),
+                &block_ptr_to,
+            )?;
+            log::info!(
+                "Indexer {} reverted {} entity versions after block {}",
+                &self.indexer,
+                count,
+                block_ptr_to.number
+            );
+            Ok(())
+        })?;
+        events::publish(IndexStoreEvent::revert(&self.indexer, block_ptr_to.number));
         Ok(())
     }
 
     fn unfail(&self) -> Result<(), StoreError> {
-        Ok(())
+        let conn = self.get_conn()?;
+        checkpoint::remove_failure(&conn, self.indexer.as_str(), self.stream.as_str())
     }
 
-    async fn fail_subgraph(&self, _error: SubgraphError) -> Result<(), StoreError> {
-        Ok(())
+    async fn fail_subgraph(&self, error: SubgraphError) -> Result<(), StoreError> {
+        let conn = self.get_conn()?;
+        checkpoint::save_failure(
+            &conn,
+            self.indexer.as_str(),
+            self.stream.as_str(),
+            error.message.as_str(),
+            error.block_ptr.as_ref(),
+            error.handler.as_deref(),
+            error.deterministic,
+        )
     }
 
+    /// The index store doesn't keep proofs of indexing
     fn supports_proof_of_indexing<'a>(self: Arc<Self>) -> DynTryFuture<'a, bool> {
-        unimplemented!()
+        Box::pin(async { Ok::<bool, Error>(false) })
     }
 
     fn get(&self, key: &EntityKey) -> Result<Option<Entity>, QueryExecutionError> {
+        // The pending blocks are newer than the tables
+        if let Some(entity) = self
+            .batch
+            .lock()
+            .unwrap()
+            .entities
+            .get(&(key.entity_type.clone(), key.entity_id.clone()))
+        {
+            return Ok(entity.clone());
+        }
+        self.flush_streams_holding(&[(key.entity_type.clone(), key.entity_id.clone())])?;
         let conn = self.get_conn()?;
         //let layout = self.layout(&conn, site)?;
 
@@ -120,7 +354,7 @@ impl WritableStore for PostgresIndexStore {
         self.layout
             .find(&conn, &key.entity_type, &key.entity_id, BLOCK_NUMBER_MAX)
             .map_err(|e| {
-                println!("Error while get entity {:?}", &e);
+                log::warn!("Error while get entity {:?}", &e);
                 QueryExecutionError::ResolveEntityError(
                     key.subgraph_id.clone(),
                     key.entity_type.to_string(),
@@ -130,6 +364,8 @@ impl WritableStore for PostgresIndexStore {
             })
     }
 
+    /// Far from the chain head the modifications are buffered and committed with the next blocks
+    /// by `flush_batch`. Close to the head every block is committed in its own transaction.
     fn transact_block_operations(
         &self,
         block_ptr_to: BlockPtr,
@@ -143,42 +379,15 @@ impl WritableStore for PostgresIndexStore {
             log::info!("Transact {:?}", modification);
         });
          */
-        let conn = self.get_conn()?;
-        let event = conn.transaction(|| -> Result<_, StoreError> {
-            // Emit a store event for the changes we are about to make. We
-            // wait with sending it until we have done all our other work
-            // so that we do not hold a lock on the notification queue
-            // for longer than we have to
-            let event: StoreEvent = mods.iter().collect();
-
-            let section = stopwatch.start_section("apply_entity_modifications");
-            let _count = self.apply_entity_modifications(&conn, mods, &block_ptr_to, stopwatch)?;
-            section.end();
-            /*
-            deployment::update_entity_count(
-                &conn,
-                site.as_ref(),
-                layout.count_query.as_str(),
-                count,
-            )?;
-            section.end();
-
-            dynds::insert(&conn, &site.deployment, data_sources, &block_ptr_to)?;
-
-            if !deterministic_errors.is_empty() {
-                deployment::insert_subgraph_errors(
-                    &conn,
-                    &site.deployment,
-                    deterministic_errors,
-                    block_ptr_to.block_number(),
-                )?;
-            }
-
-            deployment::forward_block_ptr(&conn, &site.deployment, block_ptr_to)?;
-            */
-            Ok(event)
-        })?;
-        log::info!("{:?}", &event);
+        let full = {
+            let mut batch = self.batch.lock().unwrap();
+            batch.stopwatch = Some(stopwatch);
+            batch.push(block_ptr_to, mods);
+            batch.is_full()
+        };
+        if full {
+            self.flush_batch()?;
+        }
         Ok(())
     }
 
@@ -190,8 +399,45 @@ impl WritableStore for PostgresIndexStore {
         if ids_for_type.is_empty() {
             return Ok(BTreeMap::new());
         }
-        let conn = self.get_conn()?;
-        self.layout.find_many(&conn, ids_for_type, BLOCK_NUMBER_MAX)
+        // Take the entities of the pending blocks from the batch and the others from the tables
+        let mut result: BTreeMap<EntityType, Vec<Entity>> = BTreeMap::new();
+        let mut missing_ids = BTreeMap::new();
+        {
+            let batch = self.batch.lock().unwrap();
+            for (entity_type, ids) in ids_for_type {
+                for id in ids {
+                    match batch.entities.get(&(entity_type.clone(), id.to_string())) {
+                        Some(Some(entity)) => result
+                            .entry(entity_type.clone())
+                            .or_default()
+                            .push(entity.clone()),
+                        Some(None) => {}
+                        None => missing_ids
+                            .entry(entity_type)
+                            .or_insert_with(Vec::new)
+                            .push(id),
+                    }
+                }
+            }
+        }
+        if !missing_ids.is_empty() {
+            let keys: Vec<(EntityType, String)> = missing_ids
+                .iter()
+                .flat_map(|(entity_type, ids)| {
+                    ids.iter()
+                        .map(move |id| ((*entity_type).clone(), id.to_string()))
+                })
+                .collect();
+            self.flush_streams_holding(&keys)?;
+            let conn = self.get_conn()?;
+            for (entity_type, entities) in
+                self.layout
+                    .find_many(&conn, missing_ids, BLOCK_NUMBER_MAX)?
+            {
+                result.entry(entity_type).or_default().extend(entities);
+            }
+        }
+        Ok(result)
     }
 
     fn deployment_synced(&self) -> Result<(), Error> {
@@ -219,6 +465,118 @@ impl PostgresIndexStore {
         self.connection.get_with_timeout_warning(&self.logger)
     }
 
+    /// Same as the revert of graph: delete the versions created at or after `block`
+    /// and make the versions which were ended at or after it current again
+    fn revert_entities(
+        &self,
+        conn: &PgConnection,
+        block: BlockNumber,
+    ) -> Result<usize, StoreError> {
+        let mut count = 0;
+        // The table names come from the layout, the block is bound
+        for table in self.layout.tables.values() {
+            count += sql_query(format!(
+                "delete from {} where lower(block_range) >= $1",
+                table.qualified_name.as_str()
+            ))
+            .bind::<Integer, _>(block)
+            .execute(conn)?;
+            sql_query(format!(
+                "update {} set block_range = int4range(lower(block_range), null) \
+                 where not upper_inf(block_range) and upper(block_range) >= $1",
+                table.qualified_name.as_str()
+            ))
+            .bind::<Integer, _>(block)
+            .execute(conn)?;
+        }
+        Ok(count)
+    }
+
+    /// Block ranges and block hashes are kept per indexer, not per stream: the blocks of two streams
+    /// with the same number can't be told apart. Reverts and queries of a past block are only
+    /// consistent when the indexer has a single stream.
+    fn check_single_stream(&self, conn: &PgConnection, operation: &str) -> Result<(), StoreError> {
+        let mut streams: HashSet<String> = checkpoint::load_stream_ids(conn, &self.indexer)?
+            .into_iter()
+            .collect();
+        streams.extend(self.batches.lock().unwrap().keys().cloned());
+        if !self.stream.is_empty() {
+            streams.insert(self.stream.clone());
+        }
+        if streams.len() > 1 {
+            return Err(StoreError::QueryExecutionError(format!(
+                "{} is not supported for indexer {}, it has {} streams",
+                operation,
+                &self.indexer,
+                streams.len()
+            )));
+        }
+        Ok(())
+    }
+
+    fn has_fulltext(&self, entity_type: &EntityType) -> bool {
+        self.layout
+            .table_for_entity(entity_type)
+            .map(|table| table.columns.iter().any(|column| column.is_fulltext()))
+            .unwrap_or_default()
+    }
+
+    /// Write the versions of a batch into the table of the entity type: one statement ends the
+    /// current versions, one inserts the new versions with their block range. The rows are sent
+    /// as json and converted to the columns of the table by `jsonb_populate_recordset`.
+    fn write_table_changes(
+        &self,
+        conn: &PgConnection,
+        entity_type: &EntityType,
+        changes: &TableChanges,
+    ) -> Result<(), StoreError> {
+        let table = self.layout.table_for_entity(entity_type)?;
+        if !changes.clamps.is_empty() {
+            let id_column = table.primary_key();
+            let rows: Vec<serde_json::Value> = changes
+                .clamps
+                .iter()
+                .map(|(id, block)| {
+                    json!({
+                        PRIMARY_KEY_COLUMN: column_json(id_column, &Value::String(id.to_string())),
+                        "block_range": format!("[{},)", block),
+                    })
+                })
+                .collect();
+            sql_query(format!(
+                "update {table} as e \
+                 set block_range = int4range(lower(e.block_range), lower(v.block_range)) \
+                 from jsonb_populate_recordset(null::{table}, $1::jsonb) as v \
+                 where e.id = v.id and upper_inf(e.block_range)",
+                table = table.qualified_name.as_str()
+            ))
+            .bind::<Text, _>(serde_json::Value::Array(rows).to_string())
+            .execute(conn)?;
+        }
+        if !changes.versions.is_empty() {
+            let rows: Vec<serde_json::Value> = changes
+                .versions
+                .iter()
+                .map(|(entity, lower, upper)| version_row(table, entity, *lower, *upper))
+                .collect();
+            let columns = table
+                .columns
+                .iter()
+                .map(|column| format!("\"{}\"", column.name.as_str()))
+                .collect::<Vec<_>>()
+                .join(", ");
+            sql_query(format!(
+                "insert into {table}({columns}, block_range) \
+                 select {columns}, block_range from jsonb_populate_recordset(null::{table}, $1::jsonb)",
+                table = table.qualified_name.as_str(),
+                columns = columns
+            ))
+            .bind::<Text, _>(serde_json::Value::Array(rows).to_string())
+            .execute(conn)?;
+        }
+        Ok(())
+    }
+
     fn apply_entity_modifications(
         &self,
         conn: &PgConnection,
@@ -336,3 +694,142 @@ impl PostgresIndexStore {
             .map_err(|_error| anyhow!("Failed to remove entities: {:?}", entity_keys).into())
     }
 }
+
+/// Json row of an entity version, the upper bound of the block range is open when it is None
+fn version_row(
+    table: &Table,
+    entity: &Entity,
+    lower: BlockNumber,
+    upper: Option<BlockNumber>,
+) -> serde_json::Value {
+    let mut row = serde_json::Map::new();
+    for column in table.columns.iter() {
+        if let Some(value) = entity.get(column.field.as_str()) {
+            row.insert(column.name.as_str().to_string(), column_json(column, value));
+        }
+    }
+    let upper = upper.map(|upper| upper.to_string()).unwrap_or_default();
+    row.insert(
+        "block_range".to_string(),
+        json!(format!("[{},{})", lower, upper)),
+    );
+    serde_json::Value::Object(row)
+}
+
+/// Value of a column in the text format of postgres, bytes are written as hex with a `\x` prefix
+fn column_json(column: &Column, value: &Value) -> serde_json::Value {
+    let bytea = column.column_type.sql_type() == "bytea";
+    match value {
+        Value::String(string) => match string.strip_prefix("0x") {
+            Some(hex) if bytea => json!(format!("\\x{}", hex)),
+            _ => json!(string),
+        },
+        Value::Int(int) => json!(int),
+        Value::BigDecimal(decimal) => json!(decimal.to_string()),
+        Value::BigInt(int) => json!(int.to_string()),
+        Value::Bool(bool) => json!(bool),
+        Value::Bytes(bytes) => json!(format!("\\x{}", bytes.to_string().trim_start_matches("0x"))),
+        Value::List(values) => serde_json::Value::Array(
+            values
+                .iter()
+                .map(|value| column_json(column, value))
+                .collect(),
+        ),
+        Value::Null => serde_json::Value::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::blockchain::BlockHash;
+    use graph::prelude::DeploymentHash;
+    use graph_mock::MockMetricsRegistry;
+    use massbit_common::prelude::slog;
+    use std::fs;
+
+    const INDEXER: &str = "index_store_revert_test";
+
+    fn block_ptr(number: i32) -> BlockPtr {
+        BlockPtr {
+            hash: BlockHash::from(vec![number as u8; 32]),
+            number,
+        }
+    }
+
+    fn key(id: &str) -> EntityKey {
+        EntityKey::data(
+            DeploymentHash::new("indexer").unwrap(),
+            "Token".to_string(),
+            id.to_string(),
+        )
+    }
+
+    fn token(id: &str, symbol: &str) -> Entity {
+        let mut entity = Entity::new();
+        entity.set("id", id);
+        entity.set("symbol", symbol);
+        entity
+    }
+
+    // Needs the database of DATABASE_CONNECTION_STRING
+    #[test]
+    #[ignore]
+    fn revert_removes_the_entities_and_the_checkpoint_after_the_block() {
+        let schema_path = std::env::temp_dir().join("index_store_revert_test.graphql");
+        fs::write(
+            &schema_path,
+            "type Token @entity { id: ID! symbol: String! }",
+        )
+        .unwrap();
+        let store = StoreBuilder::create_store(INDEXER, "sgd999999", &schema_path)
+            .unwrap()
+            .for_stream("stream");
+        let conn = store.get_conn().unwrap();
+        metadata::run_migrations(&conn).unwrap();
+        checkpoint::remove_block_ptr(&conn, INDEXER).unwrap();
+        store.revert_entities(&conn, 0).unwrap();
+        let stopwatch = StopwatchMetrics::new(
+            Logger::root(slog::Discard, slog::o!()),
+            DeploymentHash::new("indexer").unwrap(),
+            Arc::new(MockMetricsRegistry::new()),
+        );
+        let blocks = vec![
+            EntityModification::Insert {
+                key: key("1"),
+                data: token("1", "A"),
+            },
+            EntityModification::Overwrite {
+                key: key("1"),
+                data: token("1", "B"),
+            },
+            EntityModification::Insert {
+                key: key("2"),
+                data: token("2", "C"),
+            },
+        ];
+        for (number, modification) in blocks.into_iter().enumerate() {
+            store
+                .transact_block_operations(
+                    block_ptr(number as i32 + 1),
+                    vec![modification],
+                    stopwatch.clone(),
+                    vec![],
+                    vec![],
+                )
+                .unwrap();
+        }
+        store.flush_batch().unwrap();
+        assert_eq!(store.block_ptr().unwrap(), Some(block_ptr(3)));
+
+        store.revert_block_operations(block_ptr(1)).unwrap();
+        let entity = store.get(&key("1")).unwrap().unwrap();
+        assert_eq!(entity.get("symbol"), Some(&Value::String("A".to_string())));
+        assert_eq!(store.get(&key("2")).unwrap(), None);
+        assert_eq!(store.block_ptr().unwrap(), Some(block_ptr(1)));
+        assert_eq!(
+            checkpoint::load_block_number(&conn, INDEXER, block_ptr(2).hash.as_slice()).unwrap(),
+            None
+        );
+    }
+}
@@ -1,9 +1,12 @@
+use super::directives::derived_from;
 use diesel::debug_query;
 use graph::components::store::{
     AttributeNames, BlockNumber, EntityCollection, EntityFilter, EntityOrder, EntityRange,
-    EntityType,
+    EntityType, EntityWindow,
 };
+use graph::data::graphql::ext::DocumentExt;
 use graph::data::query::QueryExecutionError;
+use graph::data::schema::Schema;
 use graph::prelude::q;
 use graph::prelude::Logger;
 use graph_store_postgres::relational::{Layout, Table};
@@ -33,7 +36,10 @@ pub trait LayoutExt {
     fn gen_relationship(&self) -> Vec<String>;
     //fn create_dependencies(&self) -> HashMap<EntityType, HashSet<EntityType>>;
     fn create_hasura_tracking_tables(&self) -> (serde_json::Value, serde_json::Value);
-    fn create_hasura_tracking_relationships(&self) -> (serde_json::Value, serde_json::Value);
+    fn create_hasura_tracking_relationships(
+        &self,
+        schema: &Schema,
+    ) -> (serde_json::Value, serde_json::Value);
     fn filter<T: relational_queries::FromEntityData>(
         &self,
         logger: &Logger,
@@ -42,8 +48,68 @@ pub trait LayoutExt {
         filter: Option<EntityFilter>,
         order: EntityOrder,
         range: EntityRange,
+        block: BlockNumber,
     ) -> Result<Vec<T>, QueryExecutionError>;
+    /// Children of the parents of the windows in one query, `order` and `range` apply to the
+    /// children of each parent
+    fn filter_windows<T: relational_queries::FromEntityData>(
+        &self,
+        conn: &PgConnection,
+        windows: Vec<EntityWindow>,
+        order: EntityOrder,
+        range: EntityRange,
+        block: BlockNumber,
+    ) -> Result<Vec<T>, QueryExecutionError>;
+    /// Table and name of each `@derivedFrom` field with the table and column of the reference it is derived from.
+    /// Only the references to one entity are returned
+    fn derived_relationships(&self, schema: &Schema) -> Vec<(String, String, String, String)>;
+}
+fn load_collection<T: relational_queries::FromEntityData>(
+    layout: &Layout,
+    conn: &PgConnection,
+    collection: EntityCollection,
+    filter: Option<EntityFilter>,
+    order: EntityOrder,
+    range: EntityRange,
+    block: BlockNumber,
+) -> Result<Vec<T>, QueryExecutionError> {
+    let filter_collection = FilterCollection::new(layout, collection, filter.as_ref())?;
+    let query = FilterQuery::new(
+        &filter_collection,
+        filter.as_ref(),
+        order,
+        range,
+        block,
+        None,
+    )?;
+    let query_clone = query.clone();
+
+    let start = Instant::now();
+    let values = conn
+        .transaction(|| {
+            // if let Some(ref timeout_sql) = *STATEMENT_TIMEOUT {
+            //     conn.batch_execute(timeout_sql)?;
+            // }
+            query.load::<EntityData>(conn)
+        })
+        .map_err(|e| {
+            QueryExecutionError::ResolveEntitiesError(format!(
+                "{}, query = {:?}",
+                e,
+                debug_query(&query_clone).to_string()
+            ))
+        })?;
+    //log_query_timing(logger, &query_clone, start.elapsed(), values.len());
+    values
+        .into_iter()
+        .map(|entity_data| {
+            entity_data
+                .deserialize_with_layout(layout)
+                .map_err(|e| e.into())
+        })
+        .collect()
 }
+
 fn named_type(field_type: &q::Type) -> &str {
     match field_type {
         q::Type::NamedType(name) => name.as_str(),
@@ -167,7 +233,10 @@ impl LayoutExt for Layout {
             }),
         )
     }
-    fn create_hasura_tracking_relationships(&self) -> (serde_json::Value, serde_json::Value) {
+    fn create_hasura_tracking_relationships(
+        &self,
+        graphql_schema: &Schema,
+    ) -> (serde_json::Value, serde_json::Value) {
         let mut hasura_relations: Vec<serde_json::Value> = Vec::new();
         let mut hasura_down_relations: Vec<serde_json::Value> = Vec::new();
         let schema = self.site.namespace.as_str();
@@ -256,6 +325,45 @@ impl LayoutExt for Layout {
                     }));
                 });
         });
+        // A `@derivedFrom` field is an array relationship named after the field.
+        // Lists of references are stored as arrays of ids, which hasura can not join on
+        for (table, field, remote_table, remote_column) in
+            self.derived_relationships(graphql_schema)
+        {
+            hasura_relations.push(serde_json::json!({
+                "type": "create_array_relationship",
+                "args": {
+                    "name": field.as_str(),
+                    "table": {
+                        "name": table.as_str(),
+                        "schema": schema,
+                    },
+                    "using" : {
+                        "manual_configuration":{
+                            "remote_table":{
+                                "name": remote_table.as_str(),
+                                "schema": schema
+                            },
+                            "source":"default",
+                            "column_mapping":{
+                                PRIMARY_KEY_COLUMN: remote_column.as_str(),
+                            }
+                        }
+                    }
+                }
+            }));
+            hasura_down_relations.push(serde_json::json!({
+                "type": "drop_relationship",
+                "args": {
+                    "relationship": field,
+                    "source": "default",
+                    "table": {
+                        "name": table,
+                        "schema": schema,
+                    },
+                 }
+            }));
+        }
         (
             serde_json::json!({
                 "type": "bulk",
@@ -347,6 +455,7 @@ impl LayoutExt for Layout {
         filter: Option<EntityFilter>,
         order: EntityOrder,
         range: EntityRange,
+        block: BlockNumber,
     ) -> Result<Vec<T>, QueryExecutionError> {
         // fn log_query_timing(
         //     logger: &Logger,
@@ -379,40 +488,52 @@ impl LayoutExt for Layout {
         // }
 
         let collection = EntityCollection::All(vec![(entity_type, AttributeNames::All)]);
-        let filter_collection = FilterCollection::new(&self, collection, filter.as_ref())?;
-        let query = FilterQuery::new(
-            &filter_collection,
-            filter.as_ref(),
-            order,
-            range,
-            std::i32::MAX, //Ignore blockrange
-            None,
-        )?;
-        let query_clone = query.clone();
+        load_collection(self, conn, collection, filter, order, range, block)
+    }
 
-        let start = Instant::now();
-        let values = conn
-            .transaction(|| {
-                // if let Some(ref timeout_sql) = *STATEMENT_TIMEOUT {
-                //     conn.batch_execute(timeout_sql)?;
-                // }
-                query.load::<EntityData>(conn)
-            })
-            .map_err(|e| {
-                QueryExecutionError::ResolveEntitiesError(format!(
-                    "{}, query = {:?}",
-                    e,
-                    debug_query(&query_clone).to_string()
-                ))
-            })?;
-        //log_query_timing(logger, &query_clone, start.elapsed(), values.len());
-        values
-            .into_iter()
-            .map(|entity_data| {
-                entity_data
-                    .deserialize_with_layout(self)
-                    .map_err(|e| e.into())
-            })
-            .collect()
+    fn filter_windows<T: relational_queries::FromEntityData>(
+        &self,
+        conn: &PgConnection,
+        windows: Vec<EntityWindow>,
+        order: EntityOrder,
+        range: EntityRange,
+        block: BlockNumber,
+    ) -> Result<Vec<T>, QueryExecutionError> {
+        let collection = EntityCollection::Window(windows);
+        load_collection(self, conn, collection, None, order, range, block)
+    }
+
+    fn derived_relationships(&self, schema: &Schema) -> Vec<(String, String, String, String)> {
+        let mut relationships = Vec::new();
+        for object_type in schema.document.get_object_type_definitions() {
+            let table = match self.table_for_entity(&EntityType::new(object_type.name.clone())) {
+                Ok(table) => table,
+                Err(_) => continue,
+            };
+            for field in object_type.fields.iter() {
+                let source_field = match derived_from(field) {
+                    Some(source_field) => source_field,
+                    None => continue,
+                };
+                let remote_type = EntityType::new(named_type(&field.field_type).to_string());
+                let remote_table = match self.table_for_entity(&remote_type) {
+                    Ok(remote_table) => remote_table,
+                    Err(_) => continue,
+                };
+                if let Some(column) = remote_table
+                    .columns
+                    .iter()
+                    .find(|column| column.field == source_field && !column.is_list())
+                {
+                    relationships.push((
+                        table.name.as_str().to_string(),
+                        field.name.clone(),
+                        remote_table.name.as_str().to_string(),
+                        column.name.as_str().to_string(),
+                    ));
+                }
+            }
+        }
+        relationships
     }
 }
@@ -80,6 +80,15 @@ impl ValueFrom<i64> for EntityValue {
     }
 }
 
+impl<T> ValueFrom<Vec<T>> for EntityValue
+where
+    EntityValue: ValueFrom<T>,
+{
+    fn value_from(values: Vec<T>) -> Value {
+        Value::List(values.into_iter().map(EntityValue::value_from).collect())
+    }
+}
+
 pub trait TryFrom {
     fn try_from<T: Any>(value: T) -> Value;
 }
@@ -115,6 +124,9 @@ impl TryFrom for Value {
 }
 pub trait FromValueTrait {
     fn as_i64(self) -> Option<i64>;
+    fn as_string_list(self) -> Option<Vec<String>>;
+    fn as_i64_list(self) -> Option<Vec<i64>>;
+    fn as_bool_list(self) -> Option<Vec<bool>>;
 }
 impl FromValueTrait for Value {
     fn as_i64(self) -> Option<i64> {
@@ -125,4 +137,22 @@ impl FromValueTrait for Value {
             None
         }
     }
+    fn as_string_list(self) -> Option<Vec<String>> {
+        self.as_list()?
+            .into_iter()
+            .map(|value| value.as_string())
+            .collect()
+    }
+    fn as_i64_list(self) -> Option<Vec<i64>> {
+        self.as_list()?
+            .into_iter()
+            .map(FromValueTrait::as_i64)
+            .collect()
+    }
+    fn as_bool_list(self) -> Option<Vec<bool>> {
+        self.as_list()?
+            .into_iter()
+            .map(|value| value.as_bool())
+            .collect()
+    }
 }
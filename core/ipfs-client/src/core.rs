@@ -26,17 +26,39 @@ pub struct AddResponse {
     pub size: String,
 }
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct HashResponse {
+    pub hash: String,
+}
+
+// An IPFS node exposes the whole API, a gateway only serves the files under `/ipfs/<cid>`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EndpointKind {
+    Api,
+    Gateway,
+}
+
 #[derive(Clone)]
 pub struct IpfsClient {
     base: Arc<Uri>,
     client: Arc<reqwest::Client>,
+    kind: EndpointKind,
 }
 
 impl IpfsClient {
+    /// The address is a gateway when its path ends with `/ipfs`, e.g. `https://ipfs.io/ipfs/`
     pub fn new(base: &str) -> Result<Self, Error> {
+        let base = Uri::from_str(base)?;
+        let kind = if base.path().trim_end_matches('/').ends_with("/ipfs") {
+            EndpointKind::Gateway
+        } else {
+            EndpointKind::Api
+        };
         Ok(IpfsClient {
             client: Arc::new(reqwest::Client::new()),
-            base: Arc::new(Uri::from_str(base)?),
+            base: Arc::new(base),
+            kind,
         })
     }
 
@@ -44,9 +66,18 @@ impl IpfsClient {
         IpfsClient {
             client: Arc::new(reqwest::Client::new()),
             base: Arc::new(Uri::from_str("http://localhost:5001").unwrap()),
+            kind: EndpointKind::Api,
         }
     }
 
+    pub fn kind(&self) -> EndpointKind {
+        self.kind
+    }
+
+    pub fn base(&self) -> String {
+        self.base.to_string()
+    }
+
     /// Calls `object stat`.
     pub async fn object_stat(&self, path: String) -> Result<ObjectStatResponse, reqwest::Error> {
         self.call(self.url("object/stat", path), None)
@@ -57,7 +88,10 @@ impl IpfsClient {
 
     /// Download the entire contents.
     pub async fn cat_all(&self, cid: String) -> Result<Bytes, reqwest::Error> {
-        self.call(self.url("cat", cid), None).await?.bytes().await
+        match self.kind {
+            EndpointKind::Api => self.call(self.url("cat", cid), None).await?.bytes().await,
+            EndpointKind::Gateway => self.get(self.gateway_url(&cid)).await?.bytes().await,
+        }
     }
 
     pub async fn cat(
@@ -82,12 +116,47 @@ impl IpfsClient {
             .await
     }
 
+    /// Calls `add` without storing the data, to get the cid the data has on IPFS.
+    pub async fn only_hash(
+        &self,
+        data: Vec<u8>,
+        cid_version: u8,
+    ) -> Result<String, reqwest::Error> {
+        let form = multipart::Form::new().part("path", multipart::Part::bytes(data));
+        let response: HashResponse = self
+            .call(
+                format!(
+                    "{}api/v0/add?only-hash=true&cid-version={}",
+                    self.base, cid_version
+                ),
+                Some(form),
+            )
+            .await?
+            .json()
+            .await?;
+        Ok(response.hash)
+    }
+
+    fn gateway_url(&self, cid: &str) -> String {
+        // The cid is checked by the caller, it only has alphanumeric characters
+        format!("{}/{}", self.base.to_string().trim_end_matches('/'), cid)
+    }
+
     fn url(&self, route: &'static str, arg: String) -> String {
         // URL security: We control the base and the route, user-supplied input goes only into the
         // query parameters.
         format!("{}api/v0/{}?arg={}", self.base, route, arg)
     }
 
+    async fn get(&self, url: String) -> Result<reqwest::Response, reqwest::Error> {
+        self.client
+            .get(&url)
+            .send()
+            .await
+            .map(|res| res.error_for_status())
+            .and_then(|x| x)
+    }
+
     async fn call(
         &self,
         url: String,
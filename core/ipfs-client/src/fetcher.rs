@@ -0,0 +1,269 @@
+use crate::core::{create_ipfs_clients, EndpointKind, IpfsClient};
+use anyhow::{anyhow, Error};
+use bytes::Bytes;
+use slog::Logger;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+use utils::futures::retry;
+
+/// Fetch files from several IPFS nodes or gateways, in the order they are given:
+/// - every request is retried `retries` times and an attempt taking longer than `timeout` fails
+/// - the next endpoint is used when all the attempts fail
+/// - the content is checked against its cid by an IPFS node before being returned
+/// - the files are kept on disk by cid, so a file is only fetched once
+pub struct IpfsFetcher {
+    clients: Vec<IpfsClient>,
+    timeout: Duration,
+    retries: usize,
+    verify: bool,
+    cache: Option<PathBuf>,
+    logger: Logger,
+}
+
+impl IpfsFetcher {
+    pub async fn new(addresses: &Vec<String>, logger: &Logger) -> Self {
+        IpfsFetcher {
+            clients: create_ipfs_clients(addresses).await,
+            timeout: Duration::from_secs(30),
+            retries: 3,
+            verify: true,
+            cache: None,
+            logger: logger.clone(),
+        }
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    pub fn cache(mut self, folder: Option<PathBuf>) -> Self {
+        self.cache = folder;
+        self
+    }
+
+    /// Download the entire contents of `cid` from the cache or the first endpoint which has it.
+    pub async fn cat(&self, cid: &str) -> Result<Bytes, Error> {
+        check_cid(cid)?;
+        if let Some(bytes) = self.read_cache(cid) {
+            log::info!("[Ipfs Client] Read {} from the cache", cid);
+            return Ok(bytes);
+        }
+
+        let mut errors = vec![];
+        for client in self.clients.iter() {
+            let bytes = match self.cat_from(client, cid).await {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    log::warn!(
+                        "[Ipfs Client] Cannot get {} from {}: {}",
+                        cid,
+                        client.base(),
+                        e
+                    );
+                    errors.push(format!("{}: {}", client.base(), e));
+                    continue;
+                }
+            };
+            // A gateway may serve anything, the next endpoint is used when the content doesn't match
+            if self.verify {
+                if let Err(e) = self.check_content(cid, &bytes).await {
+                    log::warn!("[Ipfs Client] {} from {}", e, client.base());
+                    errors.push(format!("{}: {}", client.base(), e));
+                    continue;
+                }
+            }
+            self.write_cache(cid, &bytes);
+            return Ok(bytes);
+        }
+        Err(anyhow!(
+            "Cannot get {} from IPFS, {}",
+            cid,
+            errors.join(", ")
+        ))
+    }
+
+    async fn cat_from(&self, client: &IpfsClient, cid: &str) -> Result<Bytes, Error> {
+        let client = client.clone();
+        let cid = cid.to_string();
+        retry(
+            format!("IPFS cat {} from {}", cid, client.base()),
+            &self.logger,
+        )
+        .limit(self.retries)
+        .timeout(self.timeout)
+        .run(move || {
+            let client = client.clone();
+            let cid = cid.clone();
+            async move { client.cat_all(cid).await }
+        })
+        .await
+        .map_err(|e| anyhow!("{}", e))
+    }
+
+    // Gateways can't compute a cid, the first IPFS node that answers does it.
+    // The cid is computed with the default chunker, as the files added by the CLI or the IPFS API.
+    async fn check_content(&self, cid: &str, bytes: &Bytes) -> Result<(), Error> {
+        let cid_version = if cid.starts_with("Qm") { 0 } else { 1 };
+        for client in self.clients.iter() {
+            if client.kind() != EndpointKind::Api {
+                continue;
+            }
+            match client.only_hash(bytes.to_vec(), cid_version).await {
+                Ok(hash) if hash == cid => return Ok(()),
+                Ok(hash) => {
+                    return Err(anyhow!(
+                        "The content received for {} has the cid {}",
+                        cid,
+                        hash
+                    ))
+                }
+                Err(e) => log::warn!(
+                    "[Ipfs Client] Cannot compute the cid of {} with {}: {}",
+                    cid,
+                    client.base(),
+                    e
+                ),
+            }
+        }
+        log::warn!(
+            "[Ipfs Client] No IPFS node could check the content of {}, it is used as is",
+            cid
+        );
+        Ok(())
+    }
+
+    fn read_cache(&self, cid: &str) -> Option<Bytes> {
+        let path = self.cache.as_ref()?.join(cid);
+        fs::read(path).ok().map(Bytes::from)
+    }
+
+    // The file is written under a temporary name then renamed, so a partial file is never read
+    fn write_cache(&self, cid: &str, bytes: &Bytes) {
+        let folder = match &self.cache {
+            Some(folder) => folder,
+            None => return,
+        };
+        let partial = folder.join(format!("{}.partial", cid));
+        let result = fs::create_dir_all(folder)
+            .and_then(|_| fs::write(&partial, bytes))
+            .and_then(|_| fs::rename(&partial, folder.join(cid)));
+        if let Err(e) = result {
+            log::warn!("[Ipfs Client] Cannot cache {} in {:?}: {}", cid, folder, e);
+        }
+    }
+}
+
+// A cid is base58 (v0) or multibase (v1) encoded, anything else is rejected before being sent
+fn check_cid(cid: &str) -> Result<(), Error> {
+    let is_v0 = cid.len() == 46 && cid.starts_with("Qm");
+    let is_v1 = cid.len() > 8 && !cid.starts_with("Qm");
+    if (is_v0 || is_v1) && cid.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Ok(())
+    } else {
+        Err(anyhow!("{} is not a valid cid", cid))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    const CID: &str = "QmYwAPJzv5CZsnA625s3Xf2nemtYgPpHdWEz79ojWnPbdG";
+    const OTHER_CID: &str = "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o";
+
+    // A node serving `content` for any cid, whose `add?only-hash` answers `hash`
+    fn start_node(content: &'static str, hash: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    let header = header.to_lowercase();
+                    if let Some(value) = header.strip_prefix("content-length:") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let response = if request_line.contains("/api/v0/add") {
+                    format!("{{\"Hash\":\"{}\"}}", hash)
+                } else {
+                    content.to_string()
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    response.len(),
+                    response
+                )
+                .unwrap();
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    async fn fetcher(node: &str, cache: &PathBuf) -> IpfsFetcher {
+        let logger = Logger::root(slog::Discard, slog::o!());
+        // The gateway serves the file, the API of the same node checks it
+        let addresses = vec![format!("{}/ipfs/", node), node.to_string()];
+        IpfsFetcher::new(&addresses, &logger)
+            .await
+            .retries(1)
+            .timeout(Duration::from_secs(5))
+            .cache(Some(cache.clone()))
+    }
+
+    #[tokio::test]
+    async fn content_not_matching_its_cid_is_rejected() {
+        let node = start_node("tampered", OTHER_CID);
+        let cache = std::env::temp_dir().join("ipfs_fetcher_mismatch_test");
+        let err = fetcher(&node, &cache).await.cat(CID).await.unwrap_err();
+        assert!(err.to_string().contains(&format!(
+            "The content received for {} has the cid {}",
+            CID, OTHER_CID
+        )));
+        assert!(!cache.join(CID).exists());
+    }
+
+    #[tokio::test]
+    async fn content_matching_its_cid_is_cached() {
+        let node = start_node("schema", CID);
+        let cache = std::env::temp_dir().join("ipfs_fetcher_match_test");
+        let _ = fs::remove_dir_all(&cache);
+        let bytes = fetcher(&node, &cache).await.cat(CID).await.unwrap();
+        assert_eq!(bytes, Bytes::from("schema"));
+        assert_eq!(fs::read(cache.join(CID)).unwrap(), b"schema");
+    }
+
+    #[test]
+    fn invalid_cid_is_rejected() {
+        assert!(check_cid(CID).is_ok());
+        assert!(check_cid("../../etc/passwd").is_err());
+        assert!(check_cid("QmShort").is_err());
+    }
+}
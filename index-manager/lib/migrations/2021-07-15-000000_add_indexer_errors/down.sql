@@ -0,0 +1,3 @@
+ALTER TABLE indexers DROP COLUMN IF EXISTS error_message;
+ALTER TABLE indexers DROP COLUMN IF EXISTS error_block;
+ALTER TABLE indexers DROP COLUMN IF EXISTS error_at;
@@ -0,0 +1,4 @@
+-- Details of the last error of the indexer
+ALTER TABLE indexers ADD COLUMN IF NOT EXISTS error_message varchar;
+ALTER TABLE indexers ADD COLUMN IF NOT EXISTS error_block bigint;
+ALTER TABLE indexers ADD COLUMN IF NOT EXISTS error_at timestamp;
@@ -0,0 +1,2 @@
+ALTER TABLE indexers DROP COLUMN IF EXISTS version;
+ALTER TABLE indexers DROP COLUMN IF EXISTS current;
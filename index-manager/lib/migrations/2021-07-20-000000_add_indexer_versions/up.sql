@@ -0,0 +1,4 @@
+-- Versions of the indexers with the same name, only the current one is served
+ALTER TABLE indexers ADD COLUMN IF NOT EXISTS version integer;
+ALTER TABLE indexers ADD COLUMN IF NOT EXISTS current boolean;
+UPDATE indexers SET version = 1, current = true WHERE version IS NULL;
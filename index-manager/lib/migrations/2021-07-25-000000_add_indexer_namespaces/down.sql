@@ -0,0 +1 @@
+ALTER TABLE indexers DROP COLUMN IF EXISTS namespace;
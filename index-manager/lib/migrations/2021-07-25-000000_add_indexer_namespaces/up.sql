@@ -0,0 +1,3 @@
+-- Postgres schema of the tables of the indexer, the indexers created before had their tables in sgd0
+ALTER TABLE indexers ADD COLUMN IF NOT EXISTS namespace varchar;
+UPDATE indexers SET namespace = 'sgd0' WHERE namespace IS NULL;
@@ -0,0 +1,12 @@
+-- Each deploy request, with the indexer it created or the errors which stopped it
+CREATE TABLE IF NOT EXISTS indexer_deployments
+(
+    id         varchar   NOT NULL PRIMARY KEY,
+    name       varchar,
+    indexer_id varchar,
+    status     varchar   NOT NULL,
+    errors     text,
+    created_at timestamp NOT NULL DEFAULT now(),
+    updated_at timestamp NOT NULL DEFAULT now()
+);
+CREATE INDEX IF NOT EXISTS indexer_deployments_indexer_idx ON indexer_deployments (indexer_id);
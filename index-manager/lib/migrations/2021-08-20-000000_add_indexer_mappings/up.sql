@@ -0,0 +1,2 @@
+-- File of the mapping in the folder of the indexer, once its mapping is replaced by index_update_mapping
+ALTER TABLE indexers ADD COLUMN IF NOT EXISTS mapping varchar;
@@ -1,6 +1,6 @@
-use crate::ipfs::read_config_file;
+use crate::ipfs::parse_config_file;
 use crate::type_index::IndexConfig;
-use adapter::core::AdapterManager;
+use adapter::core::{AdapterControl, AdapterManager, AdapterProgress, MappingUpdate};
 use std::error::Error;
 use std::fs::File;
 use std::io::Read;
@@ -14,20 +14,28 @@ use graph_chain_ethereum::{Chain, DataSource};
 use std::rc::Rc;
 use std::sync::Arc;
 use std::time::Instant;
+use tokio::sync::{mpsc, watch};
 
 pub async fn adapter_init(
     index_config: &IndexConfig,
     manifest: &Option<SubgraphManifest<Chain>>,
+    control: watch::Receiver<AdapterControl>,
+    progress: watch::Sender<AdapterProgress>,
+    mapping_updates: mpsc::Receiver<MappingUpdate>,
 ) -> Result<(), Box<dyn Error>> {
     log::info!("Load library from {:?}", &index_config.mapping);
-    let config_value = read_config_file(&index_config.config);
-    let mut adapter = AdapterManager::new();
+    let config_value = parse_config_file(&index_config.config)?;
+    let mut adapter = AdapterManager::new()
+        .with_control(control)
+        .with_progress(progress)
+        .with_mapping_updates(mapping_updates);
     //assert_eq!(manifest.data_sources.len(), 1);
 
-    println!("Index config {:?}", index_config);
+    log::debug!("Index config {:?}", index_config);
     adapter
         .init(
             &index_config.identifier.name_with_hash,
+            &index_config.namespace,
             &config_value,
             &index_config.mapping,
             &index_config.schema,
@@ -0,0 +1,336 @@
+/**
+ *** Objective of this file is to fetch the files of an index (config, mapping, schema, ABIs, subgraph manifest)
+ *** from the artifact source selected by the deploy request:
+ *** - `ipfs` (default) the files are IPFS hashes, see ipfs.rs
+ *** - `local` the files are paths, relative to ARTIFACT_LOCAL_FOLDER
+ *** - `http` the files are HTTP(S) URLs
+ *** Each file is verified against the sha256 given in the request, if any, before it is written to the generated folder
+ **/
+// Generic dependencies
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use reqwest::Client;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use tokio_compat_02::FutureExt;
+
+// Massbit dependencies
+use crate::ipfs::{cat_ipfs_file, get_index_folder};
+
+lazy_static! {
+    static ref ARTIFACT_LOCAL_FOLDER: String =
+        env::var("ARTIFACT_LOCAL_FOLDER").unwrap_or(String::from("."));
+    static ref ARTIFACT_HTTP_TIMEOUT: u64 = env::var("ARTIFACT_HTTP_TIMEOUT")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(60); // In seconds
+}
+
+#[async_trait]
+pub trait ArtifactSource: Send + Sync {
+    fn name(&self) -> &'static str;
+    /// Get the content of the file identified by `reference` (an IPFS hash, a path or an URL depending on the source)
+    async fn fetch(&self, reference: &str) -> Result<Vec<u8>, Error>;
+}
+
+pub struct IpfsSource {}
+
+#[async_trait]
+impl ArtifactSource for IpfsSource {
+    fn name(&self) -> &'static str {
+        "ipfs"
+    }
+    async fn fetch(&self, reference: &str) -> Result<Vec<u8>, Error> {
+        cat_ipfs_file(reference).await
+    }
+}
+
+// Read the files from a folder of the index-manager's host, used to deploy CI artifacts or in the integration tests
+pub struct LocalSource {
+    folder: PathBuf,
+}
+
+impl Default for LocalSource {
+    fn default() -> Self {
+        LocalSource {
+            folder: PathBuf::from(ARTIFACT_LOCAL_FOLDER.as_str()),
+        }
+    }
+}
+
+#[async_trait]
+impl ArtifactSource for LocalSource {
+    fn name(&self) -> &'static str {
+        "local"
+    }
+    async fn fetch(&self, reference: &str) -> Result<Vec<u8>, Error> {
+        // A request can't read the files outside of the folder
+        let relative = Path::new(reference);
+        if !is_relative_to_folder(relative) {
+            return Err(anyhow!("{} is not inside the local folder", reference));
+        }
+        let path = self.folder.join(relative);
+        fs::read(&path).map_err(|e| anyhow!("Cannot read {:?}: {}", path, e))
+    }
+}
+
+pub struct HttpSource {
+    timeout: Duration,
+}
+
+impl Default for HttpSource {
+    fn default() -> Self {
+        HttpSource {
+            timeout: Duration::from_secs(*ARTIFACT_HTTP_TIMEOUT),
+        }
+    }
+}
+
+#[async_trait]
+impl ArtifactSource for HttpSource {
+    fn name(&self) -> &'static str {
+        "http"
+    }
+    async fn fetch(&self, reference: &str) -> Result<Vec<u8>, Error> {
+        if !reference.starts_with("http://") && !reference.starts_with("https://") {
+            return Err(anyhow!("{} is not an HTTP(S) URL", reference));
+        }
+        let response = Client::builder()
+            .timeout(self.timeout)
+            .build()?
+            .get(reference)
+            .send()
+            .compat()
+            .await
+            .map_err(|e| anyhow!("Cannot download {}: {}", reference, e))?;
+        if !response.status().is_success() {
+            return Err(anyhow!(
+                "Cannot download {}: status {}",
+                reference,
+                response.status()
+            ));
+        }
+        let bytes = response
+            .bytes()
+            .compat()
+            .await
+            .map_err(|e| anyhow!("Cannot download {}: {}", reference, e))?;
+        Ok(bytes.to_vec())
+    }
+}
+
+// The source of the deploy request, IPFS when it is not given
+pub fn create_artifact_source(kind: Option<&String>) -> Result<Box<dyn ArtifactSource>, Error> {
+    match kind.map(|kind| kind.as_str()) {
+        None | Some("ipfs") => Ok(Box::new(IpfsSource {})),
+        Some("local") => Ok(Box::new(LocalSource::default())),
+        Some("http") => Ok(Box::new(HttpSource::default())),
+        Some(kind) => Err(anyhow!(
+            "Unknown source {}, expected ipfs, local or http",
+            kind
+        )),
+    }
+}
+
+// Whether the path stays inside the folder it is joined to
+fn is_relative_to_folder(path: &Path) -> bool {
+    !path.is_absolute()
+        && !path
+            .components()
+            .any(|component| component == Component::ParentDir)
+}
+
+/*******************************************************************************
+  ArtifactDownloader
+
+  Description:
+  Fetch the files of an index from a source, check their sha256 and write them
+  to the generated folder of the index
+*******************************************************************************/
+pub struct ArtifactDownloader {
+    source: Box<dyn ArtifactSource>,
+    checksums: HashMap<String, String>, // Expected sha256 (hex) of the content by reference
+    folder: Option<PathBuf>, // Parent of the index folders, the generated folder when it is not set
+}
+
+impl Default for ArtifactDownloader {
+    fn default() -> Self {
+        ArtifactDownloader {
+            source: Box::new(IpfsSource {}),
+            checksums: Default::default(),
+            folder: None,
+        }
+    }
+}
+
+impl ArtifactDownloader {
+    pub fn new(source: Box<dyn ArtifactSource>, checksums: HashMap<String, String>) -> Self {
+        ArtifactDownloader {
+            source,
+            checksums,
+            folder: None,
+        }
+    }
+
+    pub fn with_folder(mut self, folder: PathBuf) -> Self {
+        self.folder = Some(folder);
+        self
+    }
+
+    pub async fn download(
+        &self,
+        file_name: &String,
+        folder_name: &String,
+        reference: &String,
+    ) -> Result<PathBuf, Error> {
+        log::info!(
+            "Downloading {} from {} as {}",
+            reference,
+            self.source.name(),
+            file_name
+        );
+        // The file names of the ABIs come from the request, they can't write outside of the index folder
+        if !is_relative_to_folder(Path::new(file_name)) {
+            return Err(anyhow!("{} is not inside the index folder", file_name));
+        }
+        let file_bytes = self.source.fetch(reference).await?;
+        if let Some(expected) = self.checksums.get(reference) {
+            let actual = hex::encode(Sha256::digest(&file_bytes));
+            if !actual.eq_ignore_ascii_case(expected.trim_start_matches("sha256:")) {
+                return Err(anyhow!(
+                    "Checksum mismatch for {}: expected {}, got {}",
+                    reference,
+                    expected,
+                    actual
+                ));
+            }
+        }
+
+        let folder = match &self.folder {
+            Some(folder) => folder.join(folder_name),
+            None => get_index_folder(folder_name),
+        };
+        fs::create_dir_all(&folder)?;
+        let file_path = folder.join(file_name);
+        fs::write(&file_path, file_bytes)
+            .map_err(|e| anyhow!("Could not write {} to storage: {}", file_name, e))?;
+        log::info!("Write {:?} to storage successfully", file_path);
+        Ok(file_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::generate_random_hash;
+
+    // Folder of a single test, removed with everything in it when the test ends
+    struct TestFolder(PathBuf);
+
+    impl TestFolder {
+        fn new() -> Self {
+            let folder = env::temp_dir().join(format!("artifact_test_{}", generate_random_hash()));
+            fs::create_dir_all(folder.join("source")).unwrap();
+            fs::write(
+                folder.join("source").join("schema.graphql"),
+                "type Token @entity { id: ID! }",
+            )
+            .unwrap();
+            TestFolder(folder)
+        }
+
+        fn downloader(&self, checksums: &[(&str, &str)]) -> ArtifactDownloader {
+            let checksums = checksums
+                .iter()
+                .map(|(reference, checksum)| (reference.to_string(), checksum.to_string()))
+                .collect();
+            let source = LocalSource {
+                folder: self.0.join("source"),
+            };
+            ArtifactDownloader::new(Box::new(source), checksums)
+                .with_folder(self.0.join("generated"))
+        }
+    }
+
+    impl Drop for TestFolder {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn sha256_mismatch_is_rejected() {
+        let folder = TestFolder::new();
+        let checksum = format!("sha256:{}", hex::encode(Sha256::digest(b"another schema")));
+        let downloader = folder.downloader(&[("schema.graphql", &checksum)]);
+        let err = downloader
+            .download(
+                &String::from("schema.graphql"),
+                &String::from("index"),
+                &String::from("schema.graphql"),
+            )
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Checksum mismatch for schema.graphql"));
+        assert!(!folder.0.join("generated/index/schema.graphql").exists());
+    }
+
+    #[tokio::test]
+    async fn file_matching_its_sha256_is_written() {
+        let folder = TestFolder::new();
+        let checksum = hex::encode(Sha256::digest(b"type Token @entity { id: ID! }"));
+        let downloader = folder.downloader(&[("schema.graphql", &checksum)]);
+        let path = downloader
+            .download(
+                &String::from("schema.graphql"),
+                &String::from("index"),
+                &String::from("schema.graphql"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(path, folder.0.join("generated/index/schema.graphql"));
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "type Token @entity { id: ID! }"
+        );
+    }
+
+    #[tokio::test]
+    async fn file_is_not_written_outside_of_the_index_folder() {
+        let folder = TestFolder::new();
+        let downloader = folder.downloader(&[]);
+        for file_name in &["../schema.graphql", "/tmp/schema.graphql"] {
+            let err = downloader
+                .download(
+                    &file_name.to_string(),
+                    &String::from("index"),
+                    &String::from("schema.graphql"),
+                )
+                .await
+                .unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("{} is not inside the index folder", file_name)
+            );
+        }
+        assert!(!folder.0.join("generated/schema.graphql").exists());
+    }
+
+    #[tokio::test]
+    async fn local_source_does_not_read_outside_of_its_folder() {
+        let folder = TestFolder::new();
+        let source = LocalSource {
+            folder: folder.0.join("source"),
+        };
+        assert!(source.fetch("schema.graphql").await.is_ok());
+        assert!(source.fetch("../source/schema.graphql").await.is_err());
+        assert!(source.fetch("/etc/hostname").await.is_err());
+    }
+}
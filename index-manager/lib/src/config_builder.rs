@@ -1,29 +1,26 @@
 /**
  *** Objective of this file, is to build the IndexConfig from the user's Index Request
- *** It will connect to IPFS to get the files and save them to storage
+ *** It will get the files from the artifact source of the request (IPFS by default) and save them to storage
  **/
 // Generic dependencies
 use std::path::PathBuf;
-use lazy_static::lazy_static;
 use serde::Deserialize;
 
 // Massbit dependencies
 use crate::config::{
-    generate_mapping_name_and_type, generate_random_hash, get_index_name, get_mapping_language,
+    generate_mapping_name_and_type, generate_random_hash, get_mapping_language,
 };
-use crate::ipfs::{download_ipfs_file_by_hash, read_config_file};
-use crate::type_index::{IndexConfig, IndexIdentifier, Abi};
+use crate::artifact::{create_artifact_source, ArtifactDownloader};
+use crate::ipfs::{get_index_folder, parse_config_file};
+use crate::type_index::{Abi, DeployError, IndexConfig, IndexIdentifier};
 use crate::type_request::{DeployAbi, DeployParams};
+use std::error::Error;
 use std::fs;
 use serde_yaml::{Value};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::Read;
 
-lazy_static! {
-    static ref GENERATED_FOLDER: String = String::from("index-manager/generated");
-}
-
 /**
  *** Builder Pattern
  *** Real example: https://github.com/graphprotocol/rust-web3/blob/3aac17f719b99494793111fd00a4505fe4670ca2/src/types/log.rs#L103
@@ -39,71 +36,130 @@ lazy_static! {
   IndexConfigIpfsBuilder
 
   Description:
-  To build the index config based on the config from IPFS
+  To build the index config based on the files from IPFS, or from the local
+  folder / HTTP(S) URLs when another source is set
 *******************************************************************************/
 pub struct IndexConfigIpfsBuilder {
+    name: String,
     schema: PathBuf,
     config: PathBuf,
     mapping: PathBuf,
     abi: Vec<Abi>,
     hash: String,
     subgraph: PathBuf,
+    downloader: ArtifactDownloader,
+    errors: Vec<DeployError>, // Every file that could not be downloaded, so the user can fix them all at once
 }
 
 impl Default for IndexConfigIpfsBuilder {
     fn default() -> IndexConfigIpfsBuilder {
         IndexConfigIpfsBuilder {
+            name: Default::default(),
             schema: Default::default(),
             config: Default::default(),
             mapping: Default::default(),
             abi: Default::default(),
             hash: generate_random_hash(),
             subgraph: Default::default(),
+            downloader: Default::default(),
+            errors: Default::default(),
         }
     }
 }
 
 impl IndexConfigIpfsBuilder {
-    // Call to IPFS and download mapping to local storage
+    // The versions of an index are the deploys with the same name, see IndexStore::insert_new_indexer
+    pub fn name(mut self, name: &String) -> IndexConfigIpfsBuilder {
+        if is_valid_index_name(name) {
+            self.name = name.clone();
+        } else {
+            self.errors.push(DeployError::new(
+                "name",
+                format!("Invalid index name `{}`, use letters, digits, - and _", name),
+            ));
+        }
+        self
+    }
+
+    // Select where the next files are fetched from and the sha256 they must match
+    pub fn source(
+        mut self,
+        source: &Option<String>,
+        checksums: &Option<HashMap<String, String>>,
+    ) -> IndexConfigIpfsBuilder {
+        match create_artifact_source(source.as_ref()) {
+            Ok(artifact_source) => {
+                self.downloader =
+                    ArtifactDownloader::new(artifact_source, checksums.clone().unwrap_or_default())
+            }
+            Err(e) => self.errors.push(DeployError::new("source", e)),
+        }
+        self
+    }
+
+    // Download mapping to local storage
     // Mapping file type is decided by the self.config value
     pub async fn mapping(mut self, mapping: &String) -> IndexConfigIpfsBuilder {
-        assert_eq!(
-            self.config.as_os_str().is_empty(),
-            false,
-            "Config should be provided before mapping and schema"
-        );
-        let config_value = read_config_file(&self.config);
+        if self.config.as_os_str().is_empty() {
+            // The config could not be downloaded, the error is already recorded
+            return self;
+        }
+        let config_value = match parse_config_file(&self.config) {
+            Ok(value) => value,
+            Err(e) => {
+                self.errors.push(DeployError::new("config", e));
+                return self;
+            }
+        };
+        if config_value["dataSources"][0]["mapping"]["language"].as_str().is_none() {
+            self.errors.push(DeployError::new(
+                "config",
+                "dataSources[0].mapping.language is missing",
+            ));
+            return self;
+        }
         let file_name = generate_mapping_name_and_type(&config_value);
-        self.mapping = download_ipfs_file_by_hash(&file_name, &self.hash, mapping).await;
+        match self.downloader.download(&file_name, &self.hash, mapping).await {
+            Ok(path) => self.mapping = path,
+            Err(e) => self.errors.push(DeployError::new("mapping", e)),
+        }
         self
     }
 
-    // Call to IPFS and download config to local storage
+    // Download config to local storage
     pub async fn config(mut self, config: &String) -> IndexConfigIpfsBuilder {
-        self.config = download_ipfs_file_by_hash(
-            &String::from("project.yaml"),
-            &self.hash,
-            config,
-        ).await;
-
+        match self.downloader.download(&String::from("project.yaml"), &self.hash, config).await {
+            Ok(path) => self.config = path,
+            Err(e) => self.errors.push(DeployError::new("config", e)),
+        }
         self
     }
 
-    // Call to IPFS and download schema to local storage
+    // Download schema to local storage
     pub async fn schema(mut self, schema: &String) -> IndexConfigIpfsBuilder {
-        self.schema = download_ipfs_file_by_hash(
-            &String::from("schema.graphql"),
-            &self.hash,
-            schema,
-        ).await;
+        match self.downloader.download(&String::from("schema.graphql"), &self.hash, schema).await
+        {
+            Ok(path) => self.schema = path,
+            Err(e) => self.errors.push(DeployError::new("schema", e)),
+        }
         self
     }
 
-    // Call to IPFS and download ABIs to local storage
+    // Download ABIs to local storage
     pub async fn abi(mut self, abi: Option<Vec<DeployAbi>>) -> IndexConfigIpfsBuilder {
         match abi {
             Some(v) => {
-                self.abi = build_abi(v, &self.hash).await;
+                for deploy_abi in v {
+                    match self.downloader.download(&deploy_abi.name, &self.hash, &deploy_abi.hash)
+                        .await
+                    {
+                        Ok(path) => self.abi.push(Abi {
+                            name: deploy_abi.name.clone(),
+                            path,
+                        }),
+                        Err(e) => self.errors.push(DeployError::new("abi", e)),
+                    }
+                }
                 self
             }
             None => {
@@ -117,11 +173,10 @@ impl IndexConfigIpfsBuilder {
     pub async fn subgraph(mut self, subgraph: &Option<String>) -> IndexConfigIpfsBuilder {
         match subgraph {
             Some(v) => {
-                self.subgraph = download_ipfs_file_by_hash(
-                    &String::from("subgraph.yaml"),
-                    &self.hash,
-                    v,
-                ).await;
+                match self.downloader.download(&String::from("subgraph.yaml"), &self.hash, v).await {
+                    Ok(path) => self.subgraph = path,
+                    Err(e) => self.errors.push(DeployError::new("subgraph", e)),
+                }
                 self
             }
             None => {
@@ -132,22 +187,31 @@ impl IndexConfigIpfsBuilder {
         }
     }
 
-    pub fn build(self) -> IndexConfig {
-        let config = read_config_file(&self.config);
-        let name = get_index_name(&config);
-
-        IndexConfig {
+    // Return every download error instead of a config that can't be indexed
+    pub fn build(self) -> Result<IndexConfig, Vec<DeployError>> {
+        if !self.errors.is_empty() {
+            // Nothing is deployed, the downloaded files are not needed anymore
+            let folder = get_index_folder(&self.hash);
+            if folder.exists() {
+                if let Err(e) = fs::remove_dir_all(&folder) {
+                    log::warn!("Cannot remove folder {:?}: {}", &folder, e);
+                }
+            }
+            return Err(self.errors);
+        }
+        Ok(IndexConfig {
             schema: self.schema,
             config: self.config,
             mapping: self.mapping,
             abi: Option::Some(self.abi),
             subgraph: self.subgraph,
+            namespace: Default::default(),
             identifier: IndexIdentifier {
-                name: name.clone(),
+                name: self.name.clone(),
                 hash: self.hash.clone(),
-                name_with_hash: format!("{}-{}", name, self.hash),
+                name_with_hash: format!("{}-{}", self.name, self.hash),
             },
-        }
+        })
     }
 }
 
@@ -155,75 +219,104 @@ impl IndexConfigIpfsBuilder {
   IndexConfigLocalBuilder
 
   Description:
-  To build the index config based on the name and hash from the indexers table
+  To build the index config based on the files that were downloaded to
+  the generated folder when the index was deployed
 *******************************************************************************/
 impl Default for IndexConfigLocalBuilder {
     fn default() -> IndexConfigLocalBuilder {
         IndexConfigLocalBuilder {
+            name: Default::default(),
             schema: Default::default(),
             config: Default::default(),
             mapping: Default::default(),
-            hash: generate_random_hash(),
+            subgraph: Default::default(),
+            hash: Default::default(),
+            errors: Default::default(),
         }
     }
 }
 
 pub struct IndexConfigLocalBuilder {
+    name: String,
     schema: PathBuf,
     config: PathBuf,
     mapping: PathBuf,
+    subgraph: PathBuf,
     hash: String,
+    errors: Vec<String>, // Returned by build, so an index with broken files is skipped instead of crashing the restart
 }
 
 impl IndexConfigLocalBuilder {
-    pub async fn mapping(mut self, name: &String) -> IndexConfigLocalBuilder {
-        let mapping = [GENERATED_FOLDER.as_str(), name, ".so"].join("");
-        self.mapping = PathBuf::from(mapping.to_string());
+    // The name the index was deployed with, kept in the indexers table
+    pub fn name(mut self, name: &String) -> IndexConfigLocalBuilder {
+        self.name = name.clone();
         self
     }
 
-    pub async fn config(mut self, name: &String) -> IndexConfigLocalBuilder {
-        let config = [GENERATED_FOLDER.as_str(), name, ".yaml"].join("");
-        self.config = PathBuf::from(config);
+    // Mapping file type is decided by the self.config value
+    pub async fn mapping(mut self, hash: &String) -> IndexConfigLocalBuilder {
+        if self.config.as_os_str().is_empty() {
+            self.errors.push(String::from("Config should be provided before mapping"));
+            return self;
+        }
+        match parse_config_file(&self.config) {
+            Ok(config_value) if config_value["dataSources"][0]["mapping"]["language"].as_str().is_some() => {
+                let file_name = generate_mapping_name_and_type(&config_value);
+                self.mapping = get_index_folder(hash).join(file_name);
+            }
+            Ok(_) => self.errors.push(String::from("dataSources[0].mapping.language is missing")),
+            Err(e) => self.errors.push(e.to_string()),
+        }
         self
     }
 
-    pub async fn schema(mut self, name: &String) -> IndexConfigLocalBuilder {
-        let schema = [GENERATED_FOLDER.as_str(), name, ".graphql"].join("");
-        self.schema = PathBuf::from(schema);
+    pub async fn config(mut self, hash: &String) -> IndexConfigLocalBuilder {
+        self.config = get_index_folder(hash).join("project.yaml");
+        self.hash = hash.clone();
+        self
+    }
+
+    pub async fn schema(mut self, hash: &String) -> IndexConfigLocalBuilder {
+        self.schema = get_index_folder(hash).join("schema.graphql");
+        self
+    }
+
+    // .SO mapping doesn't have subgraph.yaml
+    pub async fn subgraph(mut self, hash: &String) -> IndexConfigLocalBuilder {
+        let subgraph = get_index_folder(hash).join("subgraph.yaml");
+        if subgraph.exists() {
+            self.subgraph = subgraph;
+        }
         self
     }
 
-    pub fn build(self) -> IndexConfig {
-        IndexConfig {
+    pub fn build(self) -> Result<IndexConfig, Box<dyn Error>> {
+        if !self.errors.is_empty() {
+            return Err(self.errors.join(", ").into());
+        }
+        let name = self.name.clone();
+
+        Ok(IndexConfig {
             schema: self.schema,
             config: self.config,
             mapping: self.mapping,
-            // TODO: Add logic for these type so we can restart indexers if needed
+            // ABIs are resolved again from the subgraph manifest, so we don't need them to restart an index
             abi: Default::default(),
-            subgraph: Default::default(),
+            subgraph: self.subgraph,
+            namespace: Default::default(),
             identifier: IndexIdentifier {
-                // TODO: populate with the value from the indexer query result
-                name: Default::default(),
-                hash: Default::default(),
-                name_with_hash: Default::default(),
+                name: name.clone(),
+                hash: self.hash.clone(),
+                name_with_hash: format!("{}-{}", name, self.hash),
             },
-        }
+        })
     }
 }
 
-
-/******** Helper Functions **********/
-// Build a new ABI struct from DeployABI
-// Call to IPFS to and save the ABI files to local storage
-async fn build_abi(abi_list: Vec<DeployAbi>, folder_name: &String) -> Vec<Abi>{
-    let mut new_abi_list: Vec<Abi> = vec![];
-    for deploy_abi in abi_list {
-        let abi = Abi {
-            name: deploy_abi.name.clone(),
-            path: download_ipfs_file_by_hash(&deploy_abi.name, folder_name, &deploy_abi.hash).await
-        };
-        new_abi_list.push(abi);
-    }
-    new_abi_list
-}
\ No newline at end of file
+// The name is part of the id of the indexer and of its folder
+fn is_valid_index_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
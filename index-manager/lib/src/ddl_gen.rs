@@ -10,5 +10,8 @@ pub async fn run_ddl_gen(index_config: &IndexConfig) {
     );
 
     // Track the newly created tables in hasura
-    track_hasura_with_ddl_gen_plugin(&index_config.identifier.name_with_hash).await;
+    if let Err(e) = track_hasura_with_ddl_gen_plugin(&index_config.identifier.name_with_hash).await
+    {
+        log::error!("Cannot track the tables in hasura: {}", e);
+    }
 }
@@ -1,52 +1,185 @@
-use lazy_static::lazy_static;
 /**
 *** Objective of this file is to provide API to call to hasura
+*** The queries of a bulk are sent one by one so the tables or relationships hasura already knows
+*** don't fail the others, hasura is retried while it is unreachable
 **/
 // Generic dependencies
+use anyhow::{anyhow, Error};
+use async_trait::async_trait;
+use lazy_static::lazy_static;
 use reqwest::Client;
 use serde_json::{json, Value};
 use std::env;
+use std::path::Path;
+use std::time::Duration;
 use tokio_compat_02::FutureExt;
 
 // Massbit dependencies
 use crate::hasura_helper::{
     assert_no_duplicated_index, get_hasura_payload, get_hasura_payload_folder,
 };
+use crate::index_manager_helper::get_namespace;
+use crate::query_layer::QueryLayer;
+use crate::type_index::Indexer;
+use index_store::postgres::relational::LayoutExt;
+use index_store::postgres::store_builder::StoreBuilder;
 
 lazy_static! {
     static ref HASURA_URL: String =
         env::var("HASURA_URL").unwrap_or(String::from("http://localhost:8080/v1/query"));
+    static ref HASURA_RETRIES: u32 = env::var("HASURA_RETRIES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(5);
 }
 
-pub async fn track_hasura_by_table(table_name: &String) {
-    let body = json!({
-        "type": "track_table",
-        "args": {
-            "schema": "public",
-            "name": table_name.to_lowercase(),
+// Error codes of hasura telling that the table or relationship is already in the expected state
+const TRACKED_CODES: [&str; 2] = ["already-tracked", "already-exists"];
+const UNTRACKED_CODES: [&str; 2] = ["already-untracked", "not-exists"];
+
+pub struct HasuraQueryLayer {
+    url: String,
+    retries: u32,
+}
+
+impl Default for HasuraQueryLayer {
+    fn default() -> Self {
+        HasuraQueryLayer {
+            url: HASURA_URL.clone(),
+            retries: *HASURA_RETRIES,
         }
-    });
-    Client::new()
-        .post(&*HASURA_URL)
-        .json(&body)
-        .send()
-        .compat()
-        .await
-        .unwrap();
+    }
+}
+
+#[async_trait]
+impl QueryLayer for HasuraQueryLayer {
+    fn name(&self) -> &'static str {
+        "hasura"
+    }
+
+    // Track the tables then the relationships created from the index's schema in its postgres schema
+    async fn track(&self, indexer: &Indexer, schema: &Path) -> Result<(), Error> {
+        let (track_tables, track_relationships) = {
+            let layout = StoreBuilder::create_layout(&get_namespace(indexer), schema)?;
+            let graphql_schema = StoreBuilder::load_schema(schema)?;
+            let (track_tables, _) = layout.create_hasura_tracking_tables();
+            let (track_relationships, _) =
+                layout.create_hasura_tracking_relationships(&graphql_schema);
+            (track_tables, track_relationships)
+        };
+        self.run_bulk(&track_tables, &TRACKED_CODES).await?;
+        self.run_bulk(&track_relationships, &TRACKED_CODES).await?;
+        log::info!("[Hasura] Tracked the tables of indexer {}", &indexer.id);
+        Ok(())
+    }
+
+    // Untrack the relationships then the tables created from the index's schema
+    async fn untrack(&self, indexer: &Indexer, schema: &Path) -> Result<(), Error> {
+        let (untrack_relationships, untrack_tables) = {
+            let layout = StoreBuilder::create_layout(&get_namespace(indexer), schema)?;
+            let graphql_schema = StoreBuilder::load_schema(schema)?;
+            let (_, untrack_relationships) =
+                layout.create_hasura_tracking_relationships(&graphql_schema);
+            let (_, untrack_tables) = layout.create_hasura_tracking_tables();
+            (untrack_relationships, untrack_tables)
+        };
+        self.run_bulk(&untrack_relationships, &UNTRACKED_CODES)
+            .await?;
+        self.run_bulk(&untrack_tables, &UNTRACKED_CODES).await?;
+        log::info!("[Hasura] Untracked the tables of indexer {}", &indexer.id);
+        Ok(())
+    }
+}
+
+impl HasuraQueryLayer {
+    // Run the queries of a bulk one by one, the errors with one of the `accepted_codes` are ignored
+    async fn run_bulk(&self, bulk: &Value, accepted_codes: &[&str]) -> Result<(), Error> {
+        let queries = match bulk["args"].as_array() {
+            Some(queries) => queries.clone(),
+            None => vec![bulk.clone()],
+        };
+        for query in queries.iter() {
+            self.run(query, accepted_codes).await?;
+        }
+        Ok(())
+    }
+
+    // Send a query to hasura, retry with a growing delay while hasura is unreachable or fails internally
+    pub async fn run(&self, query: &Value, accepted_codes: &[&str]) -> Result<(), Error> {
+        let mut delay = Duration::from_millis(500);
+        let mut attempt = 1;
+        loop {
+            let error = match Client::new()
+                .post(&self.url)
+                .json(query)
+                .send()
+                .compat()
+                .await
+            {
+                Ok(response) if response.status().is_success() => return Ok(()),
+                Ok(response) if response.status().is_client_error() => {
+                    let status = response.status();
+                    let body = response.text().compat().await.unwrap_or_default();
+                    let code = serde_json::from_str::<Value>(&body)
+                        .ok()
+                        .and_then(|body| body["code"].as_str().map(String::from))
+                        .unwrap_or_default();
+                    if accepted_codes.contains(&code.as_str()) {
+                        return Ok(());
+                    }
+                    return Err(anyhow!(
+                        "Hasura rejected {}: {} {}",
+                        query["type"],
+                        status,
+                        body
+                    ));
+                }
+                Ok(response) => format!("status {}", response.status()),
+                Err(e) => e.to_string(),
+            };
+            if attempt >= self.retries {
+                return Err(anyhow!(
+                    "Hasura failed {} after {} attempts: {}",
+                    query["type"],
+                    attempt,
+                    error
+                ));
+            }
+            log::warn!(
+                "[Hasura] {} failed on attempt {}: {}, retry in {:?}",
+                query["type"],
+                attempt,
+                error,
+                delay
+            );
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+            attempt += 1;
+        }
+    }
 }
 
 // The payload of plugin is handled by ddl gen plugin
-pub async fn track_hasura_with_ddl_gen_plugin(index_name: &String) {
+pub async fn track_hasura_with_ddl_gen_plugin(index_name: &String) -> Result<(), Error> {
     log::info!("Running plugin hasura");
     assert_no_duplicated_index(&index_name);
     let folder = get_hasura_payload_folder(&index_name);
     let payload = get_hasura_payload(&folder);
-    let v: Value = serde_json::from_str(&payload).unwrap();
-    Client::new()
-        .post(&*HASURA_URL)
-        .json(&v)
-        .send()
-        .compat()
+    let bulk: Value = serde_json::from_str(&payload)?;
+    HasuraQueryLayer::default()
+        .run_bulk(&bulk, &TRACKED_CODES)
+        .await
+}
+
+pub async fn track_hasura_by_table(table_name: &String) -> Result<(), Error> {
+    let query = json!({
+        "type": "track_table",
+        "args": {
+            "schema": "public",
+            "name": table_name.to_lowercase(),
+        }
+    });
+    HasuraQueryLayer::default()
+        .run(&query, &TRACKED_CODES)
         .await
-        .unwrap();
 }
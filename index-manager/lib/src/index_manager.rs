@@ -12,15 +12,18 @@ use jsonrpc_http_server::{
     jsonrpc_core::{Compatibility, IoHandler, Params, Value},
     ServerBuilder,
 };
+use std::error::Error;
 
 // Massbit dependencies
 use crate::adapter::adapter_init;
 use crate::config_builder::IndexConfigIpfsBuilder;
 use crate::index_manager_helper::{
-    list_handler_helper, restart_all_existing_index_helper, start_new_index,
+    delete_index_helper, deploy_index_helper, deploy_status_helper, index_status_helper,
+    list_handler_helper, pause_index_helper, restart_all_existing_index_helper,
+    restart_index_helper, resume_index_helper, stop_index_helper, update_mapping_helper,
 };
-use crate::type_index::IndexStore;
-use crate::type_request::DeployParams;
+use crate::type_index::{IndexStore, InvalidRequest};
+use crate::type_request::{DeployParams, IndexParams, UpdateMappingParams};
 use tokio02_spawn::core::abort_on_panic;
 use tokio02_spawn::core::tokio02_spawn;
 
@@ -41,6 +44,14 @@ impl IndexManager {
         }));
         let sender_deploy = task_sender.clone();
         let sender_list = task_sender.clone();
+        let sender_stop = task_sender.clone();
+        let sender_pause = task_sender.clone();
+        let sender_resume = task_sender.clone();
+        let sender_restart = task_sender.clone();
+        let sender_delete = task_sender.clone();
+        let sender_status = task_sender.clone();
+        let sender_deploy_status = task_sender.clone();
+        let sender_update_mapping = task_sender.clone();
 
         handler.add_method("index_list", move |_| {
             Box::pin(tokio02_spawn(
@@ -54,7 +65,7 @@ impl IndexManager {
             Box::pin(tokio02_spawn(
                 sender_deploy.clone(),
                 async move {
-                    let params = params.parse().unwrap();
+                    let params = params.parse()?;
                     deploy_handler(params).await
                 }
                 .boxed(),
@@ -62,6 +73,102 @@ impl IndexManager {
             .compat()
         });
 
+        handler.add_method("index_stop", move |params: Params| {
+            Box::pin(tokio02_spawn(
+                sender_stop.clone(),
+                async move {
+                    let params = params.parse()?;
+                    stop_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        handler.add_method("index_pause", move |params: Params| {
+            Box::pin(tokio02_spawn(
+                sender_pause.clone(),
+                async move {
+                    let params = params.parse()?;
+                    pause_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        handler.add_method("index_resume", move |params: Params| {
+            Box::pin(tokio02_spawn(
+                sender_resume.clone(),
+                async move {
+                    let params = params.parse()?;
+                    resume_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        handler.add_method("index_restart", move |params: Params| {
+            Box::pin(tokio02_spawn(
+                sender_restart.clone(),
+                async move {
+                    let params = params.parse()?;
+                    restart_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        handler.add_method("index_delete", move |params: Params| {
+            Box::pin(tokio02_spawn(
+                sender_delete.clone(),
+                async move {
+                    let params = params.parse()?;
+                    delete_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        handler.add_method("index_status", move |params: Params| {
+            Box::pin(tokio02_spawn(
+                sender_status.clone(),
+                async move {
+                    let params = params.parse()?;
+                    status_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        handler.add_method("index_deploy_status", move |params: Params| {
+            Box::pin(tokio02_spawn(
+                sender_deploy_status.clone(),
+                async move {
+                    let params = params.parse()?;
+                    deploy_status_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
+        handler.add_method("index_update_mapping", move |params: Params| {
+            Box::pin(tokio02_spawn(
+                sender_update_mapping.clone(),
+                async move {
+                    let params = params.parse()?;
+                    update_mapping_handler(params).await
+                }
+                .boxed(),
+            ))
+            .compat()
+        });
+
         // Start the server
         let server = ServerBuilder::new(handler)
             .start_http(&http_addr.parse().unwrap())
@@ -75,15 +182,92 @@ impl IndexManager {
     }
 }
 
+// Invalid params for a request naming an unknown indexer or which it can't fulfil, internal error otherwise
+fn to_rpc_error(e: Box<dyn Error>) -> jsonrpc_core::Error {
+    match e.downcast_ref::<InvalidRequest>() {
+        Some(e) => jsonrpc_core::Error::invalid_params(e.to_string()),
+        None => {
+            let mut error = jsonrpc_core::Error::internal_error();
+            error.message = e.to_string();
+            error
+        }
+    }
+}
+
+// The deploy goes on in the background, the deployment returned is followed with index_deploy_status
 async fn deploy_handler(params: DeployParams) -> Result<Value, jsonrpc_core::Error> {
-    #[allow(unused_must_use)]
-    tokio::spawn(async move {
-        start_new_index(params).await; // Start streaming and indexing blocks
-    });
-    Ok(serde_json::to_value("Deploy index success").expect("Unable to deploy new index"))
+    match deploy_index_helper(params).await {
+        Ok(deployment) => Ok(serde_json::to_value(deployment).expect("Unable to deploy new index")),
+        Err(e) => Err(to_rpc_error(e)),
+    }
+}
+
+// Every problem found in the index is listed in the errors of the deployment
+async fn deploy_status_handler(params: IndexParams) -> Result<Value, jsonrpc_core::Error> {
+    match deploy_status_helper(&params.id).await {
+        Ok(deployment) => {
+            Ok(serde_json::to_value(deployment).expect("Unable to get deployment status"))
+        }
+        Err(e) => Err(to_rpc_error(e)),
+    }
 }
 
 async fn list_handler() -> Result<Value, jsonrpc_core::Error> {
-    let indexers = list_handler_helper().await.unwrap();
-    Ok(serde_json::to_value(indexers).expect("Unable to get index list"))
+    match list_handler_helper().await {
+        Ok(indexers) => Ok(serde_json::to_value(indexers).expect("Unable to get index list")),
+        Err(e) => Err(to_rpc_error(e)),
+    }
+}
+
+async fn stop_handler(params: IndexParams) -> Result<Value, jsonrpc_core::Error> {
+    match stop_index_helper(&params.id).await {
+        Ok(_) => Ok(serde_json::to_value("Stop index success").expect("Unable to stop index")),
+        Err(e) => Err(to_rpc_error(e)),
+    }
+}
+
+async fn pause_handler(params: IndexParams) -> Result<Value, jsonrpc_core::Error> {
+    match pause_index_helper(&params.id).await {
+        Ok(_) => Ok(serde_json::to_value("Pause index success").expect("Unable to pause index")),
+        Err(e) => Err(to_rpc_error(e)),
+    }
+}
+
+async fn resume_handler(params: IndexParams) -> Result<Value, jsonrpc_core::Error> {
+    match resume_index_helper(&params.id).await {
+        Ok(_) => Ok(serde_json::to_value("Resume index success").expect("Unable to resume index")),
+        Err(e) => Err(to_rpc_error(e)),
+    }
+}
+
+async fn restart_handler(params: IndexParams) -> Result<Value, jsonrpc_core::Error> {
+    match restart_index_helper(&params.id).await {
+        Ok(_) => {
+            Ok(serde_json::to_value("Restart index success").expect("Unable to restart index"))
+        }
+        Err(e) => Err(to_rpc_error(e)),
+    }
+}
+
+async fn delete_handler(params: IndexParams) -> Result<Value, jsonrpc_core::Error> {
+    match delete_index_helper(&params.id).await {
+        Ok(_) => Ok(serde_json::to_value("Delete index success").expect("Unable to delete index")),
+        Err(e) => Err(to_rpc_error(e)),
+    }
+}
+
+async fn update_mapping_handler(params: UpdateMappingParams) -> Result<Value, jsonrpc_core::Error> {
+    match update_mapping_helper(params).await {
+        Ok(_) => {
+            Ok(serde_json::to_value("Update mapping success").expect("Unable to update mapping"))
+        }
+        Err(e) => Err(to_rpc_error(e)),
+    }
+}
+
+async fn status_handler(params: IndexParams) -> Result<Value, jsonrpc_core::Error> {
+    match index_status_helper(&params.id).await {
+        Ok(status) => Ok(serde_json::to_value(status).expect("Unable to get index status")),
+        Err(e) => Err(to_rpc_error(e)),
+    }
 }
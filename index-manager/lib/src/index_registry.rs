@@ -0,0 +1,381 @@
+/**
+ *** Objective of this file is to keep track of the running indexer tasks
+ *** so we can stop, pause, resume and restart them from the API
+ **/
+// Generic dependencies
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+// Massbit dependencies
+use crate::adapter::adapter_init;
+use crate::index_manager_helper::promote_index_version;
+use crate::type_index::{
+    DeployError, IndexConfig, IndexError, IndexStatus, IndexStore, InvalidRequest,
+};
+use adapter::core::{AdapterControl, AdapterProgress, MappingUpdate};
+
+// Graph dependencies
+use graph::data::subgraph::SubgraphManifest;
+use graph_chain_ethereum::Chain;
+
+// An indexer within this number of blocks from the chain head is considered synced
+const SYNCED_BLOCK_LAG: u64 = 5;
+
+// Tells apart the tasks of the same indexer, so a finished task never removes the one which restarted it
+static NEXT_TASK_ID: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    static ref INDEXER_TASKS: Mutex<HashMap<String, IndexerTask>> = Mutex::new(HashMap::new());
+    // Time given to an indexer to commit its pending blocks when it is stopped, before its task is aborted
+    static ref INDEXER_STOP_TIMEOUT: Duration = Duration::from_secs(
+        env::var("INDEXER_STOP_TIMEOUT")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(60)
+    );
+}
+
+// A running indexer and the handles to control it
+pub struct IndexerTask {
+    id: u64,
+    pub index_config: IndexConfig,
+    manifest: Arc<Option<SubgraphManifest<Chain>>>,
+    control: watch::Sender<AdapterControl>,
+    progress: watch::Receiver<AdapterProgress>,
+    mapping_updates: mpsc::Sender<MappingUpdate>,
+    handle: JoinHandle<()>,
+}
+
+pub struct IndexRegistry {}
+
+impl IndexRegistry {
+    // Start the adapter of an indexer in a new task and keep its handles
+    pub fn spawn(index_config: IndexConfig, manifest: Option<SubgraphManifest<Chain>>) {
+        IndexRegistry::spawn_task(index_config, Arc::new(manifest));
+    }
+
+    fn spawn_task(index_config: IndexConfig, manifest: Arc<Option<SubgraphManifest<Chain>>>) {
+        let id = index_config.identifier.name_with_hash.clone();
+        let (control, receiver) = watch::channel(AdapterControl::Running);
+        let (progress_sender, progress) = watch::channel(AdapterProgress::default());
+        let (mapping_updates, mapping_receiver) = mpsc::channel(1);
+        let config = index_config.clone();
+        let task_manifest = manifest.clone();
+        let task_progress = progress.clone();
+        let task_id = NEXT_TASK_ID.fetch_add(1, Ordering::Relaxed);
+        // The task removes itself from the registry when it ends, it waits for its insertion below
+        let mut tasks = INDEXER_TASKS.lock().unwrap();
+        let handle = tokio::spawn(async move {
+            let status_listener = tokio::spawn(listen_progress(
+                config.identifier.name_with_hash.clone(),
+                task_progress.clone(),
+            ));
+            let result = adapter_init(
+                &config,
+                &*task_manifest,
+                receiver,
+                progress_sender,
+                mapping_receiver,
+            )
+            .await;
+            status_listener.abort();
+            match result {
+                Ok(_) => log::info!("Indexer {} stopped", &config.identifier.name_with_hash),
+                Err(err) => {
+                    log::error!(
+                        "Indexer {} stopped with error {:?}",
+                        &config.identifier.name_with_hash,
+                        err
+                    );
+                    let error = IndexError {
+                        message: err.to_string(),
+                        block_number: task_progress.borrow().block_number,
+                        timestamp: SystemTime::now(),
+                    };
+                    if let Err(e) =
+                        IndexStore::update_indexer_error(&config.identifier.name_with_hash, &error)
+                    {
+                        log::warn!(
+                            "Cannot save the error of indexer {}: {}",
+                            &config.identifier.name_with_hash,
+                            e
+                        );
+                    }
+                    // The deployment fails if the mapping could not be loaded or the stream could not start
+                    IndexStore::finish_deployment_of_indexer(
+                        &config.identifier.name_with_hash,
+                        Some(&vec![DeployError::new("mapping", &error.message)]),
+                    );
+                }
+            }
+            // The indexer is not running anymore, its status comes from the database
+            let mut tasks = INDEXER_TASKS.lock().unwrap();
+            if tasks
+                .get(&config.identifier.name_with_hash)
+                .map_or(false, |task| task.id == task_id)
+            {
+                tasks.remove(&config.identifier.name_with_hash);
+            }
+        });
+        let task = IndexerTask {
+            id: task_id,
+            index_config,
+            manifest,
+            control,
+            progress,
+            mapping_updates,
+            handle,
+        };
+        // If the same indexer is already running, cancel it so we never have two streams writing into the same tables
+        if let Some(old_task) = tasks.insert(id, task) {
+            old_task.handle.abort();
+        }
+    }
+
+    // Ask the adapter to stop: its streams stop between two blocks, commit their pending blocks and return.
+    // The task is only aborted if it doesn't stop within INDEXER_STOP_TIMEOUT
+    pub async fn stop(id: &String) -> Result<IndexConfig, Box<dyn Error>> {
+        let task = INDEXER_TASKS
+            .lock()
+            .unwrap()
+            .remove(id)
+            .ok_or_else(|| InvalidRequest(format!("Indexer {} is not running", id)))?;
+        IndexRegistry::stop_task(id, task).await
+    }
+
+    pub fn pause(id: &String) -> Result<(), Box<dyn Error>> {
+        IndexRegistry::send_control(id, AdapterControl::Paused)
+    }
+
+    pub fn resume(id: &String) -> Result<(), Box<dyn Error>> {
+        IndexRegistry::send_control(id, AdapterControl::Running)
+    }
+
+    // Stop the indexer then start it again with the same config and manifest
+    pub async fn restart(id: &String) -> Result<(), Box<dyn Error>> {
+        let task = INDEXER_TASKS
+            .lock()
+            .unwrap()
+            .remove(id)
+            .ok_or_else(|| InvalidRequest(format!("Indexer {} is not running", id)))?;
+        let manifest = task.manifest.clone();
+        let index_config = IndexRegistry::stop_task(id, task).await?;
+        log::info!("Restarting indexer {}", id);
+        IndexRegistry::spawn_task(index_config, manifest);
+        Ok(())
+    }
+
+    async fn stop_task(id: &String, mut task: IndexerTask) -> Result<IndexConfig, Box<dyn Error>> {
+        // The adapter has already returned if nobody receives the signal
+        let _ = task.control.send(AdapterControl::Stopped);
+        if timeout(*INDEXER_STOP_TIMEOUT, &mut task.handle)
+            .await
+            .is_err()
+        {
+            log::warn!(
+                "Indexer {} didn't stop within {:?}, its pending blocks are dropped",
+                id,
+                *INDEXER_STOP_TIMEOUT
+            );
+            task.handle.abort();
+        }
+        log::info!("Indexer {} is stopped", id);
+        Ok(task.index_config)
+    }
+
+    // Replace the .so mapping of a running indexer without restarting it, see AdapterManager::with_mapping_updates.
+    // The adapter keeps the old mapping if the new one can't be loaded
+    pub async fn update_mapping(id: &String, mapping: PathBuf) -> Result<(), Box<dyn Error>> {
+        let mapping_updates = INDEXER_TASKS
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|task| task.mapping_updates.clone())
+            .ok_or_else(|| InvalidRequest(format!("Indexer {} is not running", id)))?;
+        let (result, result_receiver) = oneshot::channel();
+        let mapping_path = mapping.clone();
+        if mapping_updates
+            .send(MappingUpdate { mapping, result })
+            .await
+            .is_err()
+        {
+            return Err(format!("Indexer {} has already stopped", id).into());
+        }
+        match result_receiver.await {
+            Ok(Ok(_)) => {
+                // A restart of the indexer loads the new mapping
+                if let Some(task) = INDEXER_TASKS.lock().unwrap().get_mut(id) {
+                    task.index_config.mapping = mapping_path;
+                }
+                Ok(())
+            }
+            // The new mapping can't be loaded
+            Ok(Err(e)) => Err(InvalidRequest(e).into()),
+            Err(_) => Err(format!("Indexer {} stopped before using the new mapping", id).into()),
+        }
+    }
+
+    // Latest progress reported by the adapter of a running indexer
+    pub fn progress(id: &String) -> Option<AdapterProgress> {
+        INDEXER_TASKS
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|task| task.progress.borrow().clone())
+    }
+
+    pub fn is_running(id: &String) -> bool {
+        INDEXER_TASKS.lock().unwrap().contains_key(id)
+    }
+
+    fn send_control(id: &String, signal: AdapterControl) -> Result<(), Box<dyn Error>> {
+        let tasks = INDEXER_TASKS.lock().unwrap();
+        let task = tasks
+            .get(id)
+            .ok_or_else(|| InvalidRequest(format!("Indexer {} is not running", id)))?;
+        task.control
+            .send(signal)
+            .map_err(|_| format!("Indexer {} has already stopped", id))?;
+        Ok(())
+    }
+}
+
+// Compute the status of the indexer from its progress
+pub fn get_status(progress: &AdapterProgress) -> IndexStatus {
+    if progress.failure.is_some() {
+        return IndexStatus::Failed;
+    }
+    match (progress.block_number, progress.chain_head) {
+        (Some(block_number), Some(chain_head))
+            if chain_head.saturating_sub(block_number) <= SYNCED_BLOCK_LAG =>
+        {
+            IndexStatus::Synced
+        }
+        _ => IndexStatus::Syncing,
+    }
+}
+
+// Follow the progress of the adapter and persist the status of the indexer when it changes.
+// The first progress ends the deployment of the indexer, and a new version of an index becomes the current one as soon as it is synced
+async fn listen_progress(id: String, mut progress: watch::Receiver<AdapterProgress>) {
+    let mut last_status: Option<IndexStatus> = None;
+    let mut deployed = false;
+    while progress.changed().await.is_ok() {
+        let current = progress.borrow().clone();
+        if !deployed {
+            let errors = current
+                .failure
+                .as_ref()
+                .map(|failure| vec![DeployError::new("mapping", &failure.message)]);
+            IndexStore::finish_deployment_of_indexer(&id, errors.as_ref());
+            deployed = true;
+        }
+        let status = get_status(&current);
+        if last_status.as_ref() == Some(&status) {
+            continue;
+        }
+        let saved = match &current.failure {
+            Some(failure) => {
+                let error = IndexError {
+                    message: failure.message.clone(),
+                    block_number: Some(failure.block_number),
+                    timestamp: failure.timestamp,
+                };
+                IndexStore::update_indexer_error(&id, &error)
+            }
+            None => IndexStore::update_indexer_status(&id, status.clone()),
+        }
+        .map_err(|e| e.to_string());
+        // The status is saved again with the next progress
+        if let Err(e) = saved {
+            log::warn!("Cannot save the status of indexer {}: {}", &id, e);
+            continue;
+        }
+        // The promotion is tried again with the next progress
+        if status == IndexStatus::Synced && !IndexStore::is_current_indexer(&id) {
+            if let Err(e) = promote_index_version(&id).await {
+                log::warn!("Cannot promote indexer {}: {}", &id, e);
+                continue;
+            }
+        }
+        last_status = Some(status);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use adapter::core::AdapterFailure;
+
+    fn progress(block_number: Option<u64>, chain_head: Option<u64>) -> AdapterProgress {
+        AdapterProgress {
+            block_number,
+            chain_head,
+            failure: None,
+        }
+    }
+
+    #[test]
+    fn indexer_close_to_the_chain_head_is_synced() {
+        assert_eq!(
+            get_status(&progress(Some(95), Some(100))),
+            IndexStatus::Synced
+        );
+        assert_eq!(
+            get_status(&progress(Some(94), Some(100))),
+            IndexStatus::Syncing
+        );
+        // The chain reader may report a head behind the last processed block
+        assert_eq!(
+            get_status(&progress(Some(101), Some(100))),
+            IndexStatus::Synced
+        );
+    }
+
+    #[test]
+    fn indexer_without_progress_is_syncing() {
+        assert_eq!(get_status(&progress(None, None)), IndexStatus::Syncing);
+        assert_eq!(get_status(&progress(Some(100), None)), IndexStatus::Syncing);
+        assert_eq!(get_status(&progress(None, Some(100))), IndexStatus::Syncing);
+    }
+
+    #[test]
+    fn indexer_with_a_failure_is_failed() {
+        let mut progress = progress(Some(100), Some(100));
+        progress.failure = Some(AdapterFailure {
+            message: String::from("mapping error"),
+            block_number: 101,
+            timestamp: SystemTime::now(),
+        });
+        assert_eq!(get_status(&progress), IndexStatus::Failed);
+    }
+
+    fn is_invalid_request(result: Result<(), Box<dyn Error>>) -> bool {
+        matches!(result, Err(e) if e.downcast_ref::<InvalidRequest>().is_some())
+    }
+
+    #[tokio::test]
+    async fn unknown_indexer_is_not_running() {
+        let id = String::from("index_registry_test_unknown");
+        assert!(!IndexRegistry::is_running(&id));
+        assert!(IndexRegistry::progress(&id).is_none());
+        assert!(is_invalid_request(IndexRegistry::pause(&id)));
+        assert!(is_invalid_request(IndexRegistry::resume(&id)));
+        assert!(is_invalid_request(IndexRegistry::restart(&id).await));
+        assert!(is_invalid_request(
+            IndexRegistry::stop(&id).await.map(|_| ())
+        ));
+        assert!(is_invalid_request(
+            IndexRegistry::update_mapping(&id, PathBuf::from("mapping.so")).await
+        ));
+    }
+}
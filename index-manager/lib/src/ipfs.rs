@@ -1,53 +1,81 @@
 /**
 *** Objective of this file is to call to IPFS and get the index's information
+*** The files are fetched from the IPFS nodes / gateways of IPFS_ADDRESS, in order, and cached by cid in IPFS_CACHE_FOLDER
 **/
 // Generic dependencies
 use tokio_compat_02::FutureExt;
 use lazy_static::lazy_static;
-use std::fs::File;
-use std::io::Read;
+use std::error::Error;
+use std::time::Duration;
 use std::{env, fs};
 
 // Massbit dependencies
-use ipfs_client::core::create_ipfs_clients;
+use graph::log::logger;
+use ipfs_client::fetcher::IpfsFetcher;
 use std::path::PathBuf;
 
 lazy_static! {
-    static ref IPFS_ADDRESS: String =
-        env::var("IPFS_ADDRESS").unwrap_or(String::from("0.0.0.0:5001"));
+    // Comma separated, e.g. `0.0.0.0:5001,https://ipfs.io/ipfs/`
+    pub static ref IPFS_ADDRESSES: Vec<String> = env::var("IPFS_ADDRESS")
+        .unwrap_or(String::from("0.0.0.0:5001"))
+        .split(',')
+        .map(|address| address.trim().to_string())
+        .filter(|address| !address.is_empty())
+        .collect();
+    static ref IPFS_TIMEOUT: u64 = env::var("IPFS_TIMEOUT")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(30); // In seconds
+    static ref IPFS_RETRIES: usize = env::var("IPFS_RETRIES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(3);
+    static ref IPFS_VERIFY: bool = env::var("IPFS_VERIFY")
+        .map(|value| value != "false")
+        .unwrap_or(true);
+    static ref IPFS_CACHE_FOLDER: String =
+        env::var("IPFS_CACHE_FOLDER").unwrap_or(String::from("index-manager/ipfs-cache"));
     static ref GENERATED_FOLDER: String = String::from("index-manager/generated");
 }
 
-pub async fn download_ipfs_file_by_hash(file_name: &String, folder_name: &String, ipfs_hash: &String) -> PathBuf {
+pub async fn download_ipfs_file_by_hash(
+    file_name: &String,
+    folder_name: &String,
+    ipfs_hash: &String,
+) -> Result<PathBuf, Box<dyn Error>> {
     log::info!("Downloading {} from IPFS as {}", ipfs_hash, file_name);
-    let ipfs_addresses = vec![IPFS_ADDRESS.to_string()];
-    let ipfs_clients = create_ipfs_clients(&ipfs_addresses).await;
-    let file_bytes = ipfs_clients[0]
-        .cat_all(ipfs_hash.to_string())
-        .compat()
-        .await
-        .unwrap()
-        .to_vec();
+    let file_bytes = cat_ipfs_file(ipfs_hash).await?;
 
-    fs::create_dir_all([GENERATED_FOLDER.as_str(), folder_name].join("/")).unwrap();
+    fs::create_dir_all([GENERATED_FOLDER.as_str(), folder_name].join("/"))?;
     let file_path = [GENERATED_FOLDER.as_str(), folder_name, file_name].join("/");
-    let res = fs::write(file_path.clone(), file_bytes);
-    match res {
-        Ok(_) => {
-            log::info!("Write {} to storage successfully", file_path);
-            PathBuf::from(file_path)
-        }
-        Err(err) => {
-            panic!("Could not write {} to storage {:#?}", file_name, err)
-        }
-    }
+    fs::write(file_path.clone(), file_bytes)
+        .map_err(|e| format!("Could not write {} to storage: {}", file_name, e))?;
+    log::info!("Write {} to storage successfully", file_path);
+    Ok(PathBuf::from(file_path))
+}
+
+// Get the content of a file from IPFS without storing it
+pub async fn cat_ipfs_file(ipfs_hash: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let fetcher = IpfsFetcher::new(&IPFS_ADDRESSES, &logger(true))
+        .await
+        .timeout(Duration::from_secs(*IPFS_TIMEOUT))
+        .retries(*IPFS_RETRIES)
+        .verify(*IPFS_VERIFY)
+        .cache(Some(PathBuf::from(IPFS_CACHE_FOLDER.as_str())));
+    let file_bytes = fetcher.cat(ipfs_hash).compat().await?.to_vec();
+    Ok(file_bytes)
+}
+
+// Folder where all the files of an index are stored
+pub fn get_index_folder(folder_name: &String) -> PathBuf {
+    PathBuf::from([GENERATED_FOLDER.as_str(), folder_name].join("/"))
 }
 
-pub fn read_config_file(config: &PathBuf) -> serde_yaml::Value {
-    let mut project_config_string = String::new();
-    let mut f = File::open(config).expect("Unable to open file"); // Refactor: Config to download config file from IPFS instead of just reading from local
-    f.read_to_string(&mut project_config_string)
-        .expect("Unable to read string"); // Get raw query
-    let project_config: serde_yaml::Value = serde_yaml::from_str(&project_config_string).unwrap();
-    project_config
+// Read the config of an index, return an error when it is missing or invalid
+pub fn parse_config_file(config: &PathBuf) -> Result<serde_yaml::Value, Box<dyn Error>> {
+    let project_config_string = fs::read_to_string(config)
+        .map_err(|e| format!("Unable to read config {:?}: {}", config, e))?;
+    let project_config: serde_yaml::Value = serde_yaml::from_str(&project_config_string)
+        .map_err(|e| format!("Invalid config {:?}: {}", config, e))?;
+    Ok(project_config)
 }
@@ -1,5 +1,11 @@
+#[macro_use]
+extern crate diesel;
+#[macro_use]
+extern crate diesel_migrations;
+
 pub mod index_manager;
 pub mod index_manager_helper;
+pub mod index_registry;
 
 pub mod hasura;
 pub mod hasura_helper;
@@ -8,9 +14,15 @@ pub mod config;
 pub mod config_builder;
 
 pub mod ipfs;
+pub mod query_layer;
+pub mod query_server;
+pub mod schema;
 pub mod store;
+pub mod subscription;
 
 pub mod adapter;
+pub mod artifact;
 pub mod ddl_gen;
 pub mod type_index;
 pub mod type_request;
+pub mod validator;
@@ -0,0 +1,79 @@
+/**
+ *** Objective of this file is to expose the tables of the indexers to the clients, through the query layer selected by QUERY_LAYER:
+ *** - `hasura` (default) tracks the tables and relationships in hasura, see hasura.rs
+ *** - `graphql` relies on the built-in graphql endpoint, see query_server.rs
+ *** - `none` exposes nothing
+ *** Tracking is idempotent, so a failed deploy or promote can be run again
+ **/
+// Generic dependencies
+use anyhow::Error;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::env;
+use std::path::Path;
+
+// Massbit dependencies
+use crate::hasura::HasuraQueryLayer;
+use crate::query_server::QueryServer;
+use crate::type_index::Indexer;
+
+lazy_static! {
+    static ref QUERY_LAYER_KIND: String = env::var("QUERY_LAYER").unwrap_or(String::from("hasura"));
+    pub static ref QUERY_LAYER: Box<dyn QueryLayer> = create_query_layer(&QUERY_LAYER_KIND);
+}
+
+#[async_trait]
+pub trait QueryLayer: Send + Sync {
+    fn name(&self) -> &'static str;
+    /// Expose the tables created from the schema of the indexer, it succeeds when they are exposed already
+    async fn track(&self, indexer: &Indexer, schema: &Path) -> Result<(), Error>;
+    /// Stop exposing the tables of the indexer, it succeeds when they are not exposed
+    async fn untrack(&self, indexer: &Indexer, schema: &Path) -> Result<(), Error>;
+}
+
+pub struct NoQueryLayer {}
+
+#[async_trait]
+impl QueryLayer for NoQueryLayer {
+    fn name(&self) -> &'static str {
+        "none"
+    }
+    async fn track(&self, _indexer: &Indexer, _schema: &Path) -> Result<(), Error> {
+        Ok(())
+    }
+    async fn untrack(&self, _indexer: &Indexer, _schema: &Path) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+// The query server reads the tables of any indexer on demand, there is nothing to track
+pub struct GraphqlQueryLayer {}
+
+#[async_trait]
+impl QueryLayer for GraphqlQueryLayer {
+    fn name(&self) -> &'static str {
+        "graphql"
+    }
+    async fn track(&self, _indexer: &Indexer, _schema: &Path) -> Result<(), Error> {
+        Ok(())
+    }
+    async fn untrack(&self, indexer: &Indexer, _schema: &Path) -> Result<(), Error> {
+        QueryServer::forget(&indexer.id);
+        Ok(())
+    }
+}
+
+fn create_query_layer(kind: &str) -> Box<dyn QueryLayer> {
+    match kind {
+        "hasura" => Box::new(HasuraQueryLayer::default()),
+        "graphql" => Box::new(GraphqlQueryLayer {}),
+        "none" => Box::new(NoQueryLayer {}),
+        kind => {
+            log::warn!(
+                "[Query Layer] Unknown query layer {}, use hasura instead",
+                kind
+            );
+            Box::new(HasuraQueryLayer::default())
+        }
+    }
+}
@@ -0,0 +1,221 @@
+/**
+ *** Objective of this file is to serve the graphql queries of the indexers over http, without Hasura
+ *** - POST /indexers/<id>/graphql queries a version of an index
+ *** - POST /indexers/name/<name>/graphql queries the current version of an index
+ *** The queries are answered by the index-store, see index_store::postgres::graphql
+ *** The same paths accept websocket connections for the graphql subscriptions, see subscription.rs
+ **/
+// Generic dependencies
+use hyper::header::{
+    CONNECTION, SEC_WEBSOCKET_ACCEPT, SEC_WEBSOCKET_KEY, SEC_WEBSOCKET_PROTOCOL, UPGRADE,
+};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use tokio_tungstenite::tungstenite::handshake::derive_accept_key;
+
+// Massbit dependencies
+use crate::index_manager_helper::get_namespace;
+use crate::ipfs::get_index_folder;
+use crate::subscription::SubscriptionSession;
+use crate::type_index::{IndexStore, Indexer};
+use index_store::postgres::graphql::execute_query;
+use index_store::postgres::store_builder::StoreBuilder;
+use index_store::postgres::PostgresIndexStore;
+
+lazy_static! {
+    // Stores of the queried indexers, opened on the first query
+    static ref QUERY_STORES: Mutex<HashMap<String, Arc<PostgresIndexStore>>> =
+        Mutex::new(HashMap::new());
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphqlRequest {
+    query: String,
+    #[serde(default)]
+    variables: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+enum QueryTarget {
+    Id(String),
+    Name(String),
+}
+
+pub struct QueryServer {}
+impl QueryServer {
+    pub async fn serve(http_addr: String) {
+        let addr: SocketAddr = match http_addr.parse() {
+            Ok(addr) => addr,
+            Err(e) => {
+                log::error!("[Query Server] Invalid address {}: {}", &http_addr, e);
+                return;
+            }
+        };
+        let make_service =
+            make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle_request)) });
+        log::info!("[Query Server] Serving graphql queries on {}", addr);
+        if let Err(e) = Server::bind(&addr).serve(make_service).await {
+            log::error!("[Query Server] {}", e);
+        }
+    }
+
+    // Close the store of a deleted indexer
+    pub fn forget(id: &String) {
+        QUERY_STORES.lock().unwrap().remove(id);
+    }
+}
+
+async fn handle_request(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let target = match parse_path(request.uri().path()) {
+        Some(target) => target,
+        None => return Ok(error_response(StatusCode::NOT_FOUND, "Not found")),
+    };
+    if request.method() == Method::GET && is_websocket_upgrade(&request) {
+        return Ok(upgrade_websocket(target, request).await);
+    }
+    if request.method() == Method::OPTIONS {
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("Access-Control-Allow-Origin", "*")
+            .header("Access-Control-Allow-Methods", "POST, OPTIONS")
+            .header("Access-Control-Allow-Headers", "Content-Type")
+            .body(Body::empty())
+            .unwrap());
+    }
+    if request.method() != Method::POST {
+        return Ok(error_response(
+            StatusCode::METHOD_NOT_ALLOWED,
+            "Queries must be sent with POST",
+        ));
+    }
+    let body = match hyper::body::to_bytes(request.into_body()).await {
+        Ok(body) => body,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, &e.to_string())),
+    };
+    let graphql_request: GraphqlRequest = match serde_json::from_slice(&body) {
+        Ok(graphql_request) => graphql_request,
+        Err(e) => return Ok(error_response(StatusCode::BAD_REQUEST, &e.to_string())),
+    };
+    // The store is synchronous, run the query outside of the async workers
+    let result = tokio::task::spawn_blocking(move || run_query(target, graphql_request)).await;
+    match result {
+        Ok(Ok(response)) => Ok(json_response(StatusCode::OK, response)),
+        Ok(Err(e)) => Ok(error_response(StatusCode::NOT_FOUND, &e.to_string())),
+        Err(e) => Ok(error_response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            &e.to_string(),
+        )),
+    }
+}
+
+fn parse_path(path: &str) -> Option<QueryTarget> {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["indexers", "name", name, "graphql"] => Some(QueryTarget::Name(name.to_string())),
+        ["indexers", id, "graphql"] => Some(QueryTarget::Id(id.to_string())),
+        _ => None,
+    }
+}
+
+fn run_query(
+    target: QueryTarget,
+    request: GraphqlRequest,
+) -> Result<serde_json::Value, Box<dyn Error + Send + Sync>> {
+    let store = get_target_store(target)?;
+    Ok(execute_query(
+        &store,
+        &request.query,
+        &request.variables.unwrap_or_default(),
+    ))
+}
+
+fn get_target_store(
+    target: QueryTarget,
+) -> Result<Arc<PostgresIndexStore>, Box<dyn Error + Send + Sync>> {
+    let indexer = match &target {
+        QueryTarget::Id(id) => IndexStore::get_indexer(id),
+        QueryTarget::Name(name) => IndexStore::get_current_indexer(name),
+    }
+    .ok_or_else(|| match &target {
+        QueryTarget::Id(id) => format!("Indexer {} not found", id),
+        QueryTarget::Name(name) => format!("Index {} not found", name),
+    })?;
+    get_store(&indexer)
+}
+
+fn is_websocket_upgrade(request: &Request<Body>) -> bool {
+    request
+        .headers()
+        .get(UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.eq_ignore_ascii_case("websocket"))
+        .unwrap_or_default()
+}
+
+/// Accept the websocket handshake, the subscriptions run on the upgraded connection
+async fn upgrade_websocket(target: QueryTarget, mut request: Request<Body>) -> Response<Body> {
+    let accept_key = match request.headers().get(SEC_WEBSOCKET_KEY) {
+        Some(key) => derive_accept_key(key.as_bytes()),
+        None => return error_response(StatusCode::BAD_REQUEST, "Missing Sec-WebSocket-Key"),
+    };
+    let store = match tokio::task::spawn_blocking(move || get_target_store(target)).await {
+        Ok(Ok(store)) => store,
+        Ok(Err(e)) => return error_response(StatusCode::NOT_FOUND, &e.to_string()),
+        Err(e) => return error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    };
+    // Only the graphql-ws protocol is supported
+    let with_protocol = request.headers().contains_key(SEC_WEBSOCKET_PROTOCOL);
+    tokio::spawn(async move {
+        match hyper::upgrade::on(&mut request).await {
+            Ok(upgraded) => SubscriptionSession::run(store, upgraded).await,
+            Err(e) => log::error!("[Query Server] Websocket upgrade failed: {}", e),
+        }
+    });
+    let mut response = Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(UPGRADE, "websocket")
+        .header(CONNECTION, "Upgrade")
+        .header(SEC_WEBSOCKET_ACCEPT, accept_key);
+    if with_protocol {
+        response = response.header(SEC_WEBSOCKET_PROTOCOL, "graphql-ws");
+    }
+    response.body(Body::empty()).unwrap()
+}
+
+fn get_store(indexer: &Indexer) -> Result<Arc<PostgresIndexStore>, Box<dyn Error + Send + Sync>> {
+    if let Some(store) = QUERY_STORES.lock().unwrap().get(&indexer.id) {
+        return Ok(store.clone());
+    }
+    let schema = get_index_folder(&indexer.hash).join("schema.graphql");
+    let store = Arc::new(
+        StoreBuilder::open_store(&indexer.id, &get_namespace(indexer), &schema)
+            .map_err(|e| format!("Cannot open the store of indexer {}: {}", &indexer.id, e))?,
+    );
+    QUERY_STORES
+        .lock()
+        .unwrap()
+        .insert(indexer.id.clone(), store.clone());
+    Ok(store)
+}
+
+fn json_response(status: StatusCode, body: serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .header("Access-Control-Allow-Origin", "*")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    json_response(
+        status,
+        serde_json::json!({ "errors": [{ "message": message }] }),
+    )
+}
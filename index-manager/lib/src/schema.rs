@@ -0,0 +1,35 @@
+/**
+*** Objective of this file is to describe the tables of the index-manager for diesel's query builder
+*** The tables are created by the embedded migrations of the `migrations` folder
+**/
+table! {
+    indexers (v_id) {
+        v_id -> Int4,
+        id -> Varchar,
+        network -> Varchar,
+        name -> Varchar,
+        description -> Nullable<Varchar>,
+        repo -> Nullable<Varchar>,
+        index_status -> Nullable<Varchar>,
+        hash -> Varchar,
+        error_message -> Nullable<Varchar>,
+        error_block -> Nullable<Int8>,
+        error_at -> Nullable<Timestamp>,
+        version -> Nullable<Int4>,
+        current -> Nullable<Bool>,
+        namespace -> Nullable<Varchar>,
+        mapping -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    indexer_deployments (id) {
+        id -> Varchar,
+        name -> Nullable<Varchar>,
+        indexer_id -> Nullable<Varchar>,
+        status -> Varchar,
+        errors -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
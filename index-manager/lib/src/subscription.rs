@@ -0,0 +1,220 @@
+/**
+ *** Objective of this file is to push the result of graphql subscriptions to websocket clients
+ *** - the client upgrades GET /indexers/<id>/graphql or /indexers/name/<name>/graphql to a websocket
+ *** - messages follow the graphql-ws protocol of subscriptions-transport-ws: connection_init, start, stop
+ *** - a subscription is answered when it starts, then each time the index store publishes a change
+ ***   of the entities it selects (see index_store::postgres::events)
+ **/
+// Generic dependencies
+use futures::{SinkExt, StreamExt};
+use hyper::upgrade::Upgraded;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::tungstenite::protocol::Role;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+// Massbit dependencies
+use index_store::postgres::events::{self, IndexStoreEvent};
+use index_store::postgres::graphql::{execute_query, watched_entities};
+use index_store::postgres::PostgresIndexStore;
+
+#[derive(Debug, Deserialize)]
+struct ClientMessage {
+    #[serde(rename = "type")]
+    message_type: String,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    payload: Option<SubscriptionPayload>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubscriptionPayload {
+    query: String,
+    #[serde(default)]
+    variables: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+struct Subscription {
+    query: String,
+    variables: serde_json::Map<String, serde_json::Value>,
+    entities: Vec<(String, Option<String>)>,
+    last_result: Option<serde_json::Value>,
+}
+
+impl Subscription {
+    fn is_affected_by(&self, event: &IndexStoreEvent) -> bool {
+        self.entities
+            .iter()
+            .any(|(entity_type, id)| event.matches(entity_type, id.as_deref()))
+    }
+}
+
+pub struct SubscriptionSession {
+    store: Arc<PostgresIndexStore>,
+    socket: WebSocketStream<Upgraded>,
+    subscriptions: HashMap<String, Subscription>,
+}
+
+impl SubscriptionSession {
+    pub async fn run(store: Arc<PostgresIndexStore>, upgraded: Upgraded) {
+        let socket = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+        let mut session = SubscriptionSession {
+            store,
+            socket,
+            subscriptions: HashMap::new(),
+        };
+        let mut store_events = events::subscribe();
+        loop {
+            let result = tokio::select! {
+                message = session.socket.next() => match message {
+                    Some(Ok(Message::Text(text))) => session.handle_message(&text).await,
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => Ok(true),
+                    Some(Err(e)) => Err(e.to_string()),
+                },
+                event = store_events.recv() => match event {
+                    Ok(event) if event.indexer == session.store.indexer => {
+                        session.refresh(Some(&event)).await
+                    }
+                    Ok(_) => Ok(true),
+                    // Some events were dropped, answer every subscription again
+                    Err(RecvError::Lagged(_)) => session.refresh(None).await,
+                    Err(RecvError::Closed) => break,
+                },
+            };
+            match result {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    log::warn!(
+                        "[Query Server] Subscription of indexer {} closed: {}",
+                        &session.store.indexer,
+                        e
+                    );
+                    break;
+                }
+            }
+        }
+        let _ = session.socket.close(None).await;
+    }
+
+    /// Return false when the client ends the connection
+    async fn handle_message(&mut self, text: &str) -> Result<bool, String> {
+        let message: ClientMessage = match serde_json::from_str(text) {
+            Ok(message) => message,
+            Err(e) => {
+                self.send(serde_json::json!({
+                    "type": "connection_error",
+                    "payload": { "message": e.to_string() }
+                }))
+                .await?;
+                return Ok(true);
+            }
+        };
+        match (message.message_type.as_str(), message.id, message.payload) {
+            ("connection_init", _, _) => {
+                self.send(serde_json::json!({ "type": "connection_ack" }))
+                    .await?
+            }
+            ("start", Some(id), Some(payload)) => self.start(id, payload).await?,
+            ("stop", Some(id), _) => {
+                self.subscriptions.remove(&id);
+                self.send(serde_json::json!({ "type": "complete", "id": id }))
+                    .await?
+            }
+            ("connection_terminate", _, _) => return Ok(false),
+            (message_type, id, _) => {
+                self.send(serde_json::json!({
+                    "type": "error",
+                    "id": id,
+                    "payload": { "message": format!("Invalid message {}", message_type) }
+                }))
+                .await?
+            }
+        }
+        Ok(true)
+    }
+
+    async fn start(&mut self, id: String, payload: SubscriptionPayload) -> Result<(), String> {
+        let store = self.store.clone();
+        let query = payload.query.clone();
+        let variables = payload.variables.unwrap_or_default();
+        let query_variables = variables.clone();
+        let entities = tokio::task::spawn_blocking(move || {
+            watched_entities(&store, &query, &query_variables).map_err(|e| e.to_string())
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+        match entities {
+            Ok(entities) => {
+                self.subscriptions.insert(
+                    id.clone(),
+                    Subscription {
+                        query: payload.query,
+                        variables,
+                        entities,
+                        last_result: None,
+                    },
+                );
+                self.answer(&id).await
+            }
+            Err(e) => {
+                self.send(serde_json::json!({
+                    "type": "error",
+                    "id": id,
+                    "payload": { "message": e }
+                }))
+                .await
+            }
+        }
+    }
+
+    /// Answer the subscriptions affected by the event, or all of them without event
+    async fn refresh(&mut self, event: Option<&IndexStoreEvent>) -> Result<bool, String> {
+        let ids: Vec<String> = self
+            .subscriptions
+            .iter()
+            .filter(|(_, subscription)| {
+                event
+                    .map(|event| subscription.is_affected_by(event))
+                    .unwrap_or(true)
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in ids {
+            self.answer(&id).await?;
+        }
+        Ok(true)
+    }
+
+    /// Run the query of the subscription and send the result if it changed since the last one
+    async fn answer(&mut self, id: &String) -> Result<(), String> {
+        let (query, variables) = match self.subscriptions.get(id) {
+            Some(subscription) => (subscription.query.clone(), subscription.variables.clone()),
+            None => return Ok(()),
+        };
+        let store = self.store.clone();
+        let result = tokio::task::spawn_blocking(move || execute_query(&store, &query, &variables))
+            .await
+            .map_err(|e| e.to_string())?;
+        if let Some(subscription) = self.subscriptions.get_mut(id) {
+            if subscription.last_result.as_ref() == Some(&result) {
+                return Ok(());
+            }
+            subscription.last_result = Some(result.clone());
+        }
+        self.send(serde_json::json!({ "type": "data", "id": id, "payload": result }))
+            .await
+    }
+
+    async fn send(&mut self, message: serde_json::Value) -> Result<(), String> {
+        self.socket
+            .send(Message::Text(message.to_string()))
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
@@ -3,7 +3,10 @@
 **/
 // Generic dependencies
 use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fmt;
 use std::path::PathBuf;
+use std::time::SystemTime;
 use strum_macros::AsStaticStr;
 
 // Massbit dependencies
@@ -21,6 +24,11 @@ pub struct Indexer {
     pub network: String,
     pub name: String,
     pub hash: String,
+    pub status: Option<String>,
+    pub version: Option<i32>, // Deploying an index with the same name creates a new version
+    pub current: Option<bool>, // The version that is served, see IndexStore::promote_indexer
+    pub namespace: Option<String>, // Postgres schema of the tables of the indexer
+    pub mapping: Option<String>, // Mapping file in the folder of the indexer when it is replaced, see index_update_mapping
 }
 
 // Normalized version of DeployAbi
@@ -40,6 +48,7 @@ pub struct IndexConfig {
     pub abi: Option<Vec<Abi>>, // .SO doesn't support uploading ABIs yet, only .WASM need the ABIs
     pub identifier: IndexIdentifier,
     pub subgraph: PathBuf,
+    pub namespace: String, // Postgres schema of the index's tables, assigned when the indexer is inserted
 }
 
 // Identifier of the IndexConfig is an helper to easily access the hash of the index, and index's file name
@@ -53,9 +62,99 @@ pub struct IndexIdentifier {
 // This is inspired by the syncing status from eth https://ethereum.stackexchange.com/questions/69458/sync-status-of-ethereum-node
 #[derive(Clone, Debug, PartialEq, AsStaticStr)]
 pub enum IndexStatus {
-    Synced,  // Meaning that the index is running
-    Syncing, // This mean our index is not caught up to the latest block yet. We don't support this field yet
+    Synced,  // Meaning that the index has caught up with the chain head
+    Syncing, // This mean our index is not caught up to the latest block yet
     False,   // Meaning that the index is not running
+    Paused,  // Meaning that the index is paused by the user and can be resumed
+    Stopped, // Meaning that the index is stopped by the user
+    Failed,  // Meaning that the index has stopped because of an error, see IndexError
+}
+
+// Details of the error which made an indexer fail
+#[derive(Clone, Debug)]
+pub struct IndexError {
+    pub message: String,
+    pub block_number: Option<u64>, // None when the indexer failed before processing any block
+    pub timestamp: SystemTime,
+}
+
+// Result of the index_status API
+#[derive(Serialize, Debug)]
+pub struct IndexStatusDetail {
+    pub id: String,
+    pub version: Option<i32>,
+    pub current: bool,
+    pub status: String,
+    pub health: String,
+    pub processed_block: Option<u64>,
+    pub chain_head: Option<u64>,
+    pub lag: Option<u64>,
+    pub error: Option<IndexErrorDetail>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct IndexErrorDetail {
+    pub message: String,
+    pub block_number: Option<u64>,
+    pub timestamp: u64, // Seconds since unix epoch
+}
+
+// A problem found while deploying a new index. The deployment keeps all of them, see index_deploy_status
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct DeployError {
+    pub source: String, // The invalid part of the index: config, mapping, schema, abi, subgraph or the name of a data source
+    pub message: String,
+}
+
+impl DeployError {
+    pub fn new<S: ToString>(source: &str, message: S) -> DeployError {
+        DeployError {
+            source: source.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+// A request about an unknown indexer or deployment, or which the indexer can't fulfil.
+// The API answers it as invalid params, every other error is an internal error
+#[derive(Debug)]
+pub struct InvalidRequest(pub String);
+
+impl fmt::Display for InvalidRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", &self.0)
+    }
+}
+
+impl Error for InvalidRequest {}
+
+// Steps of a deployment, in order. A deployment stops at the step which failed
+#[derive(Clone, Debug, PartialEq, AsStaticStr)]
+pub enum DeploymentStatus {
+    #[strum(serialize = "fetching_artifacts")]
+    FetchingArtifacts, // The files of the index are downloaded from the source of the request
+    #[strum(serialize = "validating")]
+    Validating, // The files are checked against each other and the chain reader
+    #[strum(serialize = "creating_schema")]
+    CreatingSchema, // The indexer is inserted, its tables are created and exposed by the query layer
+    #[strum(serialize = "loading_mapping")]
+    LoadingMapping, // The adapter is loading the mapping and connecting to the chain reader
+    #[strum(serialize = "streaming")]
+    Streaming, // The adapter reported its first progress, the index is running
+    #[strum(serialize = "failed")]
+    Failed, // See the errors of the deployment
+}
+
+// Result of the index_deploy and index_deploy_status APIs
+#[derive(Serialize, Debug)]
+pub struct Deployment {
+    pub id: String,
+    pub name: Option<String>,       // Known once the config is downloaded
+    pub indexer_id: Option<String>, // Known once the indexer is created
+    pub status: String,
+    pub errors: Vec<DeployError>,
+    pub created_at: u64, // Seconds since unix epoch
+    pub updated_at: u64,
 }
 
 pub struct IndexStore {}
@@ -5,6 +5,7 @@
  **/
 // Generic dependencies
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 // The order of params is important to correctly map the API request to this struct
 #[derive(Clone, Debug, Deserialize)]
@@ -14,11 +15,29 @@ pub struct DeployParams {
     pub schema: String,
     pub abi: Option<Vec<DeployAbi>>,  // .SO doesn't support uploading ABIs yet, only .WASM need the ABIs
     pub subgraph: Option<String>, // .SO doesn't need this parsed config file
+    pub source: Option<String>, // Where the files are fetched from: ipfs (default), local or http
+    pub checksums: Option<HashMap<String, String>>, // Expected sha256 of the files, by IPFS hash / path / URL
+    pub name: String, // Name of the index, every deploy with the same name is a new version of it
 }
 
-// User can upload multiple ABI files. So we need this object to get the abi's name and it's ipfs hash
+// User can upload multiple ABI files. So we need this object to get the abi's name and it's ipfs hash / path / URL
 #[derive(Clone, Debug, Deserialize)]
 pub struct DeployAbi {
     pub name: String,
     pub hash: String,
 }
+
+// Params of the API that replaces the .so mapping of a running index, the mapping is fetched like the deploy files
+#[derive(Clone, Debug, Deserialize)]
+pub struct UpdateMappingParams {
+    pub id: String,
+    pub mapping: String,
+    pub source: Option<String>,
+    pub checksums: Option<HashMap<String, String>>,
+}
+
+// Params of the API that manage an existing index: stop, pause, resume, restart, delete
+#[derive(Clone, Debug, Deserialize)]
+pub struct IndexParams {
+    pub id: String,
+}
@@ -0,0 +1,591 @@
+/**
+ *** Objective of this file is to validate a new index before it is deployed,
+ *** so the user gets every problem in the deploy response instead of an indexer that fails later
+ **/
+// Generic dependencies
+use lazy_static::lazy_static;
+use object::{Object, ObjectSymbol};
+use serde_yaml::Value;
+use std::env;
+use std::fs::{self, File};
+use std::path::PathBuf;
+use tonic::Request;
+use wasmparser::{Parser, Payload};
+
+// Massbit dependencies
+use crate::ipfs::parse_config_file;
+use crate::type_index::stream_mod::ListNetworksRequest;
+use crate::type_index::{ChainType, DeployError, IndexConfig, StreamoutClient};
+use index_store::postgres::store_builder::{StoreBuilder, NAMESPACE};
+
+// Graph dependencies
+use graph::data::subgraph::SubgraphManifest;
+use graph_chain_ethereum::{Chain, Mapping};
+
+lazy_static! {
+    static ref CHAIN_READER_URL: String =
+        env::var("CHAIN_READER_URL").unwrap_or(String::from("http://127.0.0.1:50051"));
+}
+
+const WASM_LANGUAGE: &str = "wasm/assemblyscript";
+// Data source kinds that have a wasm adapter, see create_wasm_adapters! in the adapter plugin
+const WASM_KINDS: &[&str] = &["ethereum"];
+// Chains whose reader sends a rollback on a reorg, see ethereum_chain.rs in the chain reader
+const REORG_KINDS: &[&str] = &["ethereum"];
+// Symbols the adapter needs to load a .so mapping, see export_plugin! in the adapter plugin
+const SO_SYMBOLS: &[&str] = &["STORE", "adapter_declaration"];
+
+// A data source with the fields we need to check, from either the subgraph manifest or the project config
+struct DataSourceInfo {
+    name: String,
+    kind: String,
+    network: Option<String>,
+    language: String,
+    // Name and kind of the handlers of a .so mapping
+    handlers: Vec<(String, String)>,
+}
+
+pub async fn validate_index(
+    index_config: &IndexConfig,
+    manifest: &Option<SubgraphManifest<Chain>>,
+) -> Vec<DeployError> {
+    let mut errors: Vec<DeployError> = vec![];
+    let data_sources = match manifest {
+        Some(manifest) => {
+            // Rust data sources are checked with the .so mapping below
+            for data_source in &manifest.data_sources {
+                validate_wasm_mapping(&data_source.name, &data_source.mapping, &mut errors);
+            }
+            for template in &manifest.templates {
+                validate_wasm_mapping(&template.name, &template.mapping, &mut errors);
+            }
+            manifest
+                .data_sources
+                .iter()
+                .map(|data_source| DataSourceInfo {
+                    name: data_source.name.clone(),
+                    kind: data_source.kind.clone(),
+                    network: data_source.network.clone(),
+                    language: data_source.mapping.language.clone(),
+                    handlers: vec![],
+                })
+                .collect()
+        }
+        None => match parse_config_file(&index_config.config) {
+            Ok(config) => get_config_data_sources(&config),
+            Err(e) => {
+                errors.push(DeployError::new("config", e));
+                vec![]
+            }
+        },
+    };
+
+    if data_sources.is_empty() {
+        errors.push(DeployError::new(
+            "subgraph",
+            "Index must have at least 1 data source",
+        ));
+    }
+    validate_data_source_group(&data_sources, &mut errors);
+    let networks = match get_reader_networks().await {
+        Ok(networks) => Some(networks),
+        Err(e) => {
+            errors.push(DeployError::new("chain reader", e));
+            None
+        }
+    };
+    for data_source in &data_sources {
+        validate_data_source(data_source, &networks, &mut errors);
+    }
+    if data_sources
+        .iter()
+        .any(|data_source| data_source.language != WASM_LANGUAGE)
+    {
+        match parse_config_file(&index_config.config) {
+            Ok(config) => validate_so_mapping(&index_config.mapping, &config, &mut errors),
+            Err(e) => errors.push(DeployError::new("config", e)),
+        }
+    }
+    for abi in index_config.abi.iter().flatten() {
+        validate_abi(&abi.name, &abi.path, &mut errors);
+    }
+    // The same layout is used to create the tables of the index, only in another namespace
+    if let Err(e) = StoreBuilder::create_layout(NAMESPACE.as_str(), &index_config.schema) {
+        errors.push(DeployError::new("schema", e));
+    }
+    errors
+}
+
+// .SO mappings don't have a subgraph manifest, so the data sources come from project.yaml
+fn get_config_data_sources(config: &Value) -> Vec<DataSourceInfo> {
+    config["dataSources"]
+        .as_sequence()
+        .map(|data_sources| {
+            data_sources
+                .iter()
+                .map(|data_source| DataSourceInfo {
+                    name: data_source["name"].as_str().unwrap_or_default().to_string(),
+                    kind: data_source["kind"].as_str().unwrap_or_default().to_string(),
+                    network: data_source["network"].as_str().map(String::from),
+                    language: data_source["mapping"]["language"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                    handlers: data_source["mapping"]["handlers"]
+                        .as_sequence()
+                        .map(|handlers| {
+                            handlers
+                                .iter()
+                                .map(|handler| {
+                                    (
+                                        handler["handler"].as_str().unwrap_or_default().to_string(),
+                                        handler["kind"].as_str().unwrap_or_default().to_string(),
+                                    )
+                                })
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// The adapter keeps the wasm modules of the data sources by name and loads one mapping
+// for the whole index. Data sources may be on different chains and networks, each pair is read
+// from its own stream of the chain reader
+fn validate_data_source_group(data_sources: &Vec<DataSourceInfo>, errors: &mut Vec<DeployError>) {
+    for (index, data_source) in data_sources.iter().enumerate() {
+        if data_sources[..index]
+            .iter()
+            .any(|other| other.name == data_source.name)
+        {
+            errors.push(DeployError::new(
+                "subgraph",
+                format!(
+                    "Data source name {} is used more than once",
+                    &data_source.name
+                ),
+            ));
+        }
+    }
+    if let Some(first) = data_sources.get(0) {
+        for data_source in &data_sources[1..] {
+            if data_source.language != first.language {
+                errors.push(DeployError::new(
+                    &format!("dataSource {}", &data_source.name),
+                    "All data sources must use the same mapping language",
+                ));
+            }
+        }
+    }
+    // The store keeps the block ranges per index, not per stream: a reorg can only be reverted
+    // when the index has a single stream
+    let mut streams: Vec<(&str, &Option<String>)> = vec![];
+    for data_source in data_sources {
+        let stream = (
+            data_source.kind.split('/').next().unwrap_or_default(),
+            &data_source.network,
+        );
+        if !streams.contains(&stream) {
+            streams.push(stream);
+        }
+    }
+    if streams.len() > 1 {
+        if let Some((chain, _)) = streams
+            .iter()
+            .find(|(chain, _)| REORG_KINDS.contains(chain))
+        {
+            errors.push(DeployError::new(
+                "subgraph",
+                format!(
+                    "Data sources are read from {} streams, {} can reorg and a reorg can only be reverted for an index with a single chain and network",
+                    streams.len(),
+                    chain
+                ),
+            ));
+        }
+    }
+}
+
+fn validate_data_source(
+    data_source: &DataSourceInfo,
+    networks: &Option<Vec<(ChainType, String)>>,
+    errors: &mut Vec<DeployError>,
+) {
+    let source = format!("dataSource {}", &data_source.name);
+    let chain = data_source.kind.split('/').next().unwrap_or_default();
+    let chain_type = match chain {
+        "substrate" => Some(ChainType::Substrate),
+        "solana" => Some(ChainType::Solana),
+        "ethereum" => Some(ChainType::Ethereum),
+        _ => None,
+    };
+    if chain_type.is_none() {
+        errors.push(DeployError::new(
+            &source,
+            format!("Unsupported kind {}", &data_source.kind),
+        ));
+    }
+    if data_source.language == WASM_LANGUAGE && !WASM_KINDS.contains(&chain) {
+        errors.push(DeployError::new(
+            &source,
+            format!("Kind {} doesn't support wasm mapping", &data_source.kind),
+        ));
+    }
+    match (&data_source.network, chain_type, networks) {
+        (None, _, _) => errors.push(DeployError::new(&source, "Network is missing")),
+        (Some(network), Some(chain_type), Some(networks)) => {
+            if !networks.contains(&(chain_type, network.clone())) {
+                errors.push(DeployError::new(
+                    &source,
+                    format!(
+                        "Network {} of {} is not served by the chain reader",
+                        network, chain
+                    ),
+                ));
+            }
+        }
+        // Unknown kind or unreachable chain reader, both are already reported
+        _ => {}
+    }
+}
+
+// Every handler in the manifest must be exported by the wasm module
+// Every handler of a wasm mapping must be exported by its module
+fn validate_wasm_mapping(name: &String, mapping: &Mapping, errors: &mut Vec<DeployError>) {
+    if mapping.language != WASM_LANGUAGE {
+        return;
+    }
+    let handlers = mapping
+        .block_handlers
+        .iter()
+        .map(|handler| &handler.handler)
+        .chain(mapping.call_handlers.iter().map(|handler| &handler.handler))
+        .chain(
+            mapping
+                .event_handlers
+                .iter()
+                .map(|handler| &handler.handler),
+        )
+        .collect();
+    validate_wasm_handlers(name, &mapping.runtime, handlers, errors);
+}
+
+fn validate_wasm_handlers(
+    name: &String,
+    runtime: &[u8],
+    handlers: Vec<&String>,
+    errors: &mut Vec<DeployError>,
+) {
+    let source = format!("dataSource {}", name);
+    let exports = match get_wasm_exports(runtime) {
+        Ok(exports) => exports,
+        Err(e) => {
+            errors.push(DeployError::new(
+                &source,
+                format!("Invalid wasm module: {}", e),
+            ));
+            return;
+        }
+    };
+    for handler in handlers {
+        if !exports.contains(handler) {
+            errors.push(DeployError::new(
+                &source,
+                format!("Handler {} is not exported by the wasm module", handler),
+            ));
+        }
+    }
+}
+
+fn get_wasm_exports(wasm: &[u8]) -> Result<Vec<String>, wasmparser::BinaryReaderError> {
+    let mut exports = vec![];
+    for payload in Parser::new(0).parse_all(wasm) {
+        if let Payload::ExportSection(reader) = payload? {
+            for export in reader {
+                exports.push(export?.field.to_string());
+            }
+        }
+    }
+    Ok(exports)
+}
+
+// The library is read, not loaded: loading an uploaded library would run its code in the index manager
+pub fn validate_so_mapping(mapping: &PathBuf, config: &Value, errors: &mut Vec<DeployError>) {
+    let (exports, symbols) = match get_so_symbols(mapping) {
+        Ok(symbols) => symbols,
+        Err(e) => {
+            errors.push(DeployError::new(
+                "mapping",
+                format!("Invalid .so mapping: {}", e),
+            ));
+            return;
+        }
+    };
+    for symbol in SO_SYMBOLS {
+        if !exports.iter().any(|export| export == symbol) {
+            errors.push(DeployError::new(
+                "mapping",
+                format!("Symbol {} is not exported", symbol),
+            ));
+        }
+    }
+    // The handlers are called through the handler adapter generated for their chain, see lib.rs.tmpl of the cli.
+    // Their symbols are only in the symbol table, which a stripped library doesn't have
+    if symbols.is_empty() {
+        log::warn!(
+            "Mapping {:?} has no symbol table, its handlers are not checked",
+            mapping
+        );
+        return;
+    }
+    for data_source in get_config_data_sources(config) {
+        let source = format!("dataSource {}", &data_source.name);
+        let chain = data_source.kind.split('/').next().unwrap_or_default();
+        let adapter = format!("{}HandlerAdapter", capitalize(chain));
+        for (name, kind) in &data_source.handlers {
+            let method = match get_handler_method(kind) {
+                Some(method) => method,
+                None => {
+                    errors.push(DeployError::new(
+                        &source,
+                        format!("Unsupported kind {} of handler {}", kind, name),
+                    ));
+                    continue;
+                }
+            };
+            // Mangled names prefix each identifier with its length
+            let mangled_method = format!("{}{}", method.len(), method);
+            if !symbols
+                .iter()
+                .any(|symbol| symbol.contains(&adapter) && symbol.contains(&mangled_method))
+            {
+                errors.push(DeployError::new(
+                    &source,
+                    format!(
+                        "Handler {} of kind {} is not in the .so mapping",
+                        name, kind
+                    ),
+                ));
+            }
+        }
+    }
+}
+
+// Names of the dynamic symbols and of the symbol table of a shared library
+fn get_so_symbols(mapping: &PathBuf) -> Result<(Vec<String>, Vec<String>), String> {
+    let data = fs::read(mapping).map_err(|e| e.to_string())?;
+    let file = object::File::parse(&*data).map_err(|e| e.to_string())?;
+    let exports = file
+        .dynamic_symbols()
+        .filter_map(|symbol| symbol.name().ok().map(String::from))
+        .collect();
+    let symbols = file
+        .symbols()
+        .filter_map(|symbol| symbol.name().ok().map(String::from))
+        .collect();
+    Ok((exports, symbols))
+}
+
+// A handler of kind `ethereum/TransactionHandler` is called by the `handle_transaction` method of the adapter
+fn get_handler_method(kind: &str) -> Option<String> {
+    let handler = kind.split('/').nth(1)?.strip_suffix("Handler")?;
+    if handler.is_empty() {
+        return None;
+    }
+    let mut method = String::from("handle");
+    for c in handler.chars() {
+        if c.is_uppercase() {
+            method.push('_');
+        }
+        method.push(c.to_ascii_lowercase());
+    }
+    Some(method)
+}
+
+fn capitalize(name: &str) -> String {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn validate_abi(name: &String, path: &PathBuf, errors: &mut Vec<DeployError>) {
+    let result = File::open(path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| ethabi::Contract::load(file).map_err(|e| e.to_string()));
+    if let Err(e) = result {
+        errors.push(DeployError::new(
+            "abi",
+            format!("Invalid ABI {}: {}", name, e),
+        ));
+    }
+}
+
+// Ask the chain reader which networks it serves. An index can't be checked, nor run, without it
+async fn get_reader_networks() -> Result<Vec<(ChainType, String)>, String> {
+    let mut client = StreamoutClient::connect(CHAIN_READER_URL.clone())
+        .await
+        .map_err(|e| {
+            format!(
+                "Cannot connect to chain reader {}: {}",
+                CHAIN_READER_URL.as_str(),
+                e
+            )
+        })?;
+    let response = client
+        .list_networks(Request::new(ListNetworksRequest {}))
+        .await
+        .map_err(|e| format!("Cannot get networks from chain reader: {}", e))?;
+    Ok(response
+        .into_inner()
+        .networks
+        .into_iter()
+        .filter_map(|info| {
+            ChainType::from_i32(info.chain_type).map(|chain_type| (chain_type, info.network))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handler_kind_gives_the_adapter_method() {
+        assert_eq!(
+            get_handler_method("ethereum/TransactionHandler").as_deref(),
+            Some("handle_transaction")
+        );
+        assert_eq!(
+            get_handler_method("solana/LogMessagesHandler").as_deref(),
+            Some("handle_log_messages")
+        );
+        assert_eq!(get_handler_method("ethereum/Handler"), None);
+        assert_eq!(get_handler_method("ethereum"), None);
+    }
+
+    #[test]
+    fn handlers_are_read_from_the_config() {
+        let config: Value = serde_yaml::from_str(
+            r#"
+dataSources:
+  - kind: ethereum
+    name: Matic-Transaction
+    network: matic
+    mapping:
+      language: rust
+      handlers:
+        - handler: handleTransaction
+          kind: ethereum/TransactionHandler
+"#,
+        )
+        .unwrap();
+        let data_sources = get_config_data_sources(&config);
+        assert_eq!(
+            data_sources[0].handlers,
+            vec![(
+                "handleTransaction".to_string(),
+                "ethereum/TransactionHandler".to_string()
+            )]
+        );
+    }
+
+    fn data_source(name: &str, kind: &str, network: &str) -> DataSourceInfo {
+        DataSourceInfo {
+            name: name.to_string(),
+            kind: kind.to_string(),
+            network: Some(network.to_string()),
+            language: String::from("rust"),
+            handlers: vec![],
+        }
+    }
+
+    #[test]
+    fn several_streams_are_rejected_on_a_chain_which_can_reorg() {
+        let mut errors = vec![];
+        let data_sources = vec![
+            data_source("Polygon", "ethereum", "matic"),
+            data_source("Bsc", "ethereum", "bsc"),
+        ];
+        validate_data_source_group(&data_sources, &mut errors);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.contains("2 streams, ethereum can reorg"));
+
+        let mut errors = vec![];
+        let data_sources = vec![
+            data_source("Polkadot", "substrate", "polkadot"),
+            data_source("Solana", "solana", "mainnet"),
+            data_source("Solana2", "solana", "mainnet"),
+        ];
+        validate_data_source_group(&data_sources, &mut errors);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn file_which_is_not_a_library_is_rejected() {
+        let mapping = env::temp_dir().join("validator_test_mapping.so");
+        fs::write(&mapping, b"not a library").unwrap();
+        let mut errors = vec![];
+        validate_so_mapping(&mapping, &Value::Null, &mut errors);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].source, "mapping");
+    }
+
+    // Module with an empty function exported as handleBlock
+    const WASM_MODULE: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // magic and version
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section: fn()
+        0x03, 0x02, 0x01, 0x00, // function section
+        0x07, 0x0f, 0x01, 0x0b, b'h', b'a', b'n', b'd', b'l', b'e', b'B', b'l', b'o', b'c', b'k',
+        0x00, 0x00, // export section: handleBlock
+        0x0a, 0x04, 0x01, 0x02, 0x00, 0x0b, // code section
+    ];
+
+    #[test]
+    fn exported_wasm_handlers_are_valid() {
+        let mut errors = vec![];
+        let handler = String::from("handleBlock");
+        validate_wasm_handlers(
+            &String::from("Factory"),
+            WASM_MODULE,
+            vec![&handler],
+            &mut errors,
+        );
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn wasm_handler_which_is_not_exported_is_rejected() {
+        let mut errors = vec![];
+        let handlers = vec![String::from("handleBlock"), String::from("handleTransfer")];
+        validate_wasm_handlers(
+            &String::from("Factory"),
+            WASM_MODULE,
+            handlers.iter().collect(),
+            &mut errors,
+        );
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].source, "dataSource Factory");
+        assert_eq!(
+            errors[0].message,
+            "Handler handleTransfer is not exported by the wasm module"
+        );
+    }
+
+    #[test]
+    fn invalid_wasm_module_is_rejected() {
+        let mut errors = vec![];
+        let handler = String::from("handleBlock");
+        validate_wasm_handlers(
+            &String::from("Factory"),
+            b"not a module",
+            vec![&handler],
+            &mut errors,
+        );
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.starts_with("Invalid wasm module"));
+    }
+}
@@ -9,12 +9,16 @@ use std::env;
 
 // Massbit dependencies
 use index_manager_lib::index_manager::IndexManager;
+use index_manager_lib::query_server::QueryServer;
+use index_manager_lib::type_index::IndexStore;
 use logger::core::init_logger;
 
 lazy_static! {
     // Restart all the indexes when the indexer manager is restarted is still a new feature.
     // We don't want it to be broken when running the E2E Tests, so default option is set to False
     static ref INDEX_MANAGER_RESTART_INDEX: String = env::var("INDEX_MANAGER_RESTART_INDEX").unwrap_or(String::from("false"));
+    // The graphql queries of the indexers are served here, without Hasura
+    static ref INDEX_MANAGER_QUERY_ADDRESS: String = env::var("INDEX_MANAGER_QUERY_ADDRESS").unwrap_or(String::from("0.0.0.0:3032"));
 }
 
 #[tokio::main]
@@ -22,12 +26,20 @@ async fn main() {
     let res = init_logger(&String::from("index-manager"));
     println!("{}", res); // Print log output type
 
+    // The indexers and their checkpoints are read right after, their tables must be up to date
+    if let Err(e) = IndexStore::run_migrations() {
+        log::error!("Cannot migrate the metadata tables: {}", e);
+        return;
+    }
+
     if INDEX_MANAGER_RESTART_INDEX.to_lowercase().as_str() == "true" {
         tokio::spawn(async move {
             IndexManager::restart_all_existing_index().await;
         });
     }
 
+    tokio::spawn(QueryServer::serve(INDEX_MANAGER_QUERY_ADDRESS.clone()));
+
     let server = IndexManager::serve("0.0.0.0:3030".to_string());
     server.wait();
 }
@@ -3,22 +3,37 @@ pub use crate::stream_mod::{
     streamout_client::StreamoutClient, ChainType, DataType, GenericDataProto, GetBlocksRequest,
 };
 pub use crate::{HandlerProxyType, PluginRegistrar, WasmHandlerProxyType};
+use futures03::future::{pending, select, try_join_all, Either};
+use futures03::Future;
+use graph::blockchain::types::{BlockHash, BlockPtr};
+use graph::components::store::WritableStore;
+use graph::data::subgraph::schema::SubgraphError;
 use graph::data::subgraph::SubgraphManifest;
 use graph::semver::Op;
 use graph_chain_ethereum::Chain;
 use graph_chain_ethereum::{DataSource, DataSourceTemplate};
 use graph_runtime_wasm::ValidModule;
+use index_store::postgres::checkpoint;
 use index_store::postgres::store_builder::*;
+use index_store::postgres::PostgresIndexStore;
 use index_store::{IndexerState, Store};
 use lazy_static::lazy_static;
 use libloading::Library;
+use massbit_common::prelude::tokio::sync::{mpsc, oneshot, watch};
 use massbit_common::prelude::tokio::time::{sleep, timeout, Duration};
 use massbit_common::NetworkType;
 use serde_yaml::Value;
 use std::path::Path;
+use std::time::SystemTime;
 use std::{
-    alloc::System, collections::HashMap, env, error::Error, ffi::OsStr, fmt, path::PathBuf,
-    sync::Arc,
+    alloc::System,
+    collections::HashMap,
+    env,
+    error::Error,
+    ffi::OsStr,
+    fmt,
+    path::PathBuf,
+    sync::{Arc, Mutex},
 };
 use tonic::transport::Channel;
 use tonic::{Request, Streaming};
@@ -73,10 +88,153 @@ impl WasmAdapter {
     }
 }
 
+/// Control signal sent by the index-manager to a running adapter.
+/// The adapter only checks it between two blocks, so a pause or a stop never happens in the middle of a block.
+/// Once stopped, the streams commit their pending blocks and the adapter returns.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AdapterControl {
+    Running,
+    Paused,
+    Stopped,
+}
+
+/// Progress of the stream loop reported to the index-manager after each block
+#[derive(Clone, Debug, Default)]
+pub struct AdapterProgress {
+    /// Last block processed successfully
+    pub block_number: Option<u64>,
+    /// Latest block known by the chain reader
+    pub chain_head: Option<u64>,
+    /// Set when the last block failed, cleared as soon as a block is processed successfully
+    pub failure: Option<AdapterFailure>,
+}
+
+/// Request of the index-manager to replace the rust mapping of a running adapter.
+/// The result is sent back once the new library handles the next blocks, or the old one is kept
+pub struct MappingUpdate {
+    pub mapping: PathBuf,
+    pub result: oneshot::Sender<Result<(), String>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct AdapterFailure {
+    pub message: String,
+    pub block_number: u64,
+    pub timestamp: SystemTime,
+}
+
+/// Each stream of the indexer reports its own progress,
+/// the caller gets the progress of the stream which is the most behind
+struct ProgressReporter {
+    stream: String,
+    sender: Option<Arc<watch::Sender<AdapterProgress>>>,
+    streams: Arc<Mutex<HashMap<String, AdapterProgress>>>,
+    progress: AdapterProgress,
+}
+
+impl ProgressReporter {
+    fn new(sender: Option<watch::Sender<AdapterProgress>>) -> ProgressReporter {
+        ProgressReporter {
+            stream: String::new(),
+            sender: sender.map(Arc::new),
+            streams: Arc::new(Mutex::new(HashMap::new())),
+            progress: AdapterProgress::default(),
+        }
+    }
+    fn for_stream(&self, stream: &String) -> ProgressReporter {
+        ProgressReporter {
+            stream: stream.clone(),
+            sender: self.sender.clone(),
+            streams: self.streams.clone(),
+            progress: AdapterProgress::default(),
+        }
+    }
+    fn processed(&mut self, data: &GenericDataProto) {
+        self.update_chain_head(data);
+        self.progress.block_number = Some(data.block_number);
+        self.progress.failure = None;
+        self.report();
+    }
+    fn failed(&mut self, data: &GenericDataProto, message: String) {
+        self.update_chain_head(data);
+        self.progress.failure = Some(AdapterFailure {
+            message,
+            block_number: data.block_number,
+            timestamp: SystemTime::now(),
+        });
+        self.report();
+    }
+    fn update_chain_head(&mut self, data: &GenericDataProto) {
+        let chain_head = std::cmp::max(data.chain_head, data.block_number);
+        if self.progress.chain_head.unwrap_or_default() < chain_head {
+            self.progress.chain_head = Some(chain_head);
+        }
+    }
+    fn report(&self) {
+        let mut streams = self.streams.lock().unwrap();
+        streams.insert(self.stream.clone(), self.progress.clone());
+        if let Some(sender) = &self.sender {
+            //Nobody is listening anymore, it's fine to ignore
+            let _ = sender.send(get_slowest_progress(&streams));
+        }
+    }
+}
+/// A failed stream first, then the stream with the largest lag behind its chain head
+fn get_slowest_progress(streams: &HashMap<String, AdapterProgress>) -> AdapterProgress {
+    streams
+        .values()
+        .max_by_key(|progress| {
+            let lag = match progress.block_number {
+                Some(block_number) => progress
+                    .chain_head
+                    .unwrap_or(block_number)
+                    .saturating_sub(block_number),
+                None => u64::MAX,
+            };
+            (progress.failure.is_some(), lag)
+        })
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Data sources read from the same stream of the chain reader
+struct DataSourceStream {
+    id: String,
+    chain_type: ChainType,
+    network: Option<NetworkType>,
+    data_sources: Vec<DataSource>,
+}
+
+/// One stream per (chain, network). Data sources keep their manifest order inside a stream
+fn group_by_stream(data_sources: &Vec<DataSource>) -> Vec<DataSourceStream> {
+    let mut streams: Vec<DataSourceStream> = vec![];
+    for data_source in data_sources {
+        let chain_type = get_chain_type(data_source);
+        let network = data_source.network.clone();
+        match streams
+            .iter_mut()
+            .find(|stream| stream.chain_type == chain_type && stream.network == network)
+        {
+            Some(stream) => stream.data_sources.push(data_source.clone()),
+            None => streams.push(DataSourceStream {
+                id: format!("{:?}/{}", chain_type, network.clone().unwrap_or_default())
+                    .to_lowercase(),
+                chain_type,
+                network,
+                data_sources: vec![data_source.clone()],
+            }),
+        }
+    }
+    streams
+}
+
 pub struct AdapterManager {
     //store: Option<dyn Store>,
     libs: HashMap<String, Arc<Library>>,
-    map_handlers: HashMap<String, AdapterHandler>,
+    map_handlers: HashMap<String, Arc<Mutex<AdapterHandler>>>,
+    control: Option<watch::Receiver<AdapterControl>>,
+    progress: Option<watch::Sender<AdapterProgress>>,
+    mapping_updates: Option<mpsc::Receiver<MappingUpdate>>,
 }
 
 impl AdapterManager {
@@ -85,11 +243,33 @@ impl AdapterManager {
             //store: None,
             libs: HashMap::default(),
             map_handlers: HashMap::default(),
+            control: None,
+            progress: None,
+            mapping_updates: None,
         }
     }
+    /// Let the caller pause and resume the stream loop of this adapter
+    pub fn with_control(mut self, control: watch::Receiver<AdapterControl>) -> AdapterManager {
+        self.control = Some(control);
+        self
+    }
+    /// Report the processed block, the chain head and the mapping errors to the caller
+    pub fn with_progress(mut self, progress: watch::Sender<AdapterProgress>) -> AdapterManager {
+        self.progress = Some(progress);
+        self
+    }
+    /// Let the caller replace the rust mapping while the adapter is running
+    pub fn with_mapping_updates(
+        mut self,
+        mapping_updates: mpsc::Receiver<MappingUpdate>,
+    ) -> AdapterManager {
+        self.mapping_updates = Some(mapping_updates);
+        self
+    }
     pub async fn init(
         &mut self,
         hash: &String,
+        namespace: &String,
         config: &Value,
         mapping: &PathBuf,
         schema: &PathBuf,
@@ -111,42 +291,41 @@ impl AdapterManager {
         }
 
         let arc_templates = Arc::new(templates);
-        //Todo: Currently adapter only works with one datasource
-        assert_eq!(
-            data_sources.len(),
-            1,
-            "Error: Number datasource: {} is not 1.",
-            data_sources.len()
-        );
+        let streams = group_by_stream(&data_sources);
+        // All the data sources share the mapping language of the first one
         match data_sources.get(0) {
             Some(data_source) => {
                 log::info!(
-                    "{} Init Streamout client for chain {} using language {}",
+                    "{} Init Streamout client for {} streams using language {} with {} data sources",
                     &*COMPONENT_NAME,
-                    &data_source.kind,
-                    &data_source.mapping.language
+                    streams.len(),
+                    &data_source.mapping.language,
+                    data_sources.len()
                 );
-                //let chain_type = get_chain_type(data_source);
                 let channel = Channel::from_static(CHAIN_READER_URL.as_str())
                     .connect()
                     .await?;
                 let timeout_channel =
                     Timeout::new(channel, Duration::from_secs(GET_BLOCK_TIMEOUT_SEC));
-                let mut client = StreamoutClient::new(timeout_channel);
+                let client = StreamoutClient::new(timeout_channel);
+                // Every stream writes into the same tables but keeps its own checkpoint
+                let store = StoreBuilder::create_store(hash.as_str(), namespace.as_str(), schema)?;
+                let reporter = ProgressReporter::new(self.progress.take());
                 match data_source.mapping.language.as_str() {
                     "wasm/assemblyscript" => {
                         self.handle_wasm_mapping(
                             hash,
-                            data_source,
+                            streams,
                             arc_templates.clone(),
-                            schema,
-                            &mut client,
+                            store,
+                            client,
+                            reporter,
                         )
                         .await
                     }
                     //Default use rust
                     _ => {
-                        self.handle_rust_mapping(hash, data_source, mapping, schema, &mut client)
+                        self.handle_rust_mapping(hash, streams, mapping, store, client, reporter)
                             .await
                     }
                 }
@@ -155,253 +334,616 @@ impl AdapterManager {
         }
     }
 
-    async fn handle_wasm_mapping<P: AsRef<Path>>(
-        &mut self,
+    async fn handle_wasm_mapping(
+        &self,
         indexer_hash: &String,
-        data_source: &DataSource,
+        streams: Vec<DataSourceStream>,
         templates: Arc<Vec<DataSourceTemplate>>,
-        schema_path: P,
-        client: &mut StreamoutClient<Timeout<Channel>>,
+        store: PostgresIndexStore,
+        client: StreamoutClient<Timeout<Channel>>,
+        reporter: ProgressReporter,
     ) -> Result<(), Box<dyn Error>> {
-        let store =
-            Arc::new(StoreBuilder::create_store(indexer_hash.as_str(), &schema_path).unwrap());
-        //let registry = Arc::new(MockMetricsRegistry::new());
-
         log::info!("{} Start mapping using wasm binary", &*COMPONENT_NAME);
-        let adapter_name = data_source
-            .kind
-            .split("/")
-            .collect::<Vec<&str>>()
-            .get(0)
-            .unwrap()
-            .to_string();
-        //Todo: store indexer state including start_block in db
-        let mut start_block = data_source.source.start_block as u64;
-        let chain_type = get_chain_type(data_source);
-        let mut opt_stream: Option<Streaming<GenericDataProto>> = None;
-        let mut handler_proxy = WasmHandlerProxyType::create_proxy(
-            &adapter_name,
-            indexer_hash,
-            store,
-            data_source.clone(), //Arc::clone(&valid_module),
-            templates,
-        );
-        if let Some(ref mut proxy) = handler_proxy {
-            loop {
-                match opt_stream {
-                    None => {
-                        log::info!(
-                            "Wasm mapping get new stream for chain {:?} from block {}.",
-                            &chain_type,
-                            start_block
-                        );
-                        opt_stream = try_create_stream(
-                            client,
-                            &chain_type,
-                            start_block,
-                            &data_source.network,
-                        )
-                        .await;
-                        if opt_stream.is_none() {
-                            //Sleep for a while and reconnect
-                            sleep(Duration::from_secs(GET_STREAM_TIMEOUT_SEC)).await;
-                        }
-                    }
-                    Some(ref mut stream) => {
-                        let response =
-                            timeout(Duration::from_secs(GET_BLOCK_TIMEOUT_SEC), stream.message())
-                                .await;
-                        match response {
-                            Ok(Ok(res)) => {
-                                if let Some(mut data) = res {
-                                    let data_type = DataType::from_i32(data.data_type).unwrap();
-                                    let data_chain_type =
-                                        ChainType::from_i32(data.chain_type).unwrap();
-                                    log::info!(
-                                        "{} Chain {:?} received data block = {:?}, hash = {:?}, data type = {:?}",
-                                        &*COMPONENT_NAME,
-                                        &data_chain_type,
-                                        data.block_number,
-                                        data.block_hash,
-                                        data_type
-                                    );
-                                    if data_chain_type == chain_type {
-                                        match proxy.handle_wasm_mapping(&mut data) {
-                                            Err(err) => {
-                                                log::error!(
-                                                    "{} Error while handle received message",
-                                                    err
-                                                );
-                                                start_block = data.block_number;
-                                            }
-                                            Ok(_) => {
-                                                start_block = data.block_number + 1;
-                                            }
-                                        }
-                                    } else {
-                                        log::error!("Chain type is not matched. Received {:?}, expected {:?}", data_chain_type, chain_type)
-                                    }
-                                } else {
-                                    log::warn!("Stream message response: {:?}", res)
-                                }
-                            }
-                            _ => {
-                                log::info!("Error while get message from reader stream {:?}. Destroy old stream", &response);
-                                opt_stream = None;
-                            }
-                        }
-                    }
+        let mut stream_loops = vec![];
+        for stream in streams {
+            let stream_store = Arc::new(store.for_stream(&stream.id));
+            let start_block = get_start_block(stream_store.as_ref(), &stream.data_sources);
+            let adapter_name = stream.data_sources[0]
+                .kind
+                .split("/")
+                .next()
+                .unwrap_or_default()
+                .to_string();
+            let handler_proxy = WasmHandlerProxyType::create_proxy(
+                &adapter_name,
+                indexer_hash,
+                stream_store.clone(),
+                store.schema.clone(),
+                stream.data_sources.clone(), //Arc::clone(&valid_module),
+                templates.clone(),
+            );
+            match handler_proxy {
+                Some(mut proxy) => {
+                    let stream_reporter = reporter.for_stream(&stream.id);
+                    stream_loops.push(run_stream(
+                        client.clone(),
+                        stream,
+                        start_block,
+                        stream_store.clone(),
+                        self.control.clone(),
+                        stream_reporter,
+                        move |data| proxy.handle_wasm_mapping(data),
+                    ));
                 }
+                None => log::debug!(
+                    "{} Cannot find adapter handler {} for stream {}",
+                    &*COMPONENT_NAME,
+                    adapter_name,
+                    &stream.id
+                ),
             }
-        };
+        }
+        try_join_all(stream_loops).await?;
         Ok(())
     }
 
     async fn handle_rust_mapping<P: AsRef<Path>>(
         &mut self,
         indexer_hash: &String,
-        data_source: &DataSource,
+        streams: Vec<DataSourceStream>,
         mapping_path: P,
-        schema_path: P,
-        client: &mut StreamoutClient<Timeout<Channel>>,
+        store: PostgresIndexStore,
+        client: StreamoutClient<Timeout<Channel>>,
+        reporter: ProgressReporter,
     ) -> Result<(), Box<dyn Error>> {
-        let store = StoreBuilder::create_store(indexer_hash.as_str(), &schema_path).unwrap();
-        let mut indexer_state = IndexerState::new(Arc::new(store));
+        // The plugin gets a single store. Streams take turns to use it
+        // and switch its underlying store so each one moves its own checkpoint
+        let indexer_state = Arc::new(Mutex::new(IndexerState::new(Arc::new(store.clone()))));
 
-        //Use unsafe to inject a store pointer into user's lib
+        //Use unsafe to inject a store pointer into user's lib.
+        //The deployment fails if the library can't be loaded
         unsafe {
-            match self
-                .load(
-                    indexer_hash,
-                    mapping_path.as_ref().as_os_str(),
-                    &indexer_state,
-                )
-                .await
-            {
+            let state = indexer_state.lock().unwrap();
+            match self.load(indexer_hash, mapping_path.as_ref().as_os_str(), &*state) {
                 Ok(_) => log::info!("{} Load library successfully", &*COMPONENT_NAME),
-                Err(err) => log::error!("Load library with error {:?}", err),
+                Err(err) => {
+                    log::error!("Load library with error {:?}", err);
+                    return Err(Box::new(AdapterError::new(&format!(
+                        "Cannot load mapping {:?}: {}",
+                        mapping_path.as_ref(),
+                        err
+                    ))));
+                }
             }
         }
         log::info!("{} Start mapping using rust", &*COMPONENT_NAME);
+        let adapter_handler = match self.map_handlers.get(indexer_hash.as_str()) {
+            Some(adapter_handler) => adapter_handler.clone(),
+            None => {
+                return Err(Box::new(AdapterError::new(&format!(
+                    "Cannot find adapter handler for indexer {}",
+                    &indexer_hash
+                ))));
+            }
+        };
+        let mut stream_loops = vec![];
+        let mut stream_stores = vec![];
+        let mut adapter_names: Vec<String> = vec![];
+        for stream in streams {
+            let handler_proxies =
+                get_handler_proxies(&*adapter_handler.lock().unwrap(), &stream.data_sources);
+            if handler_proxies.is_empty() {
+                log::debug!(
+                    "{} Cannot find proxy for any data source of stream {}",
+                    *COMPONENT_NAME,
+                    &stream.id
+                );
+                continue;
+            }
+            for (adapter_name, _) in &handler_proxies {
+                if !adapter_names.contains(adapter_name) {
+                    adapter_names.push(adapter_name.clone());
+                }
+            }
+            let stream_store = Arc::new(store.for_stream(&stream.id));
+            stream_stores.push(stream_store.clone());
+            let start_block = get_start_block(stream_store.as_ref(), &stream.data_sources);
+            let stream_state = indexer_state.clone();
+            let stream_handler = adapter_handler.clone();
+            let stream_reporter = reporter.for_stream(&stream.id);
+            stream_loops.push(run_stream(
+                client.clone(),
+                stream,
+                start_block,
+                stream_store.clone(),
+                self.control.clone(),
+                stream_reporter,
+                move |data| {
+                    let mut state = stream_state.lock().unwrap();
+                    state.store = stream_store.clone();
+                    let handler = stream_handler.lock().unwrap();
+                    let result =
+                        dispatch_rust_mapping(&*handler, &handler_proxies, data, &mut *state);
+                    if result.is_err() {
+                        //Drop what the handlers saved before failing, the block will be handled again
+                        state.entity_cache = IndexerState::create_entity_cache(&state.store);
+                    }
+                    result
+                },
+            ));
+        }
+        let mapping_updates = listen_mapping_updates(
+            self.mapping_updates.take(),
+            indexer_hash,
+            MappingSlot {
+                adapter_handler,
+                adapter_names,
+                indexer_state,
+                stream_stores,
+            },
+            &mut self.libs,
+        );
+        //The adapter stops with its streams, the mapping updates only run alongside them.
+        //A stream which can't go on stops the others
+        match select(
+            Box::pin(try_join_all(stream_loops)),
+            Box::pin(mapping_updates),
+        )
+        .await
+        {
+            Either::Left((Err(err), _)) => Err(err),
+            _ => Ok(()),
+        }
+    }
+    /// Load a plugin library
+    /// A plugin library **must** be implemented using the
+    /// [`model::adapter_declaration!()`] macro. Trying manually implement
+    /// a plugin without going through that macro will result in undefined
+    /// behaviour.
+    pub unsafe fn load<P: AsRef<OsStr>>(
+        &mut self,
+        indexer_hash: &String,
+        library_path: P,
+        store: &dyn Store,
+    ) -> Result<(), Box<dyn Error>> {
+        let registrar = load_adapter_handler(indexer_hash, library_path, store)?;
+        self.libs
+            .insert(indexer_hash.clone(), Arc::clone(&registrar.lib));
+        self.map_handlers
+            .insert(indexer_hash.clone(), Arc::new(Mutex::new(registrar)));
+        Ok(())
+    }
+    /// Unload the plugin library of an indexer.
+    /// The handlers are dropped before the library, so no code of the library runs once it is unloaded
+    pub fn unload(&mut self, indexer_hash: &String) {
+        self.map_handlers.remove(indexer_hash);
+        self.libs.remove(indexer_hash);
+    }
+}
+/// Open a plugin library, inject the store and get the handlers it registers
+unsafe fn load_adapter_handler<P: AsRef<OsStr>>(
+    indexer_hash: &String,
+    library_path: P,
+    store: &dyn Store,
+) -> Result<AdapterHandler, Box<dyn Error>> {
+    let lib = Arc::new(Library::new(library_path)?);
+    // inject store to plugin
+    lib.get::<*mut Option<&dyn Store>>(b"STORE\0")?
+        .write(Some(store));
+    let adapter_decl = lib
+        .get::<*mut AdapterDeclaration>(b"adapter_declaration\0")?
+        .read();
+    let mut registrar = AdapterHandler::new(indexer_hash.clone(), Arc::clone(&lib));
+    (adapter_decl.register)(&mut registrar);
+    Ok(registrar)
+}
+/// What the streams of a rust mapping share, so its library can be replaced while they run
+struct MappingSlot {
+    adapter_handler: Arc<Mutex<AdapterHandler>>,
+    adapter_names: Vec<String>,
+    indexer_state: Arc<Mutex<IndexerState>>,
+    stream_stores: Vec<Arc<PostgresIndexStore>>,
+}
+/// Replace the library of the rust mapping each time the caller asks for it, never returns
+async fn listen_mapping_updates(
+    mapping_updates: Option<mpsc::Receiver<MappingUpdate>>,
+    indexer_hash: &String,
+    slot: MappingSlot,
+    libs: &mut HashMap<String, Arc<Library>>,
+) {
+    let mut mapping_updates = match mapping_updates {
+        Some(mapping_updates) => mapping_updates,
+        None => return pending().await,
+    };
+    while let Some(update) = mapping_updates.recv().await {
+        let result = swap_mapping(indexer_hash, &update.mapping, &slot, libs);
+        match &result {
+            Ok(_) => log::info!(
+                "{} Indexer {} uses mapping {:?}",
+                &*COMPONENT_NAME,
+                indexer_hash,
+                &update.mapping
+            ),
+            Err(err) => log::error!(
+                "{} Cannot update mapping of indexer {}: {}",
+                &*COMPONENT_NAME,
+                indexer_hash,
+                err
+            ),
+        }
+        //The caller may have given up waiting, the mapping is updated anyway
+        let _ = update.result.send(result.map_err(|err| err.to_string()));
+    }
+    //The caller is gone, the streams go on with the current mapping
+    pending().await
+}
+/// The streams lock the indexer state for a whole block, so holding it stops them at a block boundary.
+/// The pending blocks are committed, then the new library replaces the old one if it handles every adapter
+/// of the streams. The streams go on from their checkpoint with the new handlers once the state is released.
+fn swap_mapping(
+    indexer_hash: &String,
+    mapping: &PathBuf,
+    slot: &MappingSlot,
+    libs: &mut HashMap<String, Arc<Library>>,
+) -> Result<(), Box<dyn Error>> {
+    let state = slot.indexer_state.lock().unwrap();
+    for store in &slot.stream_stores {
+        store.flush_batch()?;
+    }
+    let new_handler = unsafe { load_adapter_handler(indexer_hash, mapping.as_os_str(), &*state)? };
+    if let Some(adapter_name) = slot
+        .adapter_names
+        .iter()
+        .find(|adapter_name| !new_handler.handler_proxies.contains_key(*adapter_name))
+    {
+        return Err(Box::new(AdapterError::new(&format!(
+            "Mapping {:?} has no handler for adapter {}",
+            mapping, adapter_name
+        ))));
+    }
+    let old_handler = {
+        let mut adapter_handler = slot.adapter_handler.lock().unwrap();
+        libs.insert(indexer_hash.clone(), Arc::clone(&new_handler.lib));
+        std::mem::replace(&mut *adapter_handler, new_handler)
+    };
+    //The proxies hold the old library, it is unloaded with the last of them
+    drop(old_handler);
+    Ok(())
+}
+/// Resume from the block after the last processed block if the indexer has a checkpoint,
+/// otherwise start from the lowest start block of the data sources
+fn get_start_block(store: &dyn WritableStore, data_sources: &Vec<DataSource>) -> u64 {
+    let start_block = data_sources
+        .iter()
+        .map(|data_source| data_source.source.start_block as u64)
+        .min()
+        .unwrap_or_default();
+    match store.block_ptr() {
+        Ok(Some(block_ptr)) => {
+            let next_block = block_ptr.number as u64 + 1;
+            log::info!(
+                "{} Found checkpoint at block {}",
+                &*COMPONENT_NAME,
+                block_ptr.number
+            );
+            std::cmp::max(start_block, next_block)
+        }
+        Ok(None) => start_block,
+        Err(err) => {
+            log::error!(
+                "{} Cannot load checkpoint, start from block {}: {:?}",
+                &*COMPONENT_NAME,
+                start_block,
+                err
+            );
+            start_block
+        }
+    }
+}
+/// The plugin registers one handler per chain, so data sources of the same chain share it.
+/// Return the names of the handlers in manifest order with the lowest start block of their data sources.
+fn get_handler_proxies(
+    adapter_handler: &AdapterHandler,
+    data_sources: &Vec<DataSource>,
+) -> Vec<(String, u64)> {
+    let mut handler_proxies: Vec<(String, u64)> = vec![];
+    for data_source in data_sources {
         let adapter_name = data_source
             .kind
             .split("/")
-            .collect::<Vec<&str>>()
-            .get(0)
-            .unwrap()
+            .next()
+            .unwrap_or_default()
             .to_string();
-        if let Some(adapter_handler) = self.map_handlers.get_mut(indexer_hash.as_str()) {
-            if let Some(handler_proxy) = adapter_handler.handler_proxies.get(&adapter_name) {
-                let mut start_block = data_source.source.start_block as u64;
-                let chain_type = get_chain_type(data_source);
-                let mut opt_stream: Option<Streaming<GenericDataProto>> = None;
+        let start_block = data_source.source.start_block as u64;
+        if let Some(proxy) = handler_proxies
+            .iter_mut()
+            .find(|(name, _)| name == &adapter_name)
+        {
+            proxy.1 = std::cmp::min(proxy.1, start_block);
+            continue;
+        }
+        match adapter_handler.handler_proxies.get(&adapter_name) {
+            Some(_) => handler_proxies.push((adapter_name, start_block)),
+            None => log::debug!(
+                "{} Cannot find proxy for adapter {} of data source {}",
+                *COMPONENT_NAME,
+                adapter_name,
+                &data_source.name
+            ),
+        }
+    }
+    handler_proxies
+}
+/// Send the data to the handlers in manifest order, skipping the ones which start after this block.
+/// The handlers are looked up for each block, so the ones of a new library are used as soon as it is loaded
+fn dispatch_rust_mapping(
+    adapter_handler: &AdapterHandler,
+    handler_proxies: &Vec<(String, u64)>,
+    data: &mut GenericDataProto,
+    store: &mut dyn Store,
+) -> Result<(), Box<dyn Error>> {
+    for (adapter_name, start_block) in handler_proxies {
+        if data.block_number >= *start_block {
+            if let Some(handler_proxy) = adapter_handler.handler_proxies.get(adapter_name) {
+                handler_proxy.handle_rust_mapping(data, store)?;
+            }
+        }
+    }
+    Ok(())
+}
+/// Wait until the indexer is resumed if it is paused.
+/// Return true if the loop has been paused.
+async fn wait_while_paused(
+    control: &mut Option<watch::Receiver<AdapterControl>>,
+    store: &PostgresIndexStore,
+) -> bool {
+    let mut paused = false;
+    if let Some(receiver) = control {
+        while *receiver.borrow() == AdapterControl::Paused {
+            if !paused {
+                log::info!("{} Indexer is paused", &*COMPONENT_NAME);
+                flush_store(store);
+                paused = true;
+            }
+            //Sender is dropped, nobody can resume the indexer anymore so just continue
+            if receiver.changed().await.is_err() {
+                break;
+            }
+        }
+    }
+    if paused && !is_stopped(control) {
+        log::info!("{} Indexer is resumed", &*COMPONENT_NAME);
+    }
+    paused
+}
+fn is_stopped(control: &Option<watch::Receiver<AdapterControl>>) -> bool {
+    match control {
+        Some(receiver) => *receiver.borrow() == AdapterControl::Stopped,
+        None => false,
+    }
+}
+/// Resolve once the indexer is stopped, never if nobody can stop it
+async fn wait_for_stop(control: &mut Option<watch::Receiver<AdapterControl>>) {
+    if let Some(receiver) = control {
+        while *receiver.borrow() != AdapterControl::Stopped {
+            //Sender is dropped, nobody can stop the indexer anymore
+            if receiver.changed().await.is_err() {
+                break;
+            }
+        }
+        if *receiver.borrow() == AdapterControl::Stopped {
+            return;
+        }
+    }
+    pending().await
+}
+/// Wait for `future` unless the indexer is stopped first, then return None
+async fn unless_stopped<F: Future>(
+    future: F,
+    control: &mut Option<watch::Receiver<AdapterControl>>,
+) -> Option<F::Output> {
+    match select(Box::pin(future), Box::pin(wait_for_stop(control))).await {
+        Either::Left((output, _)) => Some(output),
+        Either::Right(_) => None,
+    }
+}
+/// Read the blocks of one stream from the chain reader and pass them to the handler
+/// until the indexer is stopped. The stream is recreated from the next block when it times out.
+/// The blocks buffered by the store are committed when the stream is idle, paused or stopped.
+/// A stop interrupts the waits for the chain reader, never the handling of a block.
+/// The stream returns an error when a reorg can't be reverted, its next blocks would be written
+/// on top of the reverted ones.
+async fn run_stream<F>(
+    mut client: StreamoutClient<Timeout<Channel>>,
+    stream: DataSourceStream,
+    mut start_block: u64,
+    store: Arc<PostgresIndexStore>,
+    mut control: Option<watch::Receiver<AdapterControl>>,
+    mut reporter: ProgressReporter,
+    mut handler: F,
+) -> Result<(), Box<dyn Error>>
+where
+    F: FnMut(&mut GenericDataProto) -> Result<(), Box<dyn Error>>,
+{
+    let first_block = start_block;
+    let mut opt_stream: Option<Streaming<GenericDataProto>> = None;
+    //The error of a failed block is kept in the store until the block is handled
+    let mut failed = false;
+    loop {
+        if wait_while_paused(&mut control, &store).await {
+            //Stream is probably timed out while pausing
+            opt_stream = None;
+        }
+        if is_stopped(&control) {
+            break;
+        }
+        match opt_stream {
+            None => {
                 log::info!(
-                    "Rust mapping get new stream for chain {:?} from block {}.",
-                    &chain_type,
+                    "{} Get new stream {} from block {}.",
+                    &*COMPONENT_NAME,
+                    &stream.id,
                     start_block
                 );
-                loop {
-                    match opt_stream {
-                        None => {
-                            opt_stream = try_create_stream(
-                                client,
-                                &chain_type,
-                                start_block,
-                                &data_source.network,
-                            )
-                            .await;
-                            if opt_stream.is_none() {
-                                //Sleep for a while and reconnect
-                                sleep(Duration::from_secs(GET_STREAM_TIMEOUT_SEC)).await;
-                            }
-                        }
-                        Some(ref mut stream) => {
-                            let response = timeout(
-                                Duration::from_secs(GET_BLOCK_TIMEOUT_SEC),
-                                stream.message(),
-                            )
-                            .await;
-                            match response {
-                                Ok(Ok(res)) => {
-                                    if let Some(mut data) = res {
-                                        let data_type = DataType::from_i32(data.data_type).unwrap();
-                                        let data_chain_type =
-                                            ChainType::from_i32(data.chain_type).unwrap();
-                                        log::info!(
-                                            "{} Chain {:?} received data block = {:?}, hash = {:?}, data type = {:?}",
+                opt_stream = match unless_stopped(
+                    try_create_stream(
+                        &mut client,
+                        &stream.chain_type,
+                        start_block,
+                        &stream.network,
+                    ),
+                    &mut control,
+                )
+                .await
+                {
+                    Some(opt_stream) => opt_stream,
+                    None => break,
+                };
+                if opt_stream.is_none() {
+                    //Sleep for a while and reconnect
+                    let wait = sleep(Duration::from_secs(GET_STREAM_TIMEOUT_SEC));
+                    if unless_stopped(wait, &mut control).await.is_none() {
+                        break;
+                    }
+                }
+            }
+            Some(ref mut data_stream) => {
+                let response = match unless_stopped(
+                    timeout(
+                        Duration::from_secs(GET_BLOCK_TIMEOUT_SEC),
+                        data_stream.message(),
+                    ),
+                    &mut control,
+                )
+                .await
+                {
+                    Some(response) => response,
+                    None => break,
+                };
+                match response {
+                    Ok(Ok(res)) => {
+                        if let Some(mut data) = res {
+                            let data_chain_type = ChainType::from_i32(data.chain_type).unwrap();
+                            log::info!(
+                                "{} Stream {} received data block = {:?}, hash = {:?}, data type = {:?}",
+                                &*COMPONENT_NAME,
+                                &stream.id,
+                                data.block_number,
+                                data.block_hash,
+                                DataType::from_i32(data.data_type).unwrap()
+                            );
+                            if data_chain_type != stream.chain_type {
+                                log::error!(
+                                    "Chain type is not matched. Received {:?}, expected {:?}",
+                                    data_chain_type,
+                                    stream.chain_type
+                                )
+                            } else if data.data_type == DataType::Rollback as i32 {
+                                //The blocks after this one are not on the chain anymore
+                                match store.revert_block_operations(get_block_ptr(&data)) {
+                                    Ok(_) => {
+                                        start_block = data.block_number + 1;
+                                        reporter.processed(&data);
+                                    }
+                                    Err(err) => {
+                                        log::error!(
+                                            "{} Cannot revert stream {} to block {}: {:?}",
                                             &*COMPONENT_NAME,
-                                            &data_chain_type,
+                                            &stream.id,
                                             data.block_number,
-                                            data.block_hash,
-                                            DataType::from_i32(data.data_type).unwrap()
+                                            err
                                         );
-                                        if data_chain_type == chain_type {
-                                            match handler_proxy
-                                                .handle_rust_mapping(&mut data, &mut indexer_state)
-                                            {
-                                                Err(err) => {
-                                                    log::error!(
-                                                        "{} Error while handle received message",
-                                                        err
-                                                    );
-                                                    start_block = data.block_number;
-                                                }
-                                                Ok(_) => {
-                                                    start_block = data.block_number + 1;
-                                                }
+                                        reporter.failed(&data, err.to_string());
+                                        //The pending blocks may be on the reverted fork
+                                        store.discard_batch();
+                                        return Err(Box::new(AdapterError::new(&format!(
+                                            "Cannot revert stream {} to block {}: {}",
+                                            &stream.id, data.block_number, err
+                                        ))));
+                                    }
+                                }
+                            } else {
+                                store.set_chain_head(data.chain_head);
+                                match handler(&mut data) {
+                                    Err(err) => {
+                                        log::error!("{} Error while handle received message", err);
+                                        //The pending blocks are dropped with the failed one,
+                                        //read again from the last committed block
+                                        store.discard_batch();
+                                        start_block = match store.block_ptr() {
+                                            Ok(Some(block_ptr)) => block_ptr.number as u64 + 1,
+                                            _ => first_block,
+                                        };
+                                        if !failed {
+                                            let error = SubgraphError {
+                                                subgraph_id: DEPLOYMENT_HASH.clone(),
+                                                message: err.to_string(),
+                                                block_ptr: Some(get_block_ptr(&data)),
+                                                handler: None,
+                                                deterministic: false,
+                                            };
+                                            if let Err(err) = store.fail_subgraph(error).await {
+                                                log::error!("Cannot save failure {:?}", err);
                                             }
-                                        } else {
-                                            log::error!("Chain type is not matched. Received {:?}, expected {:?}", data_chain_type, chain_type)
+                                            failed = true;
+                                        }
+                                        reporter.failed(&data, err.to_string());
+                                        //Nothing of this block is committed, read it again from the reader
+                                        //instead of going on with the next blocks
+                                        opt_stream = None;
+                                        let wait =
+                                            sleep(Duration::from_secs(GET_STREAM_TIMEOUT_SEC));
+                                        if unless_stopped(wait, &mut control).await.is_none() {
+                                            break;
                                         }
                                     }
-                                }
-                                _ => {
-                                    log::info!("Error while get message from reader stream {:?}. Recreate stream", &response);
-                                    opt_stream = None;
+                                    Ok(_) => {
+                                        start_block = data.block_number + 1;
+                                        if failed {
+                                            if let Err(err) = store.unfail() {
+                                                log::error!("Cannot remove failure {:?}", err);
+                                            }
+                                            failed = false;
+                                        }
+                                        reporter.processed(&data);
+                                    }
                                 }
                             }
+                        } else {
+                            log::warn!("Stream message response: {:?}", res)
                         }
                     }
+                    _ => {
+                        log::info!(
+                            "Error while get message from reader stream {:?}. Recreate stream",
+                            &response
+                        );
+                        //No new block for a while, do not keep the pending ones waiting
+                        flush_store(&store);
+                        opt_stream = None;
+                    }
                 }
-            } else {
-                log::debug!(
-                    "{} Cannot find proxy for adapter {}",
-                    *COMPONENT_NAME,
-                    adapter_name
-                );
             }
-        } else {
-            log::debug!(
-                "{} Cannot find adapter handler for indexer {}",
-                &*COMPONENT_NAME,
-                &indexer_hash
-            );
         }
-        Ok(())
     }
-    /// Load a plugin library
-    /// A plugin library **must** be implemented using the
-    /// [`model::adapter_declaration!()`] macro. Trying manually implement
-    /// a plugin without going through that macro will result in undefined
-    /// behaviour.
-    pub async unsafe fn load<P: AsRef<OsStr>>(
-        &mut self,
-        indexer_hash: &String,
-        library_path: P,
-        store: &dyn Store,
-    ) -> Result<(), Box<dyn Error>> {
-        let lib = Arc::new(Library::new(library_path)?);
-        // inject store to plugin
-        lib.get::<*mut Option<&dyn Store>>(b"STORE\0")?
-            .write(Some(store));
-        let adapter_decl = lib
-            .get::<*mut AdapterDeclaration>(b"adapter_declaration\0")?
-            .read();
-        let mut registrar = AdapterHandler::new(indexer_hash.clone(), Arc::clone(&lib));
-        (adapter_decl.register)(&mut registrar);
-        self.map_handlers.insert(indexer_hash.clone(), registrar);
-        self.libs.insert(indexer_hash.clone(), lib);
-        Ok(())
+    flush_store(&store);
+    log::info!("{} Stream {} is stopped", &*COMPONENT_NAME, &stream.id);
+    Ok(())
+}
+/// Commit the blocks buffered by the store, they are tried again with the next flush if it fails
+fn flush_store(store: &PostgresIndexStore) {
+    if let Err(err) = store.flush_batch() {
+        log::error!(
+            "{} Cannot commit pending blocks {:?}",
+            &*COMPONENT_NAME,
+            err
+        );
+    }
+}
+fn get_block_ptr(data: &GenericDataProto) -> BlockPtr {
+    BlockPtr {
+        hash: BlockHash::from(checkpoint::block_hash_bytes(&data.block_hash)),
+        number: data.block_number as i32,
     }
 }
 async fn try_create_stream(
@@ -467,3 +1009,168 @@ impl Error for AdapterError {
         &self.0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::data::subgraph::{Link, Source};
+    use graph::prelude::ethabi::Contract;
+    use graph::semver::Version;
+    use graph_chain_ethereum::{Mapping, MappingABI};
+
+    fn progress(block_number: Option<u64>, chain_head: Option<u64>) -> AdapterProgress {
+        AdapterProgress {
+            block_number,
+            chain_head,
+            failure: None,
+        }
+    }
+
+    fn streams(progresses: Vec<(&str, AdapterProgress)>) -> HashMap<String, AdapterProgress> {
+        progresses
+            .into_iter()
+            .map(|(stream, progress)| (stream.to_string(), progress))
+            .collect()
+    }
+
+    #[test]
+    fn stream_most_behind_its_chain_head_is_the_slowest() {
+        let slowest = get_slowest_progress(&streams(vec![
+            ("ethereum/matic", progress(Some(990), Some(1000))),
+            ("solana/mainnet", progress(Some(500), Some(600))),
+            ("substrate/", progress(Some(10), Some(15))),
+        ]));
+        assert_eq!(slowest.block_number, Some(500));
+        assert_eq!(slowest.chain_head, Some(600));
+    }
+
+    #[test]
+    fn stream_without_progress_is_the_slowest() {
+        let slowest = get_slowest_progress(&streams(vec![
+            ("ethereum/matic", progress(Some(10), Some(1000))),
+            ("solana/mainnet", progress(None, Some(600))),
+        ]));
+        assert_eq!(slowest.block_number, None);
+    }
+
+    #[test]
+    fn failed_stream_is_reported_first() {
+        let mut failed = progress(Some(1000), Some(1000));
+        failed.failure = Some(AdapterFailure {
+            message: String::from("mapping error"),
+            block_number: 1001,
+            timestamp: SystemTime::now(),
+        });
+        let slowest = get_slowest_progress(&streams(vec![
+            ("ethereum/matic", progress(None, None)),
+            ("solana/mainnet", failed),
+        ]));
+        assert_eq!(slowest.block_number, Some(1000));
+        assert!(slowest.failure.is_some());
+    }
+
+    #[test]
+    fn no_stream_has_no_progress() {
+        let slowest = get_slowest_progress(&HashMap::new());
+        assert_eq!(slowest.block_number, None);
+        assert_eq!(slowest.chain_head, None);
+    }
+
+    fn data_source(name: &str, kind: &str, network: Option<&str>) -> DataSource {
+        let contract_abi = Arc::new(MappingABI {
+            name: String::from("Factory"),
+            contract: Contract::load(&b"[]"[..]).unwrap(),
+        });
+        DataSource {
+            kind: kind.to_string(),
+            network: network.map(|network| network.to_string()),
+            name: name.to_string(),
+            source: Source {
+                address: None,
+                abi: String::from("Factory"),
+                start_block: 0,
+            },
+            mapping: Mapping {
+                kind: String::from("ethereum/events"),
+                api_version: Version::new(0, 0, 4),
+                language: String::from("rust"),
+                entities: vec![],
+                abis: vec![contract_abi.clone()],
+                block_handlers: vec![],
+                call_handlers: vec![],
+                event_handlers: vec![],
+                runtime: Arc::new(vec![]),
+                link: Link {
+                    link: String::from("mapping"),
+                },
+            },
+            context: Default::default(),
+            creation_block: None,
+            contract_abi,
+        }
+    }
+
+    fn stream_names(streams: &[DataSourceStream]) -> Vec<(String, Vec<String>)> {
+        streams
+            .iter()
+            .map(|stream| {
+                let names = stream
+                    .data_sources
+                    .iter()
+                    .map(|data_source| data_source.name.clone())
+                    .collect();
+                (stream.id.clone(), names)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn data_sources_are_grouped_by_chain_and_network() {
+        let streams = group_by_stream(&vec![
+            data_source("factory", "ethereum/contract", Some("matic")),
+            data_source("program", "solana", Some("mainnet")),
+            data_source("pair", "ethereum/contract", Some("matic")),
+            data_source("token", "ethereum/contract", Some("bsc")),
+            data_source("staking", "solana", Some("mainnet")),
+        ]);
+        assert_eq!(
+            stream_names(&streams),
+            vec![
+                (
+                    String::from("ethereum/matic"),
+                    vec![String::from("factory"), String::from("pair")]
+                ),
+                (
+                    String::from("solana/mainnet"),
+                    vec![String::from("program"), String::from("staking")]
+                ),
+                (String::from("ethereum/bsc"), vec![String::from("token")]),
+            ]
+        );
+        assert_eq!(streams[0].chain_type, ChainType::Ethereum);
+        assert_eq!(streams[0].network, Some(String::from("matic")));
+    }
+
+    #[test]
+    fn data_sources_without_a_network_share_a_stream() {
+        let streams = group_by_stream(&vec![
+            data_source("balances", "substrate", None),
+            data_source("transfers", "substrate", None),
+            data_source("events", "substrate", Some("polkadot")),
+        ]);
+        assert_eq!(
+            stream_names(&streams),
+            vec![
+                (
+                    String::from("substrate/"),
+                    vec![String::from("balances"), String::from("transfers")]
+                ),
+                (
+                    String::from("substrate/polkadot"),
+                    vec![String::from("events")]
+                ),
+            ]
+        );
+        assert_eq!(streams[0].network, None);
+    }
+}
@@ -5,8 +5,9 @@ use graph::blockchain::types::{BlockHash, BlockPtr};
 use graph::blockchain::{Blockchain, DataSource as DataSourceTrait, HostFn};
 use graph::cheap_clone::CheapClone;
 use graph::components::metrics::stopwatch::StopwatchMetrics;
-use graph::components::store::{ModificationsAndCache, StoreError, WritableStore};
+use graph::components::store::{ModificationsAndCache, StoreError};
 use graph::components::subgraph::{BlockState, HostMetrics};
+use graph::data::schema::Schema;
 use graph::data::subgraph::DeploymentHash;
 use graph::log::logger;
 use graph_chain_ethereum::trigger::EthereumBlockTriggerType;
@@ -16,6 +17,7 @@ use graph_chain_ethereum::{
 };
 use graph_mock::MockMetricsRegistry;
 use graph_runtime_wasm::ValidModule;
+use index_store::postgres::checkpoint;
 use index_store::postgres::store_builder::*;
 use index_store::Store;
 use libloading::Library;
@@ -59,25 +61,57 @@ impl MessageHandler for EthereumWasmHandlerProxy {
                 let block_finality: Arc<<Chain as Blockchain>::Block> =
                     Arc::new(BlockFinality::Final(arc_block.clone()));
                 let block_ptr = BlockPtr {
-                    hash: BlockHash(data.block_hash.as_bytes().into()),
+                    hash: BlockHash::from(checkpoint::block_hash_bytes(&data.block_hash)),
                     number: data.block_number as i32,
                 };
                 let data_sources = self.data_sources.clone();
                 log::info!("Cloned data_sources at {:?}", start.elapsed());
-                data_sources.into_iter().for_each(|data_source| {
-                    //wasm_instance for each datasource
-                    let mut wasm_instance: Option<WasmInstance<Chain>> = None;
+                //The data sources of the block share one state, so a data source sees the entities
+                //of the previous ones and the whole block is written at once
+                let mut block_state = BlockState::new(self.store.clone(), Default::default());
+                let mut created_data_sources = vec![];
+                //Data sources are matched in manifest order, each one from its own start block
+                for data_source in data_sources {
+                    if (data_source.source.start_block as u64) > data.block_number {
+                        continue;
+                    }
                     self.matching_block(
                         &logger,
-                        &mut wasm_instance,
+                        &mut block_state,
+                        &mut created_data_sources,
                         &data_source,
                         &eth_block,
                         block_finality.clone(),
                         &block_ptr,
                         registry.cheap_clone(),
-                        stopwatch.cheap_clone(),
+                    )?;
+                }
+                let ModificationsAndCache {
+                    modifications: mods,
+                    data_sources,
+                    entity_lfu_cache: _cache,
+                } = block_state
+                    .entity_cache
+                    .as_modifications()
+                    .map_err(|e| StoreError::Unknown(e.into()))?;
+                // One call per block, even without entities so the block pointer of the indexer moves forward.
+                // The block is handled again from the checkpoint if it can not be written
+                self.store.transact_block_operations(
+                    block_ptr.cheap_clone(),
+                    mods,
+                    stopwatch.cheap_clone(),
+                    data_sources,
+                    vec![],
+                )?;
+                // The templates created in this block are matched from the next block on
+                for data_source in created_data_sources {
+                    log::info!(
+                        "New datasource #{} with source: {:?}",
+                        self.data_sources.len() + 1,
+                        &data_source.source
                     );
-                });
+                    self.add_data_source(data_source);
+                }
             }
             _ => {}
         }
@@ -123,6 +157,7 @@ impl EthereumWasmHandlerProxy {
     fn prepare_wasm_instance(
         &mut self,
         wasm_instance: &mut Option<WasmInstance<Chain>>,
+        block_state: &mut BlockState<Chain>,
         data_source: &DataSource,
         registry: Arc<MockMetricsRegistry>,
         block_ptr: &BlockPtr,
@@ -130,12 +165,18 @@ impl EthereumWasmHandlerProxy {
         if wasm_instance.is_none() {
             let valid_module = self.prepare_wasm_module(data_source);
             let ethereum_call = self.get_ethereum_call(data_source);
+            //The instance holds the state of the block until it is done with it
+            let state = std::mem::replace(
+                block_state,
+                BlockState::new(self.store.clone(), Default::default()),
+            );
             *wasm_instance = Some(
                 load_wasm(
                     &self.indexer_hash,
                     data_source,
                     self.templates.clone(),
-                    self.store.clone(),
+                    state,
+                    self.schema.clone(),
                     valid_module,
                     ethereum_call,
                     registry,
@@ -145,17 +186,21 @@ impl EthereumWasmHandlerProxy {
             );
         }
     }
+    /// Run the handlers of the data source, then the ones of the templates it creates, on the block.
+    /// The entities are kept in `block_state`, the created templates in `created_data_sources`
     fn matching_block(
         &mut self,
         logger: &Logger,
-        wasm_instance: &mut Option<WasmInstance<Chain>>,
+        block_state: &mut BlockState<Chain>,
+        created_data_sources: &mut Vec<DataSource>,
         data_source: &DataSource,
         eth_block: &EthereumBlock,
         block_finality: Arc<<Chain as Blockchain>::Block>,
         block_ptr: &BlockPtr,
         registry: Arc<MockMetricsRegistry>,
-        stopwatch: StopwatchMetrics,
-    ) {
+    ) -> Result<(), Box<dyn Error>> {
+        //wasm_instance for each datasource
+        let mut wasm_instance: Option<WasmInstance<Chain>> = None;
         //Trigger block
         let block_trigger: <Chain as Blockchain>::TriggerData =
             EthereumTrigger::Block(block_ptr.cheap_clone(), EthereumBlockTriggerType::Every);
@@ -164,7 +209,8 @@ impl EthereumWasmHandlerProxy {
                 if let Some(trigger) = mapping_trigger {
                     log::info!("Block Mapping trigger found");
                     self.prepare_wasm_instance(
-                        wasm_instance,
+                        &mut wasm_instance,
+                        block_state,
                         data_source,
                         registry.cheap_clone(),
                         block_ptr,
@@ -178,14 +224,15 @@ impl EthereumWasmHandlerProxy {
         }
 
         //Mapping trigger log
-        eth_block.logs.iter().for_each(|log| {
+        for log in eth_block.logs.iter() {
             let arc_log = Arc::new(log.clone());
             let trigger: <Chain as Blockchain>::TriggerData = EthereumTrigger::Log(arc_log);
             match data_source.match_and_decode(&trigger, block_finality.clone(), logger) {
                 Ok(mapping_trigger) => {
                     if let Some(trigger) = mapping_trigger {
                         self.prepare_wasm_instance(
-                            wasm_instance,
+                            &mut wasm_instance,
+                            block_state,
                             data_source,
                             registry.cheap_clone(),
                             block_ptr,
@@ -197,67 +244,50 @@ impl EthereumWasmHandlerProxy {
                     log::error!("Try match EthereumTrigger::Log with error {:?}", err);
                 }
             }
-        });
-        if let Some(instance) = wasm_instance {
+        }
+        if let Some(instance) = wasm_instance.as_mut() {
             let mut context = instance.take_ctx();
-            let _has_created_data_sources = context.ctx.state.has_created_data_sources();
-            let data_source_infos = context.ctx.state.drain_created_data_sources();
-            let ModificationsAndCache {
-                modifications: mods,
-                data_sources,
-                entity_lfu_cache: _cache,
-            } = context
-                .ctx
-                .state
-                .entity_cache
-                .as_modifications()
-                .map_err(|e| {
-                    log::error!("Error {:?}", e);
-                    StoreError::Unknown(e.into())
-                })
-                .unwrap();
-
-            // Transact entity modifications into the store
-            if mods.len() > 0 {
-                match self.store.transact_block_operations(
-                    block_ptr.cheap_clone(),
-                    mods,
-                    stopwatch.cheap_clone(),
-                    data_sources,
-                    vec![],
-                ) {
-                    Ok(_) => log::info!("Transact block operation successfully"),
-                    Err(err) => log::info!("Transact block operation with error {:?}", err),
-                }
+            //Give the state of the block, with the entities of this data source, back to the next ones
+            *block_state = std::mem::replace(
+                &mut context.ctx.state,
+                BlockState::new(self.store.clone(), Default::default()),
+            );
+            //A handler which failed deterministically, e.g. an entity not matching the schema,
+            //fails the block instead of skipping the handler
+            if let Some(error) = block_state.deterministic_errors.first() {
+                return Err(format!(
+                    "Handler {} of data source {} failed: {}",
+                    error.handler.as_deref().unwrap_or_default(),
+                    &data_source.name,
+                    &error.message
+                )
+                .into());
             }
+            let data_source_infos = block_state.drain_created_data_sources();
             for ds_template_info in data_source_infos {
-                let data_source = DataSource::try_from(ds_template_info).unwrap();
-                let mut wasm_instance: Option<WasmInstance<Chain>> = None;
+                let data_source = DataSource::try_from(ds_template_info)?;
                 self.matching_block(
                     &logger,
-                    &mut wasm_instance,
+                    block_state,
+                    created_data_sources,
                     &data_source,
                     &eth_block,
                     block_finality.clone(),
                     block_ptr,
                     registry.cheap_clone(),
-                    stopwatch.cheap_clone(),
-                );
-                log::info!(
-                    "New datasource #{} with source: {:?}",
-                    self.data_sources.len() + 1,
-                    &data_source.source
-                );
-                self.add_data_source(data_source);
+                )?;
+                created_data_sources.push(data_source);
             }
         }
+        Ok(())
     }
 }
 pub fn load_wasm(
     indexer_hash: &String,
     datasource: &DataSource,
     templates: Arc<Vec<DataSourceTemplate>>,
-    store: Arc<dyn WritableStore>,
+    state: BlockState<Chain>,
+    schema: Arc<Schema>,
     valid_module: Arc<ValidModule>,
     ethereum_call: HostFn,
     registry: Arc<MockMetricsRegistry>,
@@ -283,6 +313,7 @@ pub fn load_wasm(
         datasource,
         network,
         Arc::clone(&templates),
+        schema,
         datasource.mapping.api_version.clone(),
     );
     //check if wasm module use import ethereum.call
@@ -299,7 +330,7 @@ pub fn load_wasm(
         logger: Logger::root(slog::Discard, slog::o!()),
         block_ptr: block_ptr.cheap_clone(),
         host_exports: Arc::new(host_exports),
-        state: BlockState::new(store, Default::default()),
+        state,
         //proof_of_indexing: None,
         host_fns: Arc::new(host_fns),
     };
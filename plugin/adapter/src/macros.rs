@@ -134,6 +134,7 @@ macro_rules! create_wasm_adapters {
         paste! {
             //use massbit_runtime_wasm::mapping::ValidModule;
             use graph_chain_ethereum::{DataSource, DataSourceTemplate};
+            use graph::data::schema::Schema;
             //use massbit_runtime_wasm::mapping::MappingContext;
             use graph_runtime_wasm::ValidModule;
             //use graph_runtime_wasm::{ValidModule, MappingContext, WasmInstance};
@@ -142,6 +143,7 @@ macro_rules! create_wasm_adapters {
             pub struct [<$adapter WasmHandlerProxy>] {
                 pub indexer_hash: String,
                 pub store: Arc<dyn WritableStore>,
+                pub schema: Arc<Schema>,
                 pub data_sources: Vec<DataSource>,
                 pub templates: Arc<Vec<DataSourceTemplate>>,
                 pub wasm_modules : HashMap<String, Arc<ValidModule>>,
@@ -151,12 +153,14 @@ macro_rules! create_wasm_adapters {
             impl [<$adapter WasmHandlerProxy>] {
                 pub fn new(indexer_hash: &String,
                     store: Arc<dyn WritableStore>,
-                    data_source : DataSource,
+                    schema: Arc<Schema>,
+                    data_sources : Vec<DataSource>,
                     templates: Arc<Vec<DataSourceTemplate>>) -> [<$adapter WasmHandlerProxy>] {
                     [<$adapter WasmHandlerProxy>] {
                         indexer_hash : indexer_hash.clone(),
                         store,
-                        data_sources: vec![data_source],
+                        schema,
+                        data_sources,
                         templates,
                         wasm_modules: HashMap::default(),
                         ethereum_calls: HashMap::default()
@@ -187,7 +191,8 @@ macro_rules! create_wasm_adapters {
                     adapter_name: &String,
                     indexer_hash: &String,
                     store: Arc<dyn WritableStore>,
-                    data_source : DataSource,
+                    schema: Arc<Schema>,
+                    data_sources : Vec<DataSource>,
                     templates: Arc<Vec<DataSourceTemplate>>
                 ) -> Option<WasmHandlerProxyType> {
                     log::info!("Create proxy for adapter {}", adapter_name);
@@ -195,7 +200,7 @@ macro_rules! create_wasm_adapters {
                         $(
                         if format!("{}", quote!([<$adapter:lower>])).eq(adapter_name) {
                             proxy = Some(WasmHandlerProxyType::$adapter([<$adapter WasmHandlerProxy>]::new(
-                                indexer_hash, store, data_source, templates)));
+                                indexer_hash, store, schema, data_sources, templates)));
                         }
                         )*
 
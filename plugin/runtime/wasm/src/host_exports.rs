@@ -38,8 +38,10 @@ use massbit_chain_ethereum::contract_call::{
 };
 */
 //use crate::manifest::datasource::DataSourceContext;
+use crate::validation::validate_entity;
 use graph::components::store::{EntityKey, EntityType};
 use graph::components::subgraph::{BlockState, Entity};
+use graph::data::schema::Schema;
 use graph::data::subgraph::{DataSourceContext, DeploymentHash};
 //use graph_runtime_wasm::module::IntoTrap;
 use crate::module::IntoTrap;
@@ -74,6 +76,8 @@ pub struct HostExports<C: Blockchain> {
     data_source_network: String,
     data_source_context: Arc<Option<DataSourceContext>>,
     templates: Arc<Vec<C::DataSourceTemplate>>,
+    /// Graphql schema of the indexer, the entities are checked against it on `store.set`
+    schema: Arc<Schema>,
 }
 
 impl<C: Blockchain> HostExports<C> {
@@ -82,6 +86,7 @@ impl<C: Blockchain> HostExports<C> {
         data_source: &impl DataSourceTrait<C>,
         data_source_network: String,
         templates: Arc<Vec<C::DataSourceTemplate>>,
+        schema: Arc<Schema>,
         api_version: Version,
     ) -> Self {
         Self {
@@ -93,6 +98,7 @@ impl<C: Blockchain> HostExports<C> {
             data_source_network,
             data_source_context: data_source.context().cheap_clone(),
             templates,
+            schema,
         }
     }
 
@@ -149,7 +155,7 @@ impl<C: Blockchain> HostExports<C> {
         entity_id: String,
         mut data: HashMap<String, Value>,
         stopwatch: &StopwatchMetrics,
-    ) -> Result<(), anyhow::Error> {
+    ) -> Result<(), HostExportError> {
         /*
         let poi_section = stopwatch.start_section("host_export_store_set__proof_of_indexing");
         if let Some(proof_of_indexing) = proof_of_indexing {
@@ -170,44 +176,32 @@ impl<C: Blockchain> HostExports<C> {
         // Automatically add an "id" value
         match data.insert("id".to_string(), Value::String(entity_id.clone())) {
             Some(ref v) if v != &Value::String(entity_id.clone()) => {
-                return Err(anyhow!(
+                return Err(HostExportError::Deterministic(anyhow!(
                     "Value of {} attribute 'id' conflicts with ID passed to `store.set()`: \
                      {} != {}",
                     entity_type,
                     v,
                     entity_id,
-                ));
+                )));
             }
             _ => (),
         }
 
         id_insert_section.end();
         let validation_section = stopwatch.start_section("host_export_store_set__validation");
+        // The mapping writes the whole entity, so it is checked before it is cached.
+        // The error is deterministic, the handler fails the same way each time the block is handled
+        validate_entity(&self.schema, &entity_type, &entity_id, &data)
+            .map_err(HostExportError::Deterministic)?;
         let key = EntityKey {
             subgraph_id: self.deployment_hash.cheap_clone(),
             entity_type: EntityType::new(entity_type),
             entity_id,
         };
         let entity = Entity::from(data);
-        //Todo:: validate entity
-        //let schema = self.store.input_schema(&self.subgraph_id)?;
-        //let is_valid = validate_entity(&schema.document, &key, &entity).is_ok();
         //state.set_entity(key.clone(), entity);
         state.entity_cache.set(key.clone(), entity);
         validation_section.end();
-        // Validate the changes against the subgraph schema.
-        // If the set of fields we have is already valid, avoid hitting the DB.
-        /*
-        if !is_valid {
-            stopwatch.start_section("host_export_store_set__post_validation");
-            let entity = state
-                .entity_cache
-                .get(&key)
-                .map_err(|e| HostExportError::Unknown(e.into()))?
-                .expect("we just stored this entity");
-            validate_entity(&schema.docuroment, &key, &entity)?;
-        }
-         */
         Ok(())
     }
 
@@ -1,6 +1,7 @@
 pub mod host_exports;
 pub mod mapping;
 pub mod module;
+pub mod validation;
 pub use host_exports::HostExports;
 pub use mapping::MappingContext;
 pub use module::WasmInstance;
@@ -0,0 +1,225 @@
+use graph::data::schema::Schema;
+use graph::prelude::{s, Value};
+use massbit_common::prelude::anyhow::{anyhow, Error};
+use std::collections::HashMap;
+
+/// Check the fields that a mapping passes to `store.set` against the graphql schema of the indexer:
+/// every field must be declared with a value of its type, non-null fields must be set,
+/// references must hold the id of the referenced entity and derived fields can not be set.
+pub fn validate_entity(
+    schema: &Schema,
+    entity_type: &str,
+    entity_id: &str,
+    data: &HashMap<String, Value>,
+) -> Result<(), Error> {
+    let object_type = get_object_type(schema, entity_type)
+        .ok_or_else(|| anyhow!("Entity {} is not defined in the schema", entity_type))?;
+    for field in &object_type.fields {
+        let value = data.get(&field.name).unwrap_or(&Value::Null);
+        let result = if is_derived(field) {
+            match value {
+                Value::Null => Ok(()),
+                _ => Err(anyhow!("derived field can not be set")),
+            }
+        } else {
+            check_value(schema, &field.field_type, value)
+        };
+        result.map_err(|err| {
+            anyhow!(
+                "Entity {}[{}]: invalid value for field `{}`: {}",
+                entity_type,
+                entity_id,
+                &field.name,
+                err
+            )
+        })?;
+    }
+    for name in data.keys() {
+        if !object_type.fields.iter().any(|field| &field.name == name) {
+            return Err(anyhow!(
+                "Entity {}[{}]: field `{}` is not defined in the schema",
+                entity_type,
+                entity_id,
+                name
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn get_object_type<'a>(schema: &'a Schema, name: &str) -> Option<&'a s::ObjectType> {
+    schema
+        .document
+        .definitions
+        .iter()
+        .find_map(|definition| match definition {
+            s::Definition::TypeDefinition(s::TypeDefinition::Object(object_type))
+                if object_type.name == name =>
+            {
+                Some(object_type)
+            }
+            _ => None,
+        })
+}
+
+fn get_type_definition<'a>(schema: &'a Schema, name: &str) -> Option<&'a s::TypeDefinition> {
+    schema
+        .document
+        .definitions
+        .iter()
+        .find_map(|definition| match definition {
+            s::Definition::TypeDefinition(type_definition) => match type_definition {
+                s::TypeDefinition::Object(t) if t.name == name => Some(type_definition),
+                s::TypeDefinition::Interface(t) if t.name == name => Some(type_definition),
+                s::TypeDefinition::Enum(t) if t.name == name => Some(type_definition),
+                _ => None,
+            },
+            _ => None,
+        })
+}
+
+fn is_derived(field: &s::Field) -> bool {
+    field
+        .directives
+        .iter()
+        .any(|directive| directive.name == "derivedFrom")
+}
+
+fn check_value(schema: &Schema, field_type: &s::Type, value: &Value) -> Result<(), Error> {
+    match (field_type, value) {
+        (s::Type::NonNullType(_), Value::Null) => Err(anyhow!("missing value for non-null field")),
+        (s::Type::NonNullType(inner), _) => check_value(schema, inner, value),
+        (_, Value::Null) => Ok(()),
+        (s::Type::ListType(inner), Value::List(values)) => values
+            .iter()
+            .try_for_each(|value| check_value(schema, inner, value)),
+        (s::Type::ListType(_), _) => Err(anyhow!("expected a list, found {}", type_name(value))),
+        (s::Type::NamedType(name), _) => check_named_value(schema, name, value),
+    }
+}
+
+fn check_named_value(schema: &Schema, name: &str, value: &Value) -> Result<(), Error> {
+    let valid = match (name, value) {
+        ("ID", Value::String(_)) | ("ID", Value::Bytes(_)) => true,
+        ("String", Value::String(_)) => true,
+        ("Int", Value::Int(_)) => true,
+        ("BigInt", Value::BigInt(_)) => true,
+        ("BigDecimal", Value::BigDecimal(_)) => true,
+        ("Boolean", Value::Bool(_)) => true,
+        ("Bytes", Value::Bytes(_)) => true,
+        ("ID", _)
+        | ("String", _)
+        | ("Int", _)
+        | ("BigInt", _)
+        | ("BigDecimal", _)
+        | ("Boolean", _)
+        | ("Bytes", _) => false,
+        _ => match get_type_definition(schema, name) {
+            Some(s::TypeDefinition::Enum(enum_type)) => match value {
+                Value::String(name) => enum_type.values.iter().any(|v| &v.name == name),
+                _ => false,
+            },
+            // A reference holds the id of the referenced entity
+            Some(_) => matches!(value, Value::String(_) | Value::Bytes(_)),
+            None => return Err(anyhow!("type {} is not defined in the schema", name)),
+        },
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "expected {}, found {} {}",
+            name,
+            type_name(value),
+            value
+        ))
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::String(_) => "String",
+        Value::Int(_) => "Int",
+        Value::BigDecimal(_) => "BigDecimal",
+        Value::Bool(_) => "Boolean",
+        Value::List(_) => "List",
+        Value::Null => "Null",
+        Value::Bytes(_) => "Bytes",
+        Value::BigInt(_) => "BigInt",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use graph::prelude::DeploymentHash;
+
+    const SCHEMA: &str = "
+        type Account @entity { id: ID! tokens: [Token!]! @derivedFrom(field: \"owner\") }
+        type Token @entity { id: ID! owner: Account! amount: BigInt tags: [String!] }
+    ";
+
+    fn validate(data: Vec<(&str, Value)>) -> Result<(), Error> {
+        let schema = Schema::parse(SCHEMA, DeploymentHash::new("indexer").unwrap()).unwrap();
+        let data = data
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value))
+            .collect();
+        validate_entity(&schema, "Token", "t1", &data)
+    }
+
+    fn token() -> Vec<(&'static str, Value)> {
+        vec![("id", Value::from("t1")), ("owner", Value::from("alice"))]
+    }
+
+    #[test]
+    fn entity_with_the_fields_of_the_schema_is_valid() {
+        let mut data = token();
+        data.push((
+            "tags",
+            Value::List(vec![Value::from("a"), Value::from("b")]),
+        ));
+        assert!(validate(data).is_ok());
+    }
+
+    #[test]
+    fn missing_required_field_is_rejected() {
+        let err = validate(vec![("id", Value::from("t1"))]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Entity Token[t1]: invalid value for field `owner`: missing value for non-null field"
+        );
+    }
+
+    #[test]
+    fn value_of_the_wrong_type_is_rejected() {
+        let mut data = token();
+        data.push(("amount", Value::from("10")));
+        let err = validate(data).unwrap_err();
+        assert!(err.to_string().contains("expected BigInt, found String"));
+    }
+
+    #[test]
+    fn list_values_are_checked() {
+        let mut data = token();
+        data.push(("tags", Value::from("a")));
+        let err = validate(data).unwrap_err();
+        assert!(err.to_string().contains("expected a list, found String"));
+
+        let mut data = token();
+        data.push(("tags", Value::List(vec![Value::from("a"), Value::Int(1)])));
+        let err = validate(data).unwrap_err();
+        assert!(err.to_string().contains("expected String, found Int"));
+    }
+
+    #[test]
+    fn unknown_field_is_rejected() {
+        let mut data = token();
+        data.push(("symbol", Value::from("TKN")));
+        let err = validate(data).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Entity Token[t1]: field `symbol` is not defined in the schema"
+        );
+    }
+}